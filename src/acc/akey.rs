@@ -1,41 +1,76 @@
-use openssl::{ec::EcKey, pkey};
+use std::{fmt, sync::Arc};
 
-use crate::{cert::EC_GROUP_P256, Result};
+use crate::{
+    signer::{Jwk, JwsSigner, SoftwareSigner},
+    Result,
+};
 
-#[derive(Clone, Debug)]
+#[derive(Clone)]
+enum Signer {
+    /// Key generated or read from the persistence.
+    Software(SoftwareSigner),
+    /// Key held outside the library, such as in an HSM.
+    External(Arc<dyn JwsSigner>),
+}
+
+#[derive(Clone)]
 pub(crate) struct AcmeKey {
-    private_key: EcKey<pkey::Private>,
+    signer: Signer,
+    /// public key of the signer
+    jwk: Jwk,
     /// set once we contacted the ACME API to figure out the key id
     key_id: Option<String>,
 }
 
 impl AcmeKey {
-    pub(crate) fn new() -> AcmeKey {
-        let pri_key = EcKey::generate(&EC_GROUP_P256).expect("EcKey");
-        Self::from_key(pri_key)
+    pub(crate) fn new() -> Result<AcmeKey> {
+        Self::from_software(SoftwareSigner::generate()?)
     }
 
     pub(crate) fn from_pem(pem: &[u8]) -> Result<AcmeKey> {
-        let pri_key =
-            EcKey::private_key_from_pem(pem).map_err(|e| format!("Failed to read PEM: {}", e))?;
-        Ok(Self::from_key(pri_key))
+        Self::from_software(SoftwareSigner::from_pem(pem)?)
     }
 
-    fn from_key(private_key: EcKey<pkey::Private>) -> AcmeKey {
-        AcmeKey {
-            private_key,
+    fn from_software(signer: SoftwareSigner) -> Result<AcmeKey> {
+        let jwk = signer.jwk()?;
+        Ok(AcmeKey {
+            signer: Signer::Software(signer),
+            jwk,
             key_id: None,
+        })
+    }
+
+    pub(crate) fn from_signer(signer: Arc<dyn JwsSigner>) -> Result<AcmeKey> {
+        let jwk = signer.jwk()?;
+        Ok(AcmeKey {
+            signer: Signer::External(signer),
+            jwk,
+            key_id: None,
+        })
+    }
+
+    /// Whether the private key can be exported and persisted.
+    pub(crate) fn is_external(&self) -> bool {
+        matches!(self.signer, Signer::External(_))
+    }
+
+    pub(crate) fn to_pem(&self) -> Result<Vec<u8>> {
+        match &self.signer {
+            Signer::Software(s) => s.to_pem(),
+            Signer::External(_) => Err("Account key is held by an external signer".into()),
         }
     }
 
-    pub(crate) fn to_pem(&self) -> Vec<u8> {
-        self.private_key
-            .private_key_to_pem()
-            .expect("private_key_to_pem")
+    /// ES256 signature as the 64 bytes `r || s`.
+    pub(crate) fn sign(&self, data: &[u8]) -> Result<Vec<u8>> {
+        match &self.signer {
+            Signer::Software(s) => s.sign(data),
+            Signer::External(s) => s.sign(data),
+        }
     }
 
-    pub(crate) fn private_key(&self) -> &EcKey<pkey::Private> {
-        &self.private_key
+    pub(crate) fn jwk(&self) -> &Jwk {
+        &self.jwk
     }
 
     pub(crate) fn key_id(&self) -> &str {
@@ -46,3 +81,13 @@ impl AcmeKey {
         self.key_id = Some(kid)
     }
 }
+
+impl fmt::Debug for AcmeKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("AcmeKey")
+            .field("external", &self.is_external())
+            .field("jwk", &self.jwk)
+            .field("key_id", &self.key_id)
+            .finish()
+    }
+}
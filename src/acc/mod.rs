@@ -1,14 +1,23 @@
 //
-use std::sync::Arc;
+use serde::{Deserialize, Serialize};
+use std::{
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use time::OffsetDateTime;
 
 use crate::{
-    api::{ApiAccount, ApiDirectory, ApiIdentifier, ApiOrder, ApiRevocation},
-    cert::Certificate,
+    api::{ApiAccount, ApiDirectory, ApiIdentifier, ApiOrder, ApiProblem, ApiRevocation},
+    cert::{Certificate, CertificateVersion, Revocation},
+    crypto::sha256,
+    ocsp::{OcspResponse, OcspStatus},
     order::{NewOrder, Order},
     persist::{Persist, PersistKey, PersistKind},
+    renew::{renewal_info_of, renewal_info_url, RenewalInfo},
+    req::{check_status, get, post_ocsp, ExtractHeader},
     trans::Transport,
-    util::{base64url, read_json},
-    Result, req::ExtractHeader,
+    util::{base64url, pem_encode, read_json},
+    Error, ProblemType, Result,
 };
 
 mod akey;
@@ -22,6 +31,7 @@ pub(crate) struct AccountInner<P: Persist> {
     pub realm: String,
     pub api_account: ApiAccount,
     pub api_directory: ApiDirectory,
+    pub history: usize,
 }
 
 /// Account with an ACME provider.
@@ -50,6 +60,7 @@ impl<P: Persist> Account<P> {
         realm: &str,
         api_account: ApiAccount,
         api_directory: ApiDirectory,
+        history: usize,
     ) -> Self {
         Account {
             inner: Arc::new(AccountInner {
@@ -58,15 +69,20 @@ impl<P: Persist> Account<P> {
                 realm: realm.to_string(),
                 api_account,
                 api_directory,
+                history,
             }),
         }
     }
 
     /// Private key for this account.
     ///
-    /// The key is an elliptic curve private key.
-    pub fn acme_private_key_pem(&self) -> String {
-        String::from_utf8(self.inner.transport.acme_key().to_pem()).expect("from_utf8")
+    /// The key is an elliptic curve private key. Fails for accounts accessed with
+    /// [`Directory::account_with_signer`] since the key never leaves the signer.
+    ///
+    /// [`Directory::account_with_signer`]: struct.Directory.html#method.account_with_signer
+    pub fn acme_private_key_pem(&self) -> Result<String> {
+        let pem = self.inner.transport.acme_key().to_pem()?;
+        Ok(String::from_utf8_lossy(&pem).to_string())
     }
 
     /// Get an already issued and [downloaded] certificate.
@@ -76,33 +92,99 @@ impl<P: Persist> Account<P> {
     /// from the local storage (no API calls involved).
     ///
     /// This can form the basis for implemeting automatic renewal of
-    /// certificates where the [valid days left] are running low.
+    /// certificates where the [valid days left] are running low, or that are
+    /// [revoked].
     ///
     /// [downloaded]: order/struct.CertOrder.html#method.download_and_save_cert
     /// [valid days left]: struct.Certificate.html#method.valid_days_left
+    /// [revoked]: struct.Certificate.html#method.is_revoked
     pub fn certificate(&self, primary_name: &str) -> Result<Option<Certificate>> {
-        // details needed for persistence
-        let realm = &self.inner.realm;
+        load_certificate(&self.inner.persist, &self.inner.realm, primary_name)
+    }
+
+    /// The primary names of all certificates in the persistence for this account.
+    ///
+    /// Together with [`certificate`] this can drive a renewal sweep. Requires a
+    /// [`Persist`] that implements `list`.
+    ///
+    /// [`certificate`]: struct.Account.html#method.certificate
+    /// [`Persist`]: persist/trait.Persist.html
+    pub fn certificates(&self) -> Result<Vec<String>> {
+        list_certificates(&self.inner.persist, &self.inner.realm)
+    }
+
+    /// The earlier saved versions of a certificate, oldest first. The last is the
+    /// current certificate, unless an earlier version was [activated].
+    ///
+    /// Empty unless the directory was built with [`DirectoryBuilder::certificate_history`].
+    ///
+    /// [activated]: struct.Account.html#method.activate_certificate
+    /// [`DirectoryBuilder::certificate_history`]: struct.DirectoryBuilder.html#method.certificate_history
+    pub fn certificate_history(&self, primary_name: &str) -> Result<Vec<CertificateVersion>> {
+        certificate_history(&self.inner.persist, &self.inner.realm, primary_name)
+    }
+
+    /// Make a version from the [history] the current certificate again, i.e. to roll
+    /// back a bad issuance. Fails if the certificate is revoked, has expired or expires
+    /// within a day.
+    ///
+    /// [history]: struct.Account.html#method.certificate_history
+    pub fn activate_certificate(&self, primary_name: &str, version: u32) -> Result<Certificate> {
+        activate_certificate(
+            &self.inner.persist,
+            &self.inner.realm,
+            primary_name,
+            version,
+        )
+    }
+
+    /// The OCSP response for a saved certificate, to staple it or to check that it
+    /// isn't revoked.
+    ///
+    /// The response is persisted and reused until halfway to its next update. After
+    /// that, or if the persisted response no longer verifies, a new one is fetched from
+    /// the first [OCSP responder] of the certificate with the HTTP client of the
+    /// directory, verified and persisted. A revoked status is recorded as the
+    /// [revocation] of the certificate.
+    ///
+    /// [OCSP responder]: struct.CertificateInfo.html#method.ocsp_urls
+    /// [revocation]: struct.Certificate.html#method.revocation
+    pub fn ocsp_response(&self, primary_name: &str) -> Result<OcspResponse> {
         let persist = &self.inner.persist;
+        let realm = &self.inner.realm;
+        let (cert, cached) = cached_ocsp_response(persist, realm, primary_name)?;
+        if let Some(response) = cached {
+            return Ok(response);
+        }
 
-        // read primary key
-        let pk_key = PersistKey::new(realm, PersistKind::PrivateKey, primary_name);
-        debug!("Read private key: {}", pk_key);
-        let private_key = persist
-            .get(&pk_key)?
-            .and_then(|s| String::from_utf8(s).ok());
-
-        // read certificate
-        let pk_crt = PersistKey::new(realm, PersistKind::Certificate, primary_name);
-        debug!("Read certificate: {}", pk_crt);
-        let certificate = persist
-            .get(&pk_crt)?
-            .and_then(|s| String::from_utf8(s).ok());
-
-        Ok(match (private_key, certificate) {
-            (Some(k), Some(c)) => Some(Certificate::new(k, c)),
-            _ => None,
-        })
+        let url = ocsp_url(&cert)?;
+        debug!("Fetch OCSP response: {}", url);
+        let http = self.inner.transport.http();
+        let res = check_status(post_ocsp(http, &url, &cert.ocsp_request()?)?)?;
+        save_ocsp_response(persist, realm, primary_name, &cert, res.body())
+    }
+
+    /// The renewal window the CA suggests for a certificate, `None` if the CA doesn't
+    /// provide renewal information.
+    ///
+    /// The certificate must have an authority key identifier, which certificates of
+    /// public CAs do.
+    pub fn renewal_info(&self, cert: &Certificate) -> Result<Option<RenewalInfo>> {
+        let Some(base) = &self.inner.api_directory.renewalInfo else {
+            return Ok(None);
+        };
+        let url = renewal_info_url(base, cert)?;
+        debug!("Fetch renewal info: {}", url);
+        let res = check_status(get(self.inner.transport.http(), &url)?)?;
+        renewal_info_of(res).map(Some)
+    }
+
+    /// Remove a certificate, its private key, OCSP response and revocation from the
+    /// persistence, i.e. after it has been revoked. Requires a [`Persist`] that implements `delete`.
+    ///
+    /// [`Persist`]: persist/trait.Persist.html
+    pub fn remove_certificate(&self, primary_name: &str) -> Result<()> {
+        remove_certificate(&self.inner.persist, &self.inner.realm, primary_name)
     }
 
     /// Create a new order to issue a certificate for this account.
@@ -118,18 +200,7 @@ impl<P: Persist> Account<P> {
     ///
     /// [100 names]: https://letsencrypt.org/docs/rate-limits/
     pub fn new_order(&self, primary_name: &str, alt_names: &[&str]) -> Result<NewOrder<P>> {
-        // construct the identifiers
-        let prim_arr = [primary_name];
-        let domains = prim_arr.iter().chain(alt_names);
-        let order = ApiOrder {
-            identifiers: domains
-                .map(|s| ApiIdentifier {
-                    _type: "dns".into(),
-                    value: s.to_string(),
-                })
-                .collect(),
-            ..Default::default()
-        };
+        let order = new_order_body(primary_name, alt_names);
 
         let new_order_url = &self.inner.api_directory.newOrder;
 
@@ -143,23 +214,36 @@ impl<P: Persist> Account<P> {
 
     /// Revoke a certificate for the reason given.
     ///
-    /// This calls the ACME API revoke endpoint, but does not affect the locally persisted
-    /// certs, the revoked certificate will still be available using [`certificate`].
+    /// This calls the ACME API revoke endpoint. A certificate that was revoked before and
+    /// a reason the CA doesn't accept are outcomes rather than errors.
+    ///
+    /// Unless the CA refused the reason, the revocation is recorded in the persistence.
+    /// The certificate is still available using [`certificate`], but
+    /// [`Certificate::is_revoked`] tells it must be replaced. Without the account, the
+    /// certificate key can sign the revocation instead, see
+    /// [`Directory::revoke_certificate_with_key`].
     ///
     /// [`certificate`]: struct.Account.html#method.certificate
-    pub fn revoke_certificate(&self, cert: &Certificate, reason: RevocationReason) -> Result<()> {
-        // convert to base64url of the DER (which is not PEM).
-        let certificate = base64url(&cert.certificate_der());
-
-        let revoc = ApiRevocation {
-            certificate,
-            reason: reason as usize,
-        };
+    /// [`Certificate::is_revoked`]: struct.Certificate.html#method.is_revoked
+    /// [`Directory::revoke_certificate_with_key`]: struct.Directory.html#method.revoke_certificate_with_key
+    pub fn revoke_certificate(
+        &self,
+        cert: &Certificate,
+        reason: RevocationReason,
+    ) -> Result<RevocationOutcome> {
+        let revoc = revocation_body(cert, reason)?;
 
         let url = &self.inner.api_directory.revokeCert;
-        self.inner.transport.call(url, &revoc)?;
+        let outcome = revocation_outcome(self.inner.transport.call(url, &revoc))?;
 
-        Ok(())
+        record_outcome(
+            &self.inner.persist,
+            &self.inner.realm,
+            cert,
+            reason,
+            &outcome,
+        )?;
+        Ok(outcome)
     }
 
     /// Access the underlying JSON object for debugging.
@@ -168,9 +252,322 @@ impl<P: Persist> Account<P> {
     }
 }
 
+/// Read a certificate and its private key from the persistence.
+pub(crate) fn load_certificate<P: Persist>(
+    persist: &P,
+    realm: &str,
+    primary_name: &str,
+) -> Result<Option<Certificate>> {
+    // read certificate
+    let pk_crt = PersistKey::new(realm, PersistKind::Certificate, primary_name);
+    debug!("Read certificate: {}", pk_crt);
+    let Some(certificate) = persist.get(&pk_crt)? else {
+        return Ok(None);
+    };
+    let Ok(certificate) = String::from_utf8(certificate) else {
+        return Ok(None);
+    };
+
+    let cert = match load_key(persist, realm, primary_name, &certificate)? {
+        Some(StoredKey::Private(k)) => match String::from_utf8(k) {
+            Ok(k) => Certificate::new(Some(k), certificate),
+            Err(_) => return Ok(None),
+        },
+        Some(StoredKey::External(_)) => Certificate::new(None, certificate),
+        None => return Ok(None),
+    };
+
+    let revocation = load_revocation(persist, realm, &cert)?;
+    Ok(Some(cert.with_revocation(revocation)))
+}
+
+/// The persisted key of a certificate.
+enum StoredKey {
+    /// The private key PEM.
+    Private(Vec<u8>),
+    /// The public key PEM of a private key held externally.
+    External(Vec<u8>),
+}
+
+impl StoredKey {
+    fn kind(&self) -> PersistKind {
+        match self {
+            StoredKey::Private(_) => PersistKind::PrivateKey,
+            StoredKey::External(_) => PersistKind::PublicKey,
+        }
+    }
+
+    /// The kind of key left from a certificate with the other kind of key.
+    fn stale_kind(&self) -> PersistKind {
+        match self {
+            StoredKey::Private(_) => PersistKind::PublicKey,
+            StoredKey::External(_) => PersistKind::PrivateKey,
+        }
+    }
+
+    fn pem(&self) -> &[u8] {
+        match self {
+            StoredKey::Private(pem) | StoredKey::External(pem) => pem,
+        }
+    }
+}
+
+/// Read the key of a certificate. If saving a certificate was interrupted, there may
+/// be both a private and a public key, and the public key is only used if it's the key
+/// of the certificate.
+fn load_key<P: Persist>(
+    persist: &P,
+    realm: &str,
+    name: &str,
+    certificate: &str,
+) -> Result<Option<StoredKey>> {
+    let pk_pub = PersistKey::new(realm, PersistKind::PublicKey, name);
+    debug!("Read public key: {}", pk_pub);
+    if let Some(public_key) = persist.get(&pk_pub)? {
+        let leaf = Certificate::new(None, certificate.to_string()).info();
+        if leaf.is_ok_and(|l| pem_encode("PUBLIC KEY", l.public_key_der()).as_bytes() == public_key)
+        {
+            return Ok(Some(StoredKey::External(public_key)));
+        }
+    }
+
+    let pk_key = PersistKey::new(realm, PersistKind::PrivateKey, name);
+    debug!("Read private key: {}", pk_key);
+    Ok(persist.get(&pk_key)?.map(StoredKey::Private))
+}
+
+/// Remove a certificate, its key, OCSP response and revocation from the persistence.
+pub(crate) fn remove_certificate<P: Persist>(
+    persist: &P,
+    realm: &str,
+    primary_name: &str,
+) -> Result<()> {
+    let cert = load_certificate(persist, realm, primary_name)?;
+    if let Some(key) = cert.and_then(|c| revocation_key(&c)) {
+        let pk_rev = PersistKey::new(realm, PersistKind::Revocation, &key);
+        debug!("Remove revocation: {}", pk_rev);
+        persist.delete(&pk_rev)?;
+    }
+
+    let pk_ocsp = PersistKey::new(realm, PersistKind::OcspResponse, primary_name);
+    debug!("Remove OCSP response: {}", pk_ocsp);
+    persist.delete(&pk_ocsp)?;
+
+    let pk_crt = PersistKey::new(realm, PersistKind::Certificate, primary_name);
+    debug!("Remove certificate: {}", pk_crt);
+    persist.delete(&pk_crt)?;
+
+    let pk_pub = PersistKey::new(realm, PersistKind::PublicKey, primary_name);
+    debug!("Remove public key: {}", pk_pub);
+    persist.delete(&pk_pub)?;
+
+    let pk_key = PersistKey::new(realm, PersistKind::PrivateKey, primary_name);
+    debug!("Remove private key: {}", pk_key);
+    persist.delete(&pk_key)
+}
+
+/// The saved certificate and its persisted OCSP response, if that still verifies and
+/// isn't due for a refresh.
+pub(crate) fn cached_ocsp_response<P: Persist>(
+    persist: &P,
+    realm: &str,
+    primary_name: &str,
+) -> Result<(Certificate, Option<OcspResponse>)> {
+    let cert = load_certificate(persist, realm, primary_name)?
+        .ok_or_else(|| format!("No certificate: {}", primary_name))?;
+
+    let pk_ocsp = PersistKey::new(realm, PersistKind::OcspResponse, primary_name);
+    debug!("Read OCSP response: {}", pk_ocsp);
+    let cached = match persist.get(&pk_ocsp)? {
+        // a response for a replaced certificate doesn't verify
+        Some(der) => cert
+            .check_ocsp_response(&der)
+            .ok()
+            .filter(|r| OffsetDateTime::now_utc() < r.refresh_at()),
+        None => None,
+    };
+    Ok((cert, cached))
+}
+
+/// The URL of the OCSP responder to fetch a response from.
+pub(crate) fn ocsp_url(cert: &Certificate) -> Result<String> {
+    cert.info()?
+        .ocsp_urls()
+        .first()
+        .cloned()
+        .ok_or_else(|| "Certificate has no OCSP responder".into())
+}
+
+/// Verify a fetched OCSP response and persist it.
+pub(crate) fn save_ocsp_response<P: Persist>(
+    persist: &P,
+    realm: &str,
+    primary_name: &str,
+    cert: &Certificate,
+    der: &[u8],
+) -> Result<OcspResponse> {
+    let response = cert.check_ocsp_response(der)?;
+    let pk_ocsp = PersistKey::new(realm, PersistKind::OcspResponse, primary_name);
+    debug!("Save OCSP response: {}", pk_ocsp);
+    persist.put(&pk_ocsp, der)?;
+
+    if let OcspStatus::Revoked { time, reason } = response.status() {
+        if load_revocation(persist, realm, cert)?.is_none() {
+            let time = time.into();
+            record_revocation(persist, realm, cert, &Revocation { time, reason })?;
+        }
+    }
+    Ok(response)
+}
+
+/// The body of a newOrder call for the given domains.
+pub(crate) fn new_order_body(primary_name: &str, alt_names: &[&str]) -> ApiOrder {
+    // construct the identifiers
+    let prim_arr = [primary_name];
+    let domains = prim_arr.iter().chain(alt_names);
+    ApiOrder {
+        identifiers: domains
+            .map(|s| ApiIdentifier {
+                _type: "dns".into(),
+                value: s.to_string(),
+            })
+            .collect(),
+        ..Default::default()
+    }
+}
+
+/// The body of a revokeCert call.
+pub(crate) fn revocation_body(
+    cert: &Certificate,
+    reason: RevocationReason,
+) -> Result<ApiRevocation> {
+    // convert to base64url of the DER (which is not PEM).
+    let certificate = base64url(&cert.certificate_der()?);
+
+    Ok(ApiRevocation {
+        certificate,
+        reason: reason as usize,
+    })
+}
+
+/// The outcome of a revocation request the CA answered.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RevocationOutcome {
+    /// The certificate is revoked now.
+    Revoked,
+    /// The certificate was revoked before, possibly for another reason.
+    AlreadyRevoked,
+    /// The CA doesn't accept the reason for the certificate, i.e. Let's Encrypt refuses
+    /// `CACompromise`. The certificate is not revoked.
+    BadRevocationReason(ApiProblem),
+}
+
+impl RevocationOutcome {
+    /// Whether the certificate is revoked after the request.
+    pub fn is_revoked(&self) -> bool {
+        !matches!(self, RevocationOutcome::BadRevocationReason(_))
+    }
+}
+
+/// The outcome of a revokeCert call, or the error if the CA didn't answer about the
+/// certificate.
+pub(crate) fn revocation_outcome<T>(result: Result<T>) -> Result<RevocationOutcome> {
+    match result {
+        Ok(_) => Ok(RevocationOutcome::Revoked),
+        Err(Error::ApiProblem(problem)) => match problem.problem_type() {
+            ProblemType::AlreadyRevoked => Ok(RevocationOutcome::AlreadyRevoked),
+            ProblemType::BadRevocationReason => Ok(RevocationOutcome::BadRevocationReason(problem)),
+            _ => Err(Error::ApiProblem(problem)),
+        },
+        Err(e) => Err(e),
+    }
+}
+
+/// Record the outcome of a revocation in the persistence. A certificate that was
+/// already revoked keeps the revocation recorded earlier.
+pub(crate) fn record_outcome<P: Persist>(
+    persist: &P,
+    realm: &str,
+    cert: &Certificate,
+    reason: RevocationReason,
+    outcome: &RevocationOutcome,
+) -> Result<()> {
+    let reason = match outcome {
+        RevocationOutcome::Revoked => Some(reason),
+        RevocationOutcome::AlreadyRevoked => {
+            if load_revocation(persist, realm, cert)?.is_some() {
+                return Ok(());
+            }
+            // we don't know why or when it was revoked
+            None
+        }
+        RevocationOutcome::BadRevocationReason(_) => return Ok(()),
+    };
+    let time = SystemTime::now();
+    record_revocation(persist, realm, cert, &Revocation { time, reason })
+}
+
+/// A revocation as saved in the persistence.
+#[derive(Serialize, Deserialize)]
+struct PersistedRevocation {
+    /// Unix time.
+    time: u64,
+    reason: Option<u8>,
+}
+
+/// The key of the revocation of a certificate: the hex SHA-256 fingerprint of the leaf.
+/// `None` if the certificate can't be read.
+fn revocation_key(cert: &Certificate) -> Option<String> {
+    let der = cert.certificate_der().ok()?;
+    Some(sha256(&der).iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+/// Save the revocation of a certificate.
+fn record_revocation<P: Persist>(
+    persist: &P,
+    realm: &str,
+    cert: &Certificate,
+    revocation: &Revocation,
+) -> Result<()> {
+    let key = revocation_key(cert).ok_or("Unreadable certificate")?;
+    let time = revocation
+        .time
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    let value = serde_json::to_vec(&PersistedRevocation {
+        time,
+        reason: revocation.reason.map(|r| r as u8),
+    })?;
+    let pk_rev = PersistKey::new(realm, PersistKind::Revocation, &key);
+    debug!("Save revocation: {}", pk_rev);
+    persist.put(&pk_rev, &value)
+}
+
+/// Read the revocation of a certificate, if one is recorded.
+fn load_revocation<P: Persist>(
+    persist: &P,
+    realm: &str,
+    cert: &Certificate,
+) -> Result<Option<Revocation>> {
+    // unreadable certificates are no use anyway
+    let Some(key) = revocation_key(cert) else {
+        return Ok(None);
+    };
+    let pk_rev = PersistKey::new(realm, PersistKind::Revocation, &key);
+    let Some(value) = persist.get(&pk_rev)? else {
+        return Ok(None);
+    };
+    let saved: PersistedRevocation = serde_json::from_slice(&value)?;
+    Ok(Some(Revocation {
+        time: UNIX_EPOCH + Duration::from_secs(saved.time),
+        reason: saved.reason.and_then(RevocationReason::from_code),
+    }))
+}
+
 /// Enumeration of reasons for revocation.
 ///
 /// The reason codes are taken from [rfc5280](https://tools.ietf.org/html/rfc5280#section-5.3.1).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum RevocationReason {
     Unspecified = 0,
     KeyCompromise = 1,
@@ -185,6 +582,141 @@ pub enum RevocationReason {
     AACompromise = 10,
 }
 
+impl RevocationReason {
+    /// The reason for a code, `None` for unused codes.
+    pub(crate) fn from_code(code: u8) -> Option<Self> {
+        use RevocationReason::*;
+        Some(match code {
+            0 => Unspecified,
+            1 => KeyCompromise,
+            2 => CACompromise,
+            3 => AffiliationChanged,
+            4 => Superseded,
+            5 => CessationOfOperation,
+            6 => CertificateHold,
+            8 => RemoveFromCRL,
+            9 => PrivilegeWithdrawn,
+            10 => AACompromise,
+            _ => return None,
+        })
+    }
+}
+
+/// Separates the primary name from the version and save time in the keys of the
+/// certificate history. Not valid in DNS names.
+const HISTORY_SEPARATOR: char = '#';
+
+/// The key of a version in the certificate history.
+pub(crate) fn history_key(primary_name: &str, version: u32, saved: u64) -> String {
+    format!(
+        "{}{}{}{}{}",
+        primary_name, HISTORY_SEPARATOR, version, HISTORY_SEPARATOR, saved
+    )
+}
+
+/// The primary names of the certificates, without the history.
+pub(crate) fn list_certificates<P: Persist>(persist: &P, realm: &str) -> Result<Vec<String>> {
+    let mut names = persist.list(realm, PersistKind::Certificate)?;
+    names.retain(|n| !n.contains(HISTORY_SEPARATOR));
+    Ok(names)
+}
+
+/// The versions in the history of a certificate and the unix time they were saved,
+/// oldest first.
+pub(crate) fn history_versions<P: Persist>(
+    persist: &P,
+    realm: &str,
+    primary_name: &str,
+) -> Result<Vec<(u32, u64)>> {
+    let prefix = format!("{}{}", primary_name, HISTORY_SEPARATOR);
+    let mut versions = vec![];
+    for key in persist.list(realm, PersistKind::Certificate)? {
+        let parsed = key
+            .strip_prefix(&prefix)
+            .and_then(|rest| rest.split_once(HISTORY_SEPARATOR))
+            .and_then(|(v, s)| Some((v.parse().ok()?, s.parse().ok()?)));
+        if let Some(version) = parsed {
+            versions.push(version);
+        }
+    }
+    versions.sort_unstable();
+    Ok(versions)
+}
+
+/// Read the certificate history from the persistence.
+pub(crate) fn certificate_history<P: Persist>(
+    persist: &P,
+    realm: &str,
+    primary_name: &str,
+) -> Result<Vec<CertificateVersion>> {
+    let mut history = vec![];
+    for (version, saved) in history_versions(persist, realm, primary_name)? {
+        let key = history_key(primary_name, version, saved);
+        if let Some(certificate) = load_certificate(persist, realm, &key)? {
+            history.push(CertificateVersion {
+                version,
+                saved: UNIX_EPOCH + Duration::from_secs(saved),
+                certificate,
+            });
+        }
+    }
+    Ok(history)
+}
+
+/// Copy a version from the history to the current certificate and private key.
+pub(crate) fn activate_certificate<P: Persist>(
+    persist: &P,
+    realm: &str,
+    primary_name: &str,
+    version: u32,
+) -> Result<Certificate> {
+    let pk_crt = PersistKey::new(realm, PersistKind::Certificate, primary_name);
+    let _lock = persist.lock(&pk_crt)?;
+
+    let saved = history_versions(persist, realm, primary_name)?
+        .into_iter()
+        .find(|(v, _)| *v == version)
+        .map(|(_, saved)| saved)
+        .ok_or_else(|| format!("No version {} of certificate: {}", version, primary_name))?;
+    let key = history_key(primary_name, version, saved);
+    let cert = load_certificate(persist, realm, &key)?.ok_or_else(|| {
+        format!(
+            "Incomplete version {} of certificate: {}",
+            version, primary_name
+        )
+    })?;
+    let stored = load_key(persist, realm, &key, cert.certificate())?.ok_or_else(|| {
+        format!(
+            "No key of version {} of certificate: {}",
+            version, primary_name
+        )
+    })?;
+    if cert.is_revoked() {
+        return Err(format!(
+            "Version {} of certificate is revoked: {}",
+            version, primary_name
+        )
+        .into());
+    }
+    // less than a day is not worth a rollback
+    if cert.valid_days_left()? < 1 {
+        return Err(format!(
+            "Version {} of certificate has expired: {}",
+            version, primary_name
+        )
+        .into());
+    }
+
+    debug!("Activate version {} of certificate: {}", version, pk_crt);
+    let pk_key = PersistKey::new(realm, stored.kind(), primary_name);
+    persist.put_all(&[
+        (pk_key, stored.pem()),
+        (pk_crt, cert.certificate().as_bytes()),
+    ])?;
+    persist.delete(&PersistKey::new(realm, stored.stale_kind(), primary_name))?;
+    Ok(cert)
+}
+
 #[cfg(test)]
 mod test {
     use crate::{persist::*, *};
@@ -199,4 +731,233 @@ mod test {
         let _ = acc.new_order("acmetest.example.com", &[])?;
         Ok(())
     }
+
+    #[test]
+    fn test_certificates_and_remove() -> Result<()> {
+        let server = crate::test::with_directory_server();
+        let url = DirectoryUrl::Other(&server.dir_url);
+        let persist = MemoryPersist::new();
+        let dir = Directory::from_url(persist.clone(), url)?;
+        let acc = dir.account("foo@bar.com")?;
+        assert!(acc.certificates()?.is_empty());
+
+        for kind in [PersistKind::Certificate, PersistKind::PrivateKey] {
+            let key = PersistKey::new("foo@bar.com", kind, "example.com");
+            persist.put(&key, b"VALUE")?;
+        }
+        assert_eq!(acc.certificates()?, ["example.com"]);
+        assert!(acc.certificate("example.com")?.is_some());
+
+        acc.remove_certificate("example.com")?;
+        assert!(acc.certificates()?.is_empty());
+        assert!(acc.certificate("example.com")?.is_none());
+        let key = PersistKey::new("foo@bar.com", PersistKind::PrivateKey, "example.com");
+        assert!(persist.get(&key)?.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_ocsp_response() -> Result<()> {
+        use crate::http::{HttpClient, HttpRequest, HttpResponse, UreqClient};
+        use crate::test::{EXAMPLE_CHAIN, EXAMPLE_KEY, OCSP_GOOD};
+        use std::sync::{Arc, Mutex};
+
+        /// Stands in for the OCSP responder of the example certificate.
+        struct Responder(Arc<Mutex<Vec<Vec<u8>>>>, UreqClient);
+        impl HttpClient for Responder {
+            fn request(&self, req: &HttpRequest) -> Result<HttpResponse> {
+                if req.url != "http://ocsp.example.com" {
+                    return self.1.request(req);
+                }
+                assert_eq!(req.content_type, Some("application/ocsp-request"));
+                self.0.lock().unwrap().push(req.body.to_vec());
+                Ok(HttpResponse::new(200, vec![], OCSP_GOOD.to_vec()))
+            }
+        }
+
+        let server = crate::test::with_directory_server();
+        let url = DirectoryUrl::Other(&server.dir_url);
+        let persist = MemoryPersist::new();
+        let requests = Arc::new(Mutex::new(vec![]));
+        let dir = Directory::builder(persist.clone(), url)
+            .http_client(Responder(requests.clone(), UreqClient::new()))
+            .build()?;
+        let acc = dir.account("foo@bar.com")?;
+        assert!(acc.ocsp_response("example.com").is_err());
+
+        let realm = "foo@bar.com";
+        let pk_crt = PersistKey::new(realm, PersistKind::Certificate, "example.com");
+        let pk_key = PersistKey::new(realm, PersistKind::PrivateKey, "example.com");
+        let pk_ocsp = PersistKey::new(realm, PersistKind::OcspResponse, "example.com");
+        persist.put(&pk_crt, EXAMPLE_CHAIN.as_bytes())?;
+        persist.put(&pk_key, EXAMPLE_KEY.as_bytes())?;
+
+        let response = acc.ocsp_response("example.com")?;
+        assert_eq!(response.status(), OcspStatus::Good);
+        let cert = acc.certificate("example.com")?.unwrap();
+        assert_eq!(*requests.lock().unwrap(), [cert.ocsp_request()?]);
+        assert_eq!(persist.get(&pk_ocsp)?.as_deref(), Some(OCSP_GOOD));
+
+        // from the persistence until halfway to the next update
+        assert_eq!(acc.ocsp_response("example.com")?, response);
+        assert_eq!(requests.lock().unwrap().len(), 1);
+
+        // fetched again when the persisted response doesn't verify
+        persist.put(&pk_ocsp, b"GARBAGE")?;
+        assert_eq!(acc.ocsp_response("example.com")?, response);
+        assert_eq!(requests.lock().unwrap().len(), 2);
+
+        acc.remove_certificate("example.com")?;
+        assert!(persist.get(&pk_ocsp)?.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_revoke_certificate() -> Result<()> {
+        use crate::http::{HttpClient, HttpRequest, HttpResponse, UreqClient};
+        use crate::test::{EXAMPLE_CHAIN, EXAMPLE_KEY};
+        use std::sync::{Arc, Mutex};
+
+        /// Answers revocations with the queued problem types, if any.
+        struct Problems(Arc<Mutex<Vec<&'static str>>>, UreqClient);
+        impl HttpClient for Problems {
+            fn request(&self, req: &HttpRequest) -> Result<HttpResponse> {
+                if !req.url.ends_with("/acme/revoke-cert") {
+                    return self.1.request(req);
+                }
+                match self.0.lock().unwrap().pop() {
+                    Some(t) => {
+                        let ct = ("Content-Type".into(), "application/problem+json".into());
+                        let body = format!(r#"{{"type":"urn:ietf:params:acme:error:{}"}}"#, t);
+                        Ok(HttpResponse::new(400, vec![ct], body.into_bytes()))
+                    }
+                    None => self.1.request(req),
+                }
+            }
+        }
+
+        let server = crate::test::with_directory_server();
+        let url = DirectoryUrl::Other(&server.dir_url);
+        let persist = MemoryPersist::new();
+        let problems = Arc::new(Mutex::new(vec![]));
+        let dir = Directory::builder(persist.clone(), url)
+            .http_client(Problems(problems.clone(), UreqClient::new()))
+            .build()?;
+        let acc = dir.account("foo@bar.com")?;
+
+        let save = || -> Result<Certificate> {
+            let realm = "foo@bar.com";
+            let pk_crt = PersistKey::new(realm, PersistKind::Certificate, "example.com");
+            let pk_key = PersistKey::new(realm, PersistKind::PrivateKey, "example.com");
+            persist.put(&pk_crt, EXAMPLE_CHAIN.as_bytes())?;
+            persist.put(&pk_key, EXAMPLE_KEY.as_bytes())?;
+            Ok(acc.certificate("example.com")?.unwrap())
+        };
+        let cert = save()?;
+        assert!(!cert.is_revoked());
+
+        let outcome = acc.revoke_certificate(&cert, RevocationReason::KeyCompromise)?;
+        assert_eq!(outcome, RevocationOutcome::Revoked);
+        let revoked = acc.certificate("example.com")?.unwrap();
+        let revocation = *revoked.revocation().unwrap();
+        assert_eq!(revocation.reason(), Some(RevocationReason::KeyCompromise));
+
+        // keeps what was recorded first
+        problems.lock().unwrap().push("alreadyRevoked");
+        let outcome = acc.revoke_certificate(&cert, RevocationReason::Superseded)?;
+        assert_eq!(outcome, RevocationOutcome::AlreadyRevoked);
+        assert!(outcome.is_revoked());
+        let revoked = acc.certificate("example.com")?.unwrap();
+        assert_eq!(revoked.revocation(), Some(&revocation));
+
+        acc.remove_certificate("example.com")?;
+        let cert = save()?;
+        assert!(!cert.is_revoked());
+
+        problems.lock().unwrap().push("badRevocationReason");
+        let outcome = acc.revoke_certificate(&cert, RevocationReason::CACompromise)?;
+        assert!(matches!(
+            outcome,
+            RevocationOutcome::BadRevocationReason(ref p)
+                if p.problem_type() == ProblemType::BadRevocationReason
+        ));
+        assert!(!outcome.is_revoked());
+        assert!(!acc.certificate("example.com")?.unwrap().is_revoked());
+
+        problems.lock().unwrap().push("unauthorized");
+        let res = acc.revoke_certificate(&cert, RevocationReason::KeyCompromise);
+        assert_eq!(
+            res.unwrap_err().problem_type(),
+            Some(ProblemType::Unauthorized)
+        );
+        assert!(!acc.certificate("example.com")?.unwrap().is_revoked());
+
+        // unknown reason and time for an already revoked certificate
+        problems.lock().unwrap().push("alreadyRevoked");
+        acc.revoke_certificate(&cert, RevocationReason::KeyCompromise)?;
+        let revoked = acc.certificate("example.com")?.unwrap();
+        assert_eq!(revoked.revocation().unwrap().reason(), None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_revocation_from_ocsp() -> Result<()> {
+        use super::{load_certificate, save_ocsp_response};
+        use crate::test::{EXAMPLE_CHAIN, EXAMPLE_KEY, OCSP_REVOKED};
+        use std::time::{Duration, UNIX_EPOCH};
+
+        let persist = MemoryPersist::new();
+        let realm = "foo@bar.com";
+        let pk_crt = PersistKey::new(realm, PersistKind::Certificate, "example.com");
+        let pk_key = PersistKey::new(realm, PersistKind::PrivateKey, "example.com");
+        persist.put(&pk_crt, EXAMPLE_CHAIN.as_bytes())?;
+        persist.put(&pk_key, EXAMPLE_KEY.as_bytes())?;
+
+        let cert = load_certificate(&persist, realm, "example.com")?.unwrap();
+        save_ocsp_response(&persist, realm, "example.com", &cert, OCSP_REVOKED)?;
+        let revoked = load_certificate(&persist, realm, "example.com")?.unwrap();
+        let revocation = revoked.revocation().unwrap();
+        // 2025-01-01
+        assert_eq!(
+            revocation.time(),
+            UNIX_EPOCH + Duration::from_secs(1_735_689_600)
+        );
+        assert_eq!(revocation.reason(), Some(RevocationReason::KeyCompromise));
+        Ok(())
+    }
+
+    #[test]
+    fn test_certificate_history() -> Result<()> {
+        let server = crate::test::with_directory_server();
+        let url = DirectoryUrl::Other(&server.dir_url);
+        let persist = MemoryPersist::new();
+        let dir = Directory::builder(persist, url)
+            .certificate_history(2)
+            .build()?;
+        let acc = dir.account("foo@bar.com")?;
+        assert!(acc.certificate_history("acmetest.example.com")?.is_empty());
+
+        let mut certs = vec![];
+        for _ in 0..3 {
+            let ord = acc.new_order("acmetest.example.com", &[])?;
+            let ord = order::CsrOrder { order: ord.order };
+            let cert = ord
+                .finalize_pkey(crate::test::example_key(), 1)?
+                .download_and_save_cert()?;
+            certs.push(cert);
+        }
+
+        let history = acc.certificate_history("acmetest.example.com")?;
+        let versions: Vec<_> = history.iter().map(|v| v.version()).collect();
+        assert_eq!(versions, [2, 3]);
+        assert_eq!(history[0].certificate(), &certs[1]);
+        assert_eq!(acc.certificates()?, ["acmetest.example.com"]);
+
+        let cert = acc.activate_certificate("acmetest.example.com", 2)?;
+        assert_eq!(cert, certs[1]);
+        let current = acc.certificate("acmetest.example.com")?.unwrap();
+        assert_eq!(current, cert);
+        assert!(acc.activate_certificate("acmetest.example.com", 1).is_err());
+        Ok(())
+    }
 }
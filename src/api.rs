@@ -8,6 +8,9 @@ use serde::{
     ser::{SerializeMap, Serializer},
     Deserialize, Serialize,
 };
+use std::time::Duration;
+
+use crate::error::ProblemType;
 
 /// Serializes to `""`
 pub struct ApiEmptyString;
@@ -40,16 +43,37 @@ pub struct ApiProblem {
     pub detail: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub subproblems: Option<Vec<ApiSubproblem>>,
+    /// HTTP status of the response carrying the problem.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<u16>,
+    /// The `Retry-After` header of the response carrying the problem.
+    #[serde(skip)]
+    pub retry_after: Option<Duration>,
 }
 
 impl ApiProblem {
+    /// The typed version of `_type`.
+    pub fn problem_type(&self) -> ProblemType {
+        ProblemType::from_type(&self._type)
+    }
+
+    /// Subproblems for a specific identifier (domain name).
+    pub fn subproblems_for<'a>(
+        &'a self,
+        identifier: &'a str,
+    ) -> impl Iterator<Item = &'a ApiSubproblem> + 'a {
+        self.subproblems
+            .iter()
+            .flatten()
+            .filter(move |s| s.identifier.as_ref().map(|i| &i.value[..]) == Some(identifier))
+    }
+
     pub fn is_bad_nonce(&self) -> bool {
-        self._type == "badNonce"
+        self.problem_type() == ProblemType::BadNonce
     }
 
     pub fn is_jwt_verification_error(&self) -> bool {
-        (self._type == "urn:acme:error:malformed"
-            || self._type == "urn:ietf:params:acme:error:malformed")
+        self.problem_type() == ProblemType::Malformed
             && self
                 .detail
                 .as_ref()
@@ -76,6 +100,13 @@ pub struct ApiSubproblem {
     pub identifier: Option<ApiIdentifier>,
 }
 
+impl ApiSubproblem {
+    /// The typed version of `_type`.
+    pub fn problem_type(&self) -> ProblemType {
+        ProblemType::from_type(&self._type)
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Default)]
 pub struct ApiDirectory {
     pub newNonce: String,
@@ -85,6 +116,9 @@ pub struct ApiDirectory {
     pub newAuthz: Option<String>,
     pub revokeCert: String,
     pub keyChange: String,
+    /// ACME Renewal Information (ARI), RFC 9773.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub renewalInfo: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub meta: Option<ApiDirectoryMeta>,
 }
@@ -342,6 +376,26 @@ pub struct ApiRevocation {
     pub reason: usize,
 }
 
+// {
+//   "suggestedWindow": {
+//     "start": "2025-01-02T04:00:00Z",
+//     "end": "2025-01-03T04:00:00Z"
+//   },
+//   "explanationURL": "https://acme.example.com/docs/ari"
+// }
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ApiRenewalInfo {
+    pub suggestedWindow: ApiSuggestedWindow,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub explanationURL: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ApiSuggestedWindow {
+    pub start: String,
+    pub end: String,
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -357,4 +411,39 @@ mod test {
         let x = serde_json::to_string(&ApiEmptyObject).unwrap();
         assert_eq!("{}", x);
     }
+
+    #[test]
+    fn test_api_problem_types() {
+        let json = r#"{
+            "type": "urn:ietf:params:acme:error:compound",
+            "detail": "Errors during validation",
+            "status": 403,
+            "subproblems": [
+                {
+                    "type": "urn:ietf:params:acme:error:caa",
+                    "detail": "CAA record forbids issuance",
+                    "identifier": { "type": "dns", "value": "a.example.com" }
+                },
+                {
+                    "type": "urn:ietf:params:acme:error:rejectedIdentifier",
+                    "detail": "Policy forbids this name",
+                    "identifier": { "type": "dns", "value": "b.example.com" }
+                }
+            ]
+        }"#;
+        let p: ApiProblem = serde_json::from_str(json).unwrap();
+        assert_eq!(p.problem_type(), ProblemType::Compound);
+        assert_eq!(p.status, Some(403));
+        let sub: Vec<_> = p.subproblems_for("b.example.com").collect();
+        assert_eq!(sub.len(), 1);
+        assert_eq!(sub[0].problem_type(), ProblemType::RejectedIdentifier);
+        assert_eq!(
+            ProblemType::from_type("urn:acme:error:badNonce"),
+            ProblemType::BadNonce
+        );
+        assert_eq!(
+            ProblemType::from_type("httpReqError"),
+            ProblemType::Other("httpReqError".into())
+        );
+    }
 }
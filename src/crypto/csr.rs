@@ -0,0 +1,149 @@
+//! Minimal DER encoding of a PKCS#10 certificate signing request.
+//!
+//! The CSR only carries what the ACME API looks at: the public key and the domains
+//! as subject alt names. The subject is empty.
+
+use crate::{Error, Result};
+
+/// The signature algorithms of a CSR.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SignatureAlgorithm {
+    /// ECDSA with SHA-256, for P-256 keys.
+    EcdsaSha256,
+    /// ECDSA with SHA-384, for P-384 keys.
+    EcdsaSha384,
+    /// RSASSA-PKCS1-v1_5 with SHA-256.
+    RsaSha256,
+}
+
+impl SignatureAlgorithm {
+    /// The DER encoded `AlgorithmIdentifier`.
+    fn to_der(self) -> Vec<u8> {
+        match self {
+            // ecdsa-with-SHA256, parameters absent
+            SignatureAlgorithm::EcdsaSha256 => sequence(&[&oid(&[1, 2, 840, 10045, 4, 3, 2])]),
+            // ecdsa-with-SHA384, parameters absent
+            SignatureAlgorithm::EcdsaSha384 => sequence(&[&oid(&[1, 2, 840, 10045, 4, 3, 3])]),
+            // sha256WithRSAEncryption, parameters NULL
+            SignatureAlgorithm::RsaSha256 => {
+                sequence(&[&oid(&[1, 2, 840, 113549, 1, 1, 11]), &[0x05, 0x00]])
+            }
+        }
+    }
+}
+
+/// The `CertificationRequestInfo` to be signed.
+pub(crate) fn request_info(public_key_der: &[u8], domains: &[&str]) -> Result<Vec<u8>> {
+    for domain in domains {
+        check_dns_name(domain)?;
+    }
+    let version = [0x02, 0x01, 0x00];
+    let subject = sequence(&[]);
+
+    // subjectAltName with one dNSName ([2] IMPLICIT IA5String) per domain
+    let names: Vec<Vec<u8>> = domains.iter().map(|d| tlv(0x82, d.as_bytes())).collect();
+    let names: Vec<&[u8]> = names.iter().map(|n| &n[..]).collect();
+    let san = sequence(&[&oid(&[2, 5, 29, 17]), &tlv(0x04, &sequence(&names))]);
+    let extensions = sequence(&[&san]);
+
+    // extensionRequest attribute in the [0] IMPLICIT SET OF attributes
+    let ext_req = sequence(&[
+        &oid(&[1, 2, 840, 113549, 1, 9, 14]),
+        &tlv(0x31, &extensions),
+    ]);
+    let attributes = tlv(0xa0, &ext_req);
+
+    Ok(sequence(&[&version, &subject, public_key_der, &attributes]))
+}
+
+/// A dNSName is an IA5String, so internationalized names must be given as punycode
+/// A-labels. Checked here, as the CA only answers `badCSR`.
+fn check_dns_name(domain: &str) -> Result<()> {
+    let valid = !domain.is_empty()
+        && domain
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"-.*_".contains(&b));
+    if valid {
+        Ok(())
+    } else {
+        Err(Error::Crypto(format!(
+            "Invalid domain name for CSR, international names must be punycode (xn--): {:?}",
+            domain
+        )))
+    }
+}
+
+/// The complete `CertificationRequest` from the signed info.
+pub(crate) fn request(info: &[u8], algorithm: SignatureAlgorithm, signature: &[u8]) -> Vec<u8> {
+    // BIT STRING with no unused bits
+    let mut bits = Vec::with_capacity(signature.len() + 1);
+    bits.push(0);
+    bits.extend_from_slice(signature);
+
+    sequence(&[info, &algorithm.to_der(), &tlv(0x03, &bits)])
+}
+
+pub(super) fn sequence(parts: &[&[u8]]) -> Vec<u8> {
+    tlv(0x30, &parts.concat())
+}
+
+pub(super) fn tlv(tag: u8, content: &[u8]) -> Vec<u8> {
+    let mut v = vec![tag];
+    let len = content.len();
+    if len < 0x80 {
+        v.push(len as u8);
+    } else {
+        let bytes: Vec<u8> = len
+            .to_be_bytes()
+            .iter()
+            .copied()
+            .skip_while(|b| *b == 0)
+            .collect();
+        v.push(0x80 | bytes.len() as u8);
+        v.extend(bytes);
+    }
+    v.extend_from_slice(content);
+    v
+}
+
+pub(super) fn oid(arcs: &[u64]) -> Vec<u8> {
+    let mut content = vec![(arcs[0] * 40 + arcs[1]) as u8];
+    for &arc in &arcs[2..] {
+        // base 128, most significant group first, all but the last with the high bit set
+        let mut groups = vec![(arc & 0x7f) as u8];
+        let mut rest = arc >> 7;
+        while rest > 0 {
+            groups.push((rest & 0x7f) as u8 | 0x80);
+            rest >>= 7;
+        }
+        content.extend(groups.iter().rev());
+    }
+    tlv(0x06, &content)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_der_encoding() {
+        assert_eq!(
+            oid(&[1, 2, 840, 10045, 4, 3, 2]),
+            vec![0x06, 0x08, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x04, 0x03, 0x02]
+        );
+        assert_eq!(tlv(0x04, &[1, 2]), vec![0x04, 0x02, 1, 2]);
+        let long = tlv(0x04, &[0; 300]);
+        assert_eq!(&long[..4], &[0x04, 0x82, 0x01, 0x2c]);
+        assert_eq!(long.len(), 304);
+    }
+
+    #[test]
+    fn test_request_info_names() {
+        let key = [0x30, 0x00];
+        assert!(request_info(&key, &["example.com", "*.xn--bcher-kva.example"]).is_ok());
+        for bad in ["bücher.example", "", "a b.example", "example.com\0"] {
+            let err = request_info(&key, &["example.com", bad]).unwrap_err();
+            assert!(matches!(err, Error::Crypto(_)), "{}", bad);
+        }
+    }
+}
@@ -0,0 +1,134 @@
+//! Cryptography backend.
+//!
+//! Key handling, signing, hashing and certificate parsing are done by either the
+//! `openssl` feature (the default) or the pure Rust `rustcrypto` feature. Both
+//! backends provide the same items. If both features are enabled, openssl is used.
+
+pub(crate) mod csr;
+pub(crate) mod ocsp;
+
+#[cfg(feature = "openssl")]
+mod with_openssl;
+#[cfg(feature = "openssl")]
+pub(crate) use self::with_openssl::*;
+
+#[cfg(all(feature = "rustcrypto", not(feature = "openssl")))]
+mod with_rustcrypto;
+#[cfg(all(feature = "rustcrypto", not(feature = "openssl")))]
+pub(crate) use self::with_rustcrypto::*;
+
+#[cfg(not(any(feature = "openssl", feature = "rustcrypto")))]
+compile_error!("acme-lib requires either the `openssl` or the `rustcrypto` feature");
+
+use std::convert::TryFrom;
+
+use crate::{signer::CsrSigner, Result};
+
+impl KeyPair {
+    /// A DER encoded CSR for the domains, signed by this key.
+    pub(crate) fn create_csr(&self, domains: &[&str]) -> Result<Vec<u8>> {
+        let info = csr::request_info(&self.public_key_der()?, domains)?;
+        let (algorithm, signature) = self.sign_csr(&info)?;
+        Ok(csr::request(&info, algorithm, &signature))
+    }
+}
+
+/// A DER encoded CSR for the domains, signed by an external signer with the
+/// given public key.
+pub(crate) fn create_external_csr(
+    signer: &dyn CsrSigner,
+    public_key_der: &[u8],
+    domains: &[&str],
+) -> Result<Vec<u8>> {
+    let info = csr::request_info(public_key_der, domains)?;
+    let signature = signer.sign(&info)?;
+    Ok(csr::request(&info, signer.algorithm(), &signature))
+}
+
+/// The digest of a signature to verify.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum SignatureDigest {
+    Sha256,
+    Sha384,
+    Sha512,
+}
+
+/// The type of key of a signature to verify. A key of another type never verifies it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum SignatureKey {
+    Ecdsa,
+    Rsa,
+}
+
+/// The content octets of the OIDs of the supported signature algorithms.
+const OID_ECDSA_SHA256: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x04, 0x03, 0x02];
+const OID_ECDSA_SHA384: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x04, 0x03, 0x03];
+const OID_RSA_SHA256: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x0b];
+const OID_RSA_SHA384: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x0c];
+const OID_RSA_SHA512: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x0d];
+
+/// The key type and digest of a signature algorithm from the content octets of its
+/// OID, which is what the public ACME CAs use: ECDSA or RSA PKCS#1 v1.5 with SHA-2.
+pub(crate) fn signature_algorithm(oid: &[u8]) -> Option<(SignatureKey, SignatureDigest)> {
+    Some(match oid {
+        OID_ECDSA_SHA256 => (SignatureKey::Ecdsa, SignatureDigest::Sha256),
+        OID_ECDSA_SHA384 => (SignatureKey::Ecdsa, SignatureDigest::Sha384),
+        OID_RSA_SHA256 => (SignatureKey::Rsa, SignatureDigest::Sha256),
+        OID_RSA_SHA384 => (SignatureKey::Rsa, SignatureDigest::Sha384),
+        OID_RSA_SHA512 => (SignatureKey::Rsa, SignatureDigest::Sha512),
+        _ => return None,
+    })
+}
+
+/// RFC 4514 string of a distinguished name from the short names and values of the
+/// attributes, in the order they are encoded.
+fn format_dn(attributes: &[(String, String)]) -> String {
+    let mut dn = String::new();
+    for (name, value) in attributes.iter().rev() {
+        if !dn.is_empty() {
+            dn.push(',');
+        }
+        dn.push_str(name);
+        dn.push('=');
+        for (i, c) in value.chars().enumerate() {
+            match c {
+                '#' if i == 0 => dn.push_str("\\#"),
+                ' ' if i == 0 || i == value.chars().count() - 1 => dn.push_str("\\ "),
+                '"' | '+' | ',' | ';' | '<' | '>' | '\\' => {
+                    dn.push('\\');
+                    dn.push(c);
+                }
+                _ => dn.push(c),
+            }
+        }
+    }
+    dn
+}
+
+/// Upper case hex of a big endian integer, without leading zeros.
+fn serial_hex(bytes: &[u8]) -> String {
+    let start = bytes.iter().position(|b| *b != 0).unwrap_or(bytes.len());
+    let hex: String = bytes[start..]
+        .iter()
+        .map(|b| format!("{:02X}", b))
+        .collect();
+    if hex.is_empty() {
+        "00".to_string()
+    } else {
+        hex
+    }
+}
+
+/// The IP address of a subject alternative name.
+fn ip_address(bytes: &[u8]) -> Option<std::net::IpAddr> {
+    match bytes.len() {
+        4 => Some(<[u8; 4]>::try_from(bytes).ok()?.into()),
+        16 => Some(<[u8; 16]>::try_from(bytes).ok()?.into()),
+        _ => None,
+    }
+}
+
+/// Turn any backend error into an `Error::Crypto`.
+fn crypto_err<E: std::fmt::Display>(e: E) -> crate::Error {
+    crate::Error::Crypto(e.to_string())
+}
@@ -0,0 +1,464 @@
+//! Minimal DER encoding of OCSP requests and decoding of OCSP responses (RFC 6960).
+//!
+//! Requests identify the certificate by SHA-1 hashes of the issuer, like every
+//! responder expects, and carry no nonce, which the public ACME CAs don't support.
+//!
+//! Both backends share it, since the RustCrypto crates used here have no OCSP
+//! support. The checks of the responses are tested in `crate::ocsp`.
+
+use std::convert::TryFrom;
+use time::{Date, Month, OffsetDateTime, PrimitiveDateTime, Time};
+
+use super::{
+    crypto_err,
+    csr::{oid, sequence, tlv},
+    sha1, sha256, signature_algorithm, SignatureDigest, SignatureKey,
+};
+use crate::{ocsp::OcspStatus, Error, Result, RevocationReason};
+
+const TAG_INTEGER: u8 = 0x02;
+const TAG_BIT_STRING: u8 = 0x03;
+const TAG_OCTET_STRING: u8 = 0x04;
+const TAG_OID: u8 = 0x06;
+const TAG_ENUMERATED: u8 = 0x0a;
+const TAG_GENERALIZED_TIME: u8 = 0x18;
+const TAG_SEQUENCE: u8 = 0x30;
+
+/// The content octets of the OIDs, as compared with the decoded elements.
+const OID_SHA1: &[u8] = &[0x2b, 0x0e, 0x03, 0x02, 0x1a];
+const OID_SHA256: &[u8] = &[0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02, 0x01];
+const OID_OCSP_BASIC: &[u8] = &[0x2b, 0x06, 0x01, 0x05, 0x05, 0x07, 0x30, 0x01, 0x01];
+const OID_EXT_KEY_USAGE: &[u8] = &[0x55, 0x1d, 0x25];
+const OID_OCSP_SIGNING: &[u8] = &[0x2b, 0x06, 0x01, 0x05, 0x05, 0x07, 0x03, 0x09];
+
+/// A decoded DER element.
+#[derive(Debug, Clone, Copy)]
+struct Element<'a> {
+    tag: u8,
+    content: &'a [u8],
+    /// The whole encoding, tag and length included.
+    raw: &'a [u8],
+}
+
+impl<'a> Element<'a> {
+    /// Read the elements inside a constructed element.
+    fn reader(&self) -> Reader<'a> {
+        Reader(self.content)
+    }
+}
+
+/// Reads DER elements one after another.
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    fn next(&mut self) -> Result<Element<'a>> {
+        let data = self.0;
+        let malformed = || crypto_err("Malformed DER");
+        let (&tag, rest) = data.split_first().ok_or_else(malformed)?;
+        // tag numbers above 30 take more octets, which OCSP doesn't use
+        if tag & 0x1f == 0x1f {
+            return Err(malformed());
+        }
+        let (&first, rest) = rest.split_first().ok_or_else(malformed)?;
+        let (len, rest) = if first < 0x80 {
+            (first as usize, rest)
+        } else {
+            // long form, the indefinite length (0x80) isn't DER
+            let count = (first & 0x7f) as usize;
+            if count == 0 || count > 4 || rest.len() < count {
+                return Err(malformed());
+            }
+            let len = rest[..count]
+                .iter()
+                .fold(0_usize, |len, b| (len << 8) | *b as usize);
+            (len, &rest[count..])
+        };
+        if rest.len() < len {
+            return Err(malformed());
+        }
+        let header = data.len() - rest.len();
+        self.0 = &rest[len..];
+        Ok(Element {
+            tag,
+            content: &rest[..len],
+            raw: &data[..header + len],
+        })
+    }
+
+    /// The next element, which must have the tag.
+    fn expect(&mut self, tag: u8) -> Result<Element<'a>> {
+        let element = self.next()?;
+        if element.tag != tag {
+            return Err(crypto_err(format!(
+                "Expected DER tag {:#04x}, found {:#04x}",
+                tag, element.tag
+            )));
+        }
+        Ok(element)
+    }
+
+    /// The next element if it has the tag.
+    fn optional(&mut self, tag: u8) -> Result<Option<Element<'a>>> {
+        if self.0.first() == Some(&tag) {
+            self.next().map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+/// The fields of a certificate needed to identify it to an OCSP responder.
+struct TbsCertificate<'a> {
+    serial: &'a [u8],
+    issuer: &'a [u8],
+    subject: &'a [u8],
+    public_key: &'a [u8],
+    extensions: Option<Element<'a>>,
+}
+
+fn tbs_certificate(der: &[u8]) -> Result<TbsCertificate<'_>> {
+    let cert = Reader(der).expect(TAG_SEQUENCE)?;
+    let mut tbs = cert.reader().expect(TAG_SEQUENCE)?.reader();
+    // version [0] EXPLICIT
+    tbs.optional(0xa0)?;
+    let serial = tbs.expect(TAG_INTEGER)?.content;
+    tbs.expect(TAG_SEQUENCE)?;
+    let issuer = tbs.expect(TAG_SEQUENCE)?.raw;
+    tbs.expect(TAG_SEQUENCE)?;
+    let subject = tbs.expect(TAG_SEQUENCE)?.raw;
+    let mut spki = tbs.expect(TAG_SEQUENCE)?.reader();
+    spki.expect(TAG_SEQUENCE)?;
+    let bits = spki.expect(TAG_BIT_STRING)?.content;
+    // issuerUniqueID [1] and subjectUniqueID [2] are long gone
+    tbs.optional(0x81)?;
+    tbs.optional(0x82)?;
+    let extensions = tbs.optional(0xa3)?;
+    Ok(TbsCertificate {
+        serial,
+        issuer,
+        subject,
+        // without the octet of unused bits
+        public_key: bits.get(1..).unwrap_or_default(),
+        extensions,
+    })
+}
+
+/// Whether the certificate is issued for signing OCSP responses, i.e. it has the
+/// OCSPSigning extended key usage.
+pub(crate) fn is_ocsp_signer(der: &[u8]) -> Result<bool> {
+    let tbs = tbs_certificate(der)?;
+    let Some(extensions) = tbs.extensions else {
+        return Ok(false);
+    };
+    let mut list = extensions.reader().expect(TAG_SEQUENCE)?.reader();
+    while !list.is_empty() {
+        let mut ext = list.expect(TAG_SEQUENCE)?.reader();
+        if ext.expect(TAG_OID)?.content != OID_EXT_KEY_USAGE {
+            continue;
+        }
+        // critical BOOLEAN DEFAULT FALSE
+        ext.optional(0x01)?;
+        let value = ext.expect(TAG_OCTET_STRING)?.content;
+        let mut usages = Reader(value).expect(TAG_SEQUENCE)?.reader();
+        while !usages.is_empty() {
+            if usages.expect(TAG_OID)?.content == OID_OCSP_SIGNING {
+                return Ok(true);
+            }
+        }
+    }
+    Ok(false)
+}
+
+/// Identifies a certificate by its issuer and serial number.
+pub(crate) struct CertId<'a> {
+    issuer_name: &'a [u8],
+    issuer_key: &'a [u8],
+    serial: &'a [u8],
+}
+
+impl<'a> CertId<'a> {
+    /// The id of the certificate from its DER and that of its issuer.
+    pub fn new(der: &'a [u8], issuer_der: &'a [u8]) -> Result<Self> {
+        let cert = tbs_certificate(der)?;
+        let issuer = tbs_certificate(issuer_der)?;
+        if cert.issuer != issuer.subject {
+            return Err(crypto_err("The issuer didn't issue the certificate"));
+        }
+        Ok(CertId {
+            issuer_name: issuer.subject,
+            issuer_key: issuer.public_key,
+            serial: cert.serial,
+        })
+    }
+
+    /// The DER encoded `CertID` with SHA-1 hashes.
+    fn to_der(&self) -> Vec<u8> {
+        let algorithm = sequence(&[&oid(&[1, 3, 14, 3, 2, 26]), &[0x05, 0x00]]);
+        sequence(&[
+            &algorithm,
+            &tlv(TAG_OCTET_STRING, &sha1(self.issuer_name)),
+            &tlv(TAG_OCTET_STRING, &sha1(self.issuer_key)),
+            &tlv(TAG_INTEGER, self.serial),
+        ])
+    }
+
+    /// Whether a `CertID` of a response is this one. Responders answer with the hash
+    /// algorithm of the request, but SHA-256 is accepted too.
+    fn matches(&self, cert_id: Element) -> Result<bool> {
+        let mut fields = cert_id.reader();
+        let algorithm = fields
+            .expect(TAG_SEQUENCE)?
+            .reader()
+            .expect(TAG_OID)?
+            .content;
+        let name_hash = fields.expect(TAG_OCTET_STRING)?.content;
+        let key_hash = fields.expect(TAG_OCTET_STRING)?.content;
+        let serial = fields.expect(TAG_INTEGER)?.content;
+        let (name, key) = match algorithm {
+            OID_SHA1 => (
+                sha1(self.issuer_name).to_vec(),
+                sha1(self.issuer_key).to_vec(),
+            ),
+            OID_SHA256 => (
+                sha256(self.issuer_name).to_vec(),
+                sha256(self.issuer_key).to_vec(),
+            ),
+            _ => return Ok(false),
+        };
+        Ok(name_hash == name && key_hash == key && serial == self.serial)
+    }
+}
+
+/// The DER encoded unsigned `OCSPRequest` for one certificate.
+pub(crate) fn request(cert_id: &CertId) -> Vec<u8> {
+    // Request, requestList, TBSRequest, OCSPRequest
+    let request = sequence(&[&cert_id.to_der()]);
+    let tbs_request = sequence(&[&sequence(&[&request])]);
+    sequence(&[&tbs_request])
+}
+
+/// A decoded `BasicOCSPResponse`, not yet verified.
+pub(crate) struct BasicResponse<'a> {
+    /// The signed `ResponseData`.
+    pub tbs: &'a [u8],
+    pub key: SignatureKey,
+    pub digest: SignatureDigest,
+    pub signature: &'a [u8],
+    /// Certificates to help verify the signature, i.e. of a delegated responder.
+    pub certs: Vec<&'a [u8]>,
+    pub produced_at: OffsetDateTime,
+    responses: Vec<Element<'a>>,
+}
+
+/// The answer of a response about one certificate.
+pub(crate) struct SingleResponse {
+    pub status: OcspStatus,
+    pub this_update: OffsetDateTime,
+    pub next_update: Option<OffsetDateTime>,
+}
+
+impl<'a> BasicResponse<'a> {
+    /// Decode a DER `OCSPResponse`, which fails unless the responder answered with
+    /// a basic response.
+    pub fn from_der(der: &'a [u8]) -> Result<Self> {
+        let mut response = Reader(der).expect(TAG_SEQUENCE)?.reader();
+        let status = response.expect(TAG_ENUMERATED)?.content;
+        if status != [0] {
+            let name = match status {
+                [1] => "malformedRequest",
+                [2] => "internalError",
+                [3] => "tryLater",
+                [5] => "sigRequired",
+                [6] => "unauthorized",
+                _ => "unknown",
+            };
+            return Err(Error::Other(format!(
+                "OCSP responder answered with status {}",
+                name
+            )));
+        }
+
+        let mut bytes = response
+            .expect(0xa0)?
+            .reader()
+            .expect(TAG_SEQUENCE)?
+            .reader();
+        if bytes.expect(TAG_OID)?.content != OID_OCSP_BASIC {
+            return Err(crypto_err("Unsupported OCSP response type"));
+        }
+        let basic = bytes.expect(TAG_OCTET_STRING)?.content;
+
+        let mut basic = Reader(basic).expect(TAG_SEQUENCE)?.reader();
+        let tbs = basic.expect(TAG_SEQUENCE)?;
+        let algorithm = basic
+            .expect(TAG_SEQUENCE)?
+            .reader()
+            .expect(TAG_OID)?
+            .content;
+        let (key, digest) = signature_algorithm(algorithm)
+            .ok_or_else(|| crypto_err("Unsupported OCSP signature algorithm"))?;
+        let signature = basic.expect(TAG_BIT_STRING)?.content;
+        let mut certs = vec![];
+        if let Some(explicit) = basic.optional(0xa0)? {
+            let mut list = explicit.reader().expect(TAG_SEQUENCE)?.reader();
+            while !list.is_empty() {
+                certs.push(list.expect(TAG_SEQUENCE)?.raw);
+            }
+        }
+
+        let mut data = tbs.reader();
+        // version [0] EXPLICIT, responderID [1] byName or [2] byKey
+        data.optional(0xa0)?;
+        if data.optional(0xa1)?.is_none() {
+            data.expect(0xa2)?;
+        }
+        let produced_at = generalized_time(data.expect(TAG_GENERALIZED_TIME)?)?;
+        let mut list = data.expect(TAG_SEQUENCE)?.reader();
+        let mut responses = vec![];
+        while !list.is_empty() {
+            responses.push(list.expect(TAG_SEQUENCE)?);
+        }
+
+        Ok(BasicResponse {
+            tbs: tbs.raw,
+            key,
+            digest,
+            // without the octet of unused bits
+            signature: signature.get(1..).unwrap_or_default(),
+            certs,
+            produced_at,
+            responses,
+        })
+    }
+
+    /// The answer about the certificate, if the response has one.
+    pub fn find(&self, cert_id: &CertId) -> Result<Option<SingleResponse>> {
+        for response in &self.responses {
+            let mut fields = response.reader();
+            if !cert_id.matches(fields.expect(TAG_SEQUENCE)?)? {
+                continue;
+            }
+            let status = fields.next()?;
+            let status = match status.tag {
+                0x80 => OcspStatus::Good,
+                0xa1 => {
+                    let mut info = status.reader();
+                    let time = generalized_time(info.expect(TAG_GENERALIZED_TIME)?)?;
+                    let reason = match info.optional(0xa0)? {
+                        Some(reason) => {
+                            let code = reason.reader().expect(TAG_ENUMERATED)?.content;
+                            code.last().and_then(|c| RevocationReason::from_code(*c))
+                        }
+                        None => None,
+                    };
+                    OcspStatus::Revoked { time, reason }
+                }
+                0x82 => OcspStatus::Unknown,
+                _ => return Err(crypto_err("Malformed OCSP certificate status")),
+            };
+            let this_update = generalized_time(fields.expect(TAG_GENERALIZED_TIME)?)?;
+            let next_update = match fields.optional(0xa0)? {
+                Some(next) => Some(generalized_time(
+                    next.reader().expect(TAG_GENERALIZED_TIME)?,
+                )?),
+                None => None,
+            };
+            return Ok(Some(SingleResponse {
+                status,
+                this_update,
+                next_update,
+            }));
+        }
+        Ok(None)
+    }
+}
+
+/// A `GeneralizedTime` in UTC, `YYYYMMDDHHMMSSZ` with optional fractions of a second.
+fn generalized_time(element: Element) -> Result<OffsetDateTime> {
+    let s = std::str::from_utf8(element.content).map_err(crypto_err)?;
+    let malformed = || crypto_err(format!("Malformed time: {}", s));
+    let digits = s
+        .strip_suffix('Z')
+        .filter(|d| d.len() >= 14 && d.is_char_boundary(14))
+        .ok_or_else(malformed)?;
+    if !digits[14..].is_empty() && !digits[14..].starts_with('.') {
+        return Err(malformed());
+    }
+    let num = |range: std::ops::Range<usize>| -> Result<u16> {
+        digits[range].parse().map_err(|_| malformed())
+    };
+    let month = Month::try_from(num(4..6)? as u8).map_err(|_| malformed())?;
+    let date = Date::from_calendar_date(num(0..4)? as i32, month, num(6..8)? as u8)
+        .map_err(|_| malformed())?;
+    let time = Time::from_hms(num(8..10)? as u8, num(10..12)? as u8, num(12..14)? as u8)
+        .map_err(|_| malformed())?;
+    Ok(PrimitiveDateTime::new(date, time).assume_utc())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use time::macros::datetime;
+
+    #[test]
+    fn test_request() -> Result<()> {
+        use crate::{test::EXAMPLE_CHAIN, Certificate};
+
+        // openssl ocsp -issuer <intermediate> -cert <leaf> -no_nonce -reqout
+        let expected = [
+            "304a3048304630443042300906052b0e03021a0500041449d7c7c3ac1441770e",
+            "fcdece0d8396396bdd705d04141f8d35f52c9513c712cd47c0a7faca0f7cdab9",
+            "00020900c0ffee0123456789",
+        ]
+        .concat();
+        let request = Certificate::new(None, EXAMPLE_CHAIN.into()).ocsp_request()?;
+        let hex: String = request.iter().map(|b| format!("{:02x}", b)).collect();
+        assert_eq!(hex, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn test_reader() -> Result<()> {
+        let long = tlv(TAG_OCTET_STRING, &[7; 300]);
+        let der = sequence(&[&[0x02, 0x01, 0x05], &long]);
+        let mut outer = Reader(&der);
+        let seq = outer.expect(TAG_SEQUENCE)?;
+        assert!(outer.is_empty());
+        assert_eq!(seq.raw, &der[..]);
+        let mut inner = seq.reader();
+        assert!(inner.optional(0xa0)?.is_none());
+        assert_eq!(inner.expect(TAG_INTEGER)?.content, [5]);
+        assert_eq!(inner.next()?.content.len(), 300);
+        assert!(inner.next().is_err());
+
+        // truncated, indefinite length, wrong tag
+        assert!(Reader(&der[..der.len() - 1]).next().is_err());
+        assert!(Reader(&[0x30, 0x80, 0x00, 0x00]).next().is_err());
+        assert!(Reader(&der).expect(TAG_INTEGER).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_generalized_time() -> Result<()> {
+        let parse = |s: &str| {
+            let der = tlv(TAG_GENERALIZED_TIME, s.as_bytes());
+            generalized_time(Reader(&der).next()?)
+        };
+        assert_eq!(
+            parse("20250101120030Z")?,
+            datetime!(2025-01-01 12:00:30 UTC)
+        );
+        assert_eq!(
+            parse("20250101120030.25Z")?,
+            datetime!(2025-01-01 12:00:30 UTC)
+        );
+        assert!(parse("20250101120030").is_err());
+        assert!(parse("20251301120030Z").is_err());
+        assert!(parse("2025010112Z").is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,453 @@
+//
+use once_cell::sync::Lazy;
+use openssl::{
+    bn::{BigNum, BigNumContext},
+    ec::{Asn1Flag, EcGroup, EcKey},
+    ecdsa::EcdsaSig,
+    hash::MessageDigest,
+    nid::Nid,
+    pkcs12::Pkcs12,
+    pkey::{self, Id, PKey},
+    rsa::Rsa,
+    sign::{Signer, Verifier},
+    stack::Stack,
+    x509::{X509NameRef, X509},
+};
+use std::fmt;
+use time::{macros::format_description, OffsetDateTime, PrimitiveDateTime};
+
+use super::{
+    crypto_err, csr::SignatureAlgorithm, format_dn, ip_address, serial_hex, SignatureDigest,
+    SignatureKey,
+};
+use crate::{
+    cert::{CertificateInfo, KeyAlgorithm},
+    jwt::Jwk,
+    Result,
+};
+
+static EC_GROUP_P256: Lazy<EcGroup> = Lazy::new(|| ec_group(Nid::X9_62_PRIME256V1));
+static EC_GROUP_P384: Lazy<EcGroup> = Lazy::new(|| ec_group(Nid::SECP384R1));
+
+fn ec_group(nid: Nid) -> EcGroup {
+    let mut g = EcGroup::from_curve_name(nid).expect("EcGroup");
+    // this is required for openssl 1.0.x (but not 1.1.x)
+    g.set_asn1_flag(Asn1Flag::NAMED_CURVE);
+    g
+}
+
+pub(crate) fn sha1(data: &[u8]) -> [u8; 20] {
+    openssl::sha::sha1(data)
+}
+
+pub(crate) fn sha256(data: &[u8]) -> [u8; 32] {
+    openssl::sha::sha256(data)
+}
+
+pub(crate) fn random_bytes(buf: &mut [u8]) -> Result<()> {
+    Ok(openssl::rand::rand_bytes(buf)?)
+}
+
+/// P-256 key used for signing the JWS of the ACME account.
+#[derive(Clone)]
+pub(crate) struct EcP256Key(EcKey<pkey::Private>);
+
+impl EcP256Key {
+    pub fn generate() -> Result<Self> {
+        Ok(EcP256Key(EcKey::generate(&EC_GROUP_P256)?))
+    }
+
+    pub fn from_pem(pem: &[u8]) -> Result<Self> {
+        let key =
+            EcKey::private_key_from_pem(pem).map_err(|e| format!("Failed to read PEM: {}", e))?;
+        Ok(EcP256Key(key))
+    }
+
+    /// SEC1 PEM (`EC PRIVATE KEY`).
+    pub fn to_pem(&self) -> Result<Vec<u8>> {
+        Ok(self.0.private_key_to_pem()?)
+    }
+
+    /// The affine coordinates of the public key, 32 bytes each.
+    pub fn public_xy(&self) -> Result<(Vec<u8>, Vec<u8>)> {
+        let mut ctx = BigNumContext::new()?;
+        let mut x = BigNum::new()?;
+        let mut y = BigNum::new()?;
+        self.0
+            .public_key()
+            .affine_coordinates_gfp(&EC_GROUP_P256, &mut x, &mut y, &mut ctx)?;
+        Ok((x.to_vec_padded(32)?, y.to_vec_padded(32)?))
+    }
+
+    /// ES256 signature as the 64 bytes `r || s`.
+    pub fn sign_es256(&self, data: &[u8]) -> Result<Vec<u8>> {
+        let digest = sha256(data);
+        let sig = EcdsaSig::sign(&digest, &self.0)?;
+        let mut v = sig.r().to_vec_padded(32)?;
+        v.extend(sig.s().to_vec_padded(32)?);
+        Ok(v)
+    }
+}
+
+impl fmt::Debug for EcP256Key {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "EcP256Key")
+    }
+}
+
+/// Private key of an issued certificate.
+#[derive(Clone)]
+pub(crate) struct KeyPair(pub(crate) PKey<pkey::Private>);
+
+impl KeyPair {
+    pub fn rsa(bits: u32) -> Result<Self> {
+        Ok(KeyPair(PKey::from_rsa(Rsa::generate(bits)?)?))
+    }
+
+    pub fn p256() -> Result<Self> {
+        let key = EcKey::generate(&EC_GROUP_P256)?;
+        Ok(KeyPair(PKey::from_ec_key(key)?))
+    }
+
+    pub fn p384() -> Result<Self> {
+        let key = EcKey::generate(&EC_GROUP_P384)?;
+        Ok(KeyPair(PKey::from_ec_key(key)?))
+    }
+
+    pub fn from_pem(pem: &[u8]) -> Result<Self> {
+        Ok(KeyPair(PKey::private_key_from_pem(pem)?))
+    }
+
+    /// PKCS#8 PEM.
+    pub fn to_pem(&self) -> Result<Vec<u8>> {
+        Ok(self.0.private_key_to_pem_pkcs8()?)
+    }
+
+    /// DER in the traditional format (PKCS#1 for RSA, SEC1 for EC).
+    pub fn to_der(&self) -> Result<Vec<u8>> {
+        Ok(self.0.private_key_to_der()?)
+    }
+
+    /// DER encoded `SubjectPublicKeyInfo`.
+    pub fn public_key_der(&self) -> Result<Vec<u8>> {
+        Ok(self.0.public_key_to_der()?)
+    }
+
+    /// The public key as a JWK for the signatures of `sign_jws`.
+    pub fn jwk(&self) -> Result<Jwk> {
+        match self.0.id() {
+            Id::RSA => {
+                let rsa = self.0.rsa()?;
+                Ok(Jwk::rsa(&rsa.n().to_vec(), &rsa.e().to_vec()))
+            }
+            Id::EC => {
+                let ec = self.0.ec_key()?;
+                let mut ctx = BigNumContext::new()?;
+                let mut x = BigNum::new()?;
+                let mut y = BigNum::new()?;
+                ec.public_key()
+                    .affine_coordinates(ec.group(), &mut x, &mut y, &mut ctx)?;
+                match ec.group().curve_name() {
+                    Some(Nid::X9_62_PRIME256V1) => {
+                        Ok(Jwk::p256(&x.to_vec_padded(32)?, &y.to_vec_padded(32)?))
+                    }
+                    Some(Nid::SECP384R1) => {
+                        Ok(Jwk::p384(&x.to_vec_padded(48)?, &y.to_vec_padded(48)?))
+                    }
+                    nid => Err(crypto_err(format!("Unsupported curve: {:?}", nid))),
+                }
+            }
+            id => Err(crypto_err(format!("Unsupported key type: {:?}", id))),
+        }
+    }
+
+    /// JWS signature, `r || s` for ES256 and ES384 or PKCS#1 v1.5 for RS256.
+    pub fn sign_jws(&self, data: &[u8]) -> Result<Vec<u8>> {
+        if self.0.id() == Id::RSA {
+            let mut signer = Signer::new(MessageDigest::sha256(), &self.0)?;
+            signer.update(data)?;
+            return Ok(signer.sign_to_vec()?);
+        }
+        let ec = self.0.ec_key()?;
+        let (digest, size) = match ec.group().curve_name() {
+            Some(Nid::SECP384R1) => (openssl::sha::sha384(data).to_vec(), 48),
+            _ => (sha256(data).to_vec(), 32),
+        };
+        let sig = EcdsaSig::sign(&digest, &ec)?;
+        let mut v = sig.r().to_vec_padded(size)?;
+        v.extend(sig.s().to_vec_padded(size)?);
+        Ok(v)
+    }
+
+    pub fn sign_csr(&self, data: &[u8]) -> Result<(SignatureAlgorithm, Vec<u8>)> {
+        let (algorithm, md) = match self.0.id() {
+            Id::RSA => (SignatureAlgorithm::RsaSha256, MessageDigest::sha256()),
+            Id::EC => {
+                let nid = self.0.ec_key()?.group().curve_name();
+                if nid == Some(Nid::SECP384R1) {
+                    (SignatureAlgorithm::EcdsaSha384, MessageDigest::sha384())
+                } else if nid == Some(Nid::X9_62_PRIME256V1) {
+                    (SignatureAlgorithm::EcdsaSha256, MessageDigest::sha256())
+                } else {
+                    return Err(crypto_err(format!("Unsupported curve: {:?}", nid)));
+                }
+            }
+            id => return Err(crypto_err(format!("Unsupported key type: {:?}", id))),
+        };
+        let mut signer = Signer::new(md, &self.0)?;
+        signer.update(data)?;
+        Ok((algorithm, signer.sign_to_vec()?))
+    }
+}
+
+/// DER of the first certificate in the PEM.
+pub(crate) fn certificate_der(pem: &[u8]) -> Result<Vec<u8>> {
+    Ok(X509::from_pem(pem)?.to_der()?)
+}
+
+/// The end of the validity of the first certificate in the PEM.
+pub(crate) fn certificate_not_after(pem: &[u8]) -> Result<OffsetDateTime> {
+    let x509 = X509::from_pem(pem)?;
+
+    // convert asn1 time to Tm
+    let not_after = format!("{}", x509.not_after());
+    // Display trait produces this format, which is kinda dumb.
+    // Apr 19 08:48:46 2019 GMT
+    parse_date(&not_after)
+}
+
+/// Whether the certificate is signed by the key of the issuer, both as DER.
+pub(crate) fn verify_signature(der: &[u8], issuer_der: &[u8]) -> Result<bool> {
+    let issuer_key = X509::from_der(issuer_der)?.public_key()?;
+    Ok(X509::from_der(der)?.verify(&issuer_key)?)
+}
+
+/// Whether the signature of the data is made by the key of the DER encoded
+/// `SubjectPublicKeyInfo`, with the type of key and the digest.
+pub(crate) fn verify_signed(
+    public_key_der: &[u8],
+    kind: SignatureKey,
+    digest: SignatureDigest,
+    data: &[u8],
+    signature: &[u8],
+) -> Result<bool> {
+    let key = PKey::public_key_from_der(public_key_der)?;
+    let expected = match kind {
+        SignatureKey::Ecdsa => Id::EC,
+        SignatureKey::Rsa => Id::RSA,
+    };
+    if key.id() != expected {
+        return Ok(false);
+    }
+    let md = match digest {
+        SignatureDigest::Sha256 => MessageDigest::sha256(),
+        SignatureDigest::Sha384 => MessageDigest::sha384(),
+        SignatureDigest::Sha512 => MessageDigest::sha512(),
+    };
+    let mut verifier = Verifier::new(md, &key)?;
+    verifier.update(data)?;
+    // a malformed signature is as bad as a wrong one
+    Ok(verifier.verify(signature).unwrap_or(false))
+}
+
+/// A password protected PKCS#12 of the key and the certificates as DER, the issued
+/// certificate first. Encrypted with AES-256-CBC and a SHA-256 MAC.
+pub(crate) fn create_pkcs12(
+    key: &KeyPair,
+    certs: &[Vec<u8>],
+    friendly_name: &str,
+    password: &str,
+) -> Result<Vec<u8>> {
+    let (leaf, chain) = certs.split_first().ok_or("No certificate for PKCS#12")?;
+    let mut ca = Stack::new()?;
+    for der in chain {
+        ca.push(X509::from_der(der)?)?;
+    }
+    let leaf = X509::from_der(leaf)?;
+    let pkcs12 = Pkcs12::builder()
+        .name(friendly_name)
+        .pkey(&key.0)
+        .cert(&leaf)
+        .ca(ca)
+        .key_algorithm(Nid::AES_256_CBC)
+        .cert_algorithm(Nid::AES_256_CBC)
+        .mac_md(MessageDigest::sha256())
+        .build2(password)?;
+    Ok(pkcs12.to_der()?)
+}
+
+/// Parse the DER of a certificate.
+pub(crate) fn certificate_info(der: &[u8]) -> Result<CertificateInfo> {
+    let x509 = X509::from_der(der)?;
+
+    let mut dns_names = vec![];
+    let mut ip_addresses = vec![];
+    for name in x509.subject_alt_names().into_iter().flatten() {
+        if let Some(dns) = name.dnsname() {
+            dns_names.push(dns.to_string());
+        } else if let Some(ip) = name.ipaddress().and_then(ip_address) {
+            ip_addresses.push(ip);
+        }
+    }
+
+    // an error means there's no authority info access extension
+    let ocsp_urls = match x509.ocsp_responders() {
+        Ok(urls) => urls.iter().map(|u| u.to_string()).collect(),
+        Err(_) => vec![],
+    };
+
+    let key = x509.public_key()?;
+    let key_algorithm = match key.id() {
+        Id::RSA => KeyAlgorithm::Rsa,
+        Id::EC => KeyAlgorithm::Ec,
+        Id::ED25519 => KeyAlgorithm::Ed25519,
+        _ => KeyAlgorithm::Other,
+    };
+
+    Ok(CertificateInfo {
+        der: der.to_vec(),
+        serial_number: serial_hex(&x509.serial_number().to_bn()?.to_vec()),
+        subject: name_string(x509.subject_name())?,
+        issuer: name_string(x509.issuer_name())?,
+        not_before: parse_date(&x509.not_before().to_string())?,
+        not_after: parse_date(&x509.not_after().to_string())?,
+        dns_names,
+        ip_addresses,
+        public_key_der: key.public_key_to_der()?,
+        key_algorithm,
+        key_bits: key.bits(),
+        subject_key_id: x509.subject_key_id().map(|id| id.as_slice().to_vec()),
+        authority_key_id: x509.authority_key_id().map(|id| id.as_slice().to_vec()),
+        ocsp_urls,
+    })
+}
+
+fn name_string(name: &X509NameRef) -> Result<String> {
+    let mut attributes = vec![];
+    for entry in name.entries() {
+        let nid = entry.object().nid();
+        let short = match nid.short_name() {
+            Ok(n) if nid != Nid::UNDEF => n.to_string(),
+            _ => entry.object().to_string(),
+        };
+        attributes.push((short, entry.data().to_string()?));
+    }
+    Ok(format_dn(&attributes))
+}
+
+fn parse_date(s: &str) -> Result<OffsetDateTime> {
+    debug!("Parse date/time: {}", s);
+    let format = format_description!(
+        "[month repr:short] [day padding:space] [hour repr:24]:[minute]:[second] [year repr:full] GMT"
+    );
+    let date = PrimitiveDateTime::parse(s, &format)
+        .map_err(|e| format!("Failed to parse date {}: {}", s, e))?;
+    Ok(date.assume_utc())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use openssl::x509::X509Req;
+
+    #[test]
+    fn test_parse_date() {
+        let x = parse_date("May  3 07:40:15 2019 GMT").unwrap();
+        let format = format_description!("[year]-[month]-[day] [hour]:[minute]:[second]");
+        assert_eq!(x.format(format).unwrap(), "2019-05-03 07:40:15");
+        assert!(parse_date("not a date").is_err());
+    }
+
+    #[test]
+    fn test_csr_verifies() -> Result<()> {
+        for key in [KeyPair::p256()?, KeyPair::p384()?, KeyPair::rsa(2048)?] {
+            let der = key.create_csr(&["example.com", "www.example.com"])?;
+            let req = X509Req::from_der(&der)?;
+            let public_key = req.public_key()?;
+            assert!(req.verify(&public_key)?);
+            let exts = req.extensions()?;
+            assert_eq!(exts.len(), 1);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_es256_signature() -> Result<()> {
+        let key = EcP256Key::generate()?;
+        let sig = key.sign_es256(b"data")?;
+        assert_eq!(sig.len(), 64);
+        let r = BigNum::from_slice(&sig[..32])?;
+        let s = BigNum::from_slice(&sig[32..])?;
+        let sig = EcdsaSig::from_private_components(r, s)?;
+        assert!(sig.verify(&sha256(b"data"), &key.0)?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_jws_signature() -> Result<()> {
+        for (key, size) in [(KeyPair::p256()?, 32), (KeyPair::p384()?, 48)] {
+            let sig = key.sign_jws(b"data")?;
+            assert_eq!(sig.len(), size * 2);
+            let r = BigNum::from_slice(&sig[..size])?;
+            let s = BigNum::from_slice(&sig[size..])?;
+            let sig = EcdsaSig::from_private_components(r, s)?;
+            let digest = if size == 32 {
+                sha256(b"data").to_vec()
+            } else {
+                openssl::sha::sha384(b"data").to_vec()
+            };
+            assert!(sig.verify(&digest, &*key.0.ec_key()?)?);
+
+            let digest = if size == 32 {
+                SignatureDigest::Sha256
+            } else {
+                SignatureDigest::Sha384
+            };
+            let (der, public_key) = (sig.to_der()?, key.public_key_der()?);
+            let verify = |kind| verify_signed(&public_key, kind, digest, b"data", &der);
+            assert!(verify(SignatureKey::Ecdsa)?);
+            assert!(!verify(SignatureKey::Rsa)?);
+        }
+
+        let key = KeyPair::rsa(2048)?;
+        let sig = key.sign_jws(b"data")?;
+        let public_key = key.public_key_der()?;
+        assert!(verify_signed(
+            &public_key,
+            SignatureKey::Rsa,
+            SignatureDigest::Sha256,
+            b"data",
+            &sig
+        )?);
+        // the signature algorithm must be of the type of the key
+        assert!(!verify_signed(
+            &public_key,
+            SignatureKey::Ecdsa,
+            SignatureDigest::Sha256,
+            b"data",
+            &sig
+        )?);
+        assert_eq!(key.jwk()?.alg(), "RS256");
+        Ok(())
+    }
+
+    #[test]
+    fn test_pkcs12() -> Result<()> {
+        let key = KeyPair::from_pem(crate::test::EXAMPLE_KEY.as_bytes())?;
+        let certs: Vec<_> = X509::stack_from_pem(crate::test::EXAMPLE_CHAIN.as_bytes())?
+            .iter()
+            .map(|c| c.to_der())
+            .collect::<std::result::Result<_, _>>()?;
+        let der = create_pkcs12(&key, &certs, "example.com", "secret")?;
+
+        let pkcs12 = Pkcs12::from_der(&der)?;
+        assert!(pkcs12.parse2("wrong").is_err());
+        let parsed = pkcs12.parse2("secret")?;
+        assert!(parsed.pkey.unwrap().public_eq(&key.0));
+        assert_eq!(parsed.cert.unwrap().to_der()?, certs[0]);
+        let ca = parsed.ca.unwrap();
+        assert_eq!(ca.len(), 1);
+        assert_eq!(ca[0].to_der()?, certs[1]);
+        Ok(())
+    }
+}
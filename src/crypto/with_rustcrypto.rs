@@ -0,0 +1,797 @@
+//
+use cms::{
+    content_info::{CmsVersion, ContentInfo},
+    encrypted_data::EncryptedData,
+    enveloped_data::EncryptedContentInfo,
+};
+use hmac::{Hmac, Mac};
+use p256::{
+    ecdsa::signature::{hazmat::PrehashVerifier, Signer},
+    elliptic_curve::sec1::ToEncodedPoint,
+    pkcs8::{DecodePrivateKey, EncodePrivateKey, EncodePublicKey, LineEnding},
+};
+use pkcs12::{
+    cert_type::CertBag,
+    digest_info::DigestInfo,
+    kdf::{derive_key_utf8, Pkcs12KeyType},
+    mac_data::MacData,
+    pfx::Pfx,
+    safe_bag::SafeBag,
+    PKCS_12_CERT_BAG_OID, PKCS_12_PKCS8_KEY_BAG_OID, PKCS_12_X509_CERT_OID,
+};
+use pkcs8::{
+    pkcs5::{pbes2, EncryptionScheme},
+    PrivateKeyInfo, SecretDocument,
+};
+use rand_core::{OsRng, RngCore};
+use rsa::{
+    pkcs1::{DecodeRsaPrivateKey, EncodeRsaPrivateKey},
+    pkcs8::DecodePublicKey,
+    signature::SignatureEncoding,
+    traits::PublicKeyParts,
+    Pkcs1v15Sign, RsaPrivateKey, RsaPublicKey,
+};
+use sha1::Sha1;
+use sha2::{Digest, Sha256, Sha384, Sha512};
+use std::{convert::TryFrom, fmt};
+use time::OffsetDateTime;
+use x509_cert::{
+    attr::{Attribute, Attributes},
+    der::{
+        asn1::{Any, BmpString, ObjectIdentifier, OctetString, SetOfVec},
+        Decode, Encode, Tag, Tagged,
+    },
+    ext::pkix::{
+        name::GeneralName, AuthorityInfoAccessSyntax, AuthorityKeyIdentifier, SubjectAltName,
+        SubjectKeyIdentifier,
+    },
+    name::Name,
+    spki::AlgorithmIdentifierOwned,
+    time::Time,
+};
+
+use super::{
+    crypto_err, csr::SignatureAlgorithm, format_dn, ip_address, serial_hex, signature_algorithm,
+    SignatureDigest, SignatureKey,
+};
+use crate::{
+    cert::{CertificateInfo, KeyAlgorithm},
+    jwt::Jwk,
+    Result,
+};
+
+pub(crate) fn sha1(data: &[u8]) -> [u8; 20] {
+    Sha1::digest(data).into()
+}
+
+pub(crate) fn sha256(data: &[u8]) -> [u8; 32] {
+    Sha256::digest(data).into()
+}
+
+pub(crate) fn random_bytes(buf: &mut [u8]) -> Result<()> {
+    OsRng.try_fill_bytes(buf).map_err(crypto_err)
+}
+
+/// P-256 key used for signing the JWS of the ACME account.
+#[derive(Clone)]
+pub(crate) struct EcP256Key(p256::SecretKey);
+
+impl EcP256Key {
+    pub fn generate() -> Result<Self> {
+        Ok(EcP256Key(p256::SecretKey::random(&mut OsRng)))
+    }
+
+    pub fn from_pem(pem: &[u8]) -> Result<Self> {
+        let pem = std::str::from_utf8(pem).map_err(|e| format!("Failed to read PEM: {}", e))?;
+        let key = p256::SecretKey::from_sec1_pem(pem)
+            .or_else(|_| p256::SecretKey::from_pkcs8_pem(pem))
+            .map_err(|e| format!("Failed to read PEM: {}", e))?;
+        Ok(EcP256Key(key))
+    }
+
+    /// SEC1 PEM (`EC PRIVATE KEY`).
+    pub fn to_pem(&self) -> Result<Vec<u8>> {
+        let pem = self.0.to_sec1_pem(LineEnding::LF).map_err(crypto_err)?;
+        Ok(pem.as_bytes().to_vec())
+    }
+
+    /// The affine coordinates of the public key, 32 bytes each.
+    pub fn public_xy(&self) -> Result<(Vec<u8>, Vec<u8>)> {
+        let point = self.0.public_key().to_encoded_point(false);
+        match (point.x(), point.y()) {
+            (Some(x), Some(y)) => Ok((x.to_vec(), y.to_vec())),
+            _ => Err(crypto_err("Public key is the identity point")),
+        }
+    }
+
+    /// ES256 signature as the 64 bytes `r || s`.
+    pub fn sign_es256(&self, data: &[u8]) -> Result<Vec<u8>> {
+        let signer = p256::ecdsa::SigningKey::from(&self.0);
+        let sig: p256::ecdsa::Signature = signer.try_sign(data).map_err(crypto_err)?;
+        Ok(sig.to_bytes().to_vec())
+    }
+}
+
+impl fmt::Debug for EcP256Key {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "EcP256Key")
+    }
+}
+
+/// Private key of an issued certificate.
+#[derive(Clone)]
+pub(crate) enum KeyPair {
+    P256(p256::SecretKey),
+    P384(p384::SecretKey),
+    Rsa(Box<RsaPrivateKey>),
+}
+
+impl KeyPair {
+    pub fn rsa(bits: u32) -> Result<Self> {
+        let key = RsaPrivateKey::new(&mut OsRng, bits as usize).map_err(crypto_err)?;
+        Ok(KeyPair::Rsa(Box::new(key)))
+    }
+
+    pub fn p256() -> Result<Self> {
+        Ok(KeyPair::P256(p256::SecretKey::random(&mut OsRng)))
+    }
+
+    pub fn p384() -> Result<Self> {
+        Ok(KeyPair::P384(p384::SecretKey::random(&mut OsRng)))
+    }
+
+    pub fn from_pem(pem: &[u8]) -> Result<Self> {
+        let pem = std::str::from_utf8(pem).map_err(crypto_err)?;
+        if let Ok(k) = p256::SecretKey::from_pkcs8_pem(pem) {
+            return Ok(KeyPair::P256(k));
+        }
+        if let Ok(k) = p384::SecretKey::from_pkcs8_pem(pem) {
+            return Ok(KeyPair::P384(k));
+        }
+        if let Ok(k) = RsaPrivateKey::from_pkcs8_pem(pem) {
+            return Ok(KeyPair::Rsa(Box::new(k)));
+        }
+        if let Ok(k) = p256::SecretKey::from_sec1_pem(pem) {
+            return Ok(KeyPair::P256(k));
+        }
+        if let Ok(k) = p384::SecretKey::from_sec1_pem(pem) {
+            return Ok(KeyPair::P384(k));
+        }
+        if let Ok(k) = RsaPrivateKey::from_pkcs1_pem(pem) {
+            return Ok(KeyPair::Rsa(Box::new(k)));
+        }
+        Err(crypto_err("Unsupported or malformed private key PEM"))
+    }
+
+    /// PKCS#8 PEM.
+    pub fn to_pem(&self) -> Result<Vec<u8>> {
+        let pem = match self {
+            KeyPair::P256(k) => k.to_pkcs8_pem(LineEnding::LF),
+            KeyPair::P384(k) => k.to_pkcs8_pem(LineEnding::LF),
+            KeyPair::Rsa(k) => k.to_pkcs8_pem(LineEnding::LF),
+        }
+        .map_err(crypto_err)?;
+        Ok(pem.as_bytes().to_vec())
+    }
+
+    /// PKCS#8 DER.
+    fn to_pkcs8_der(&self) -> Result<SecretDocument> {
+        match self {
+            KeyPair::P256(k) => k.to_pkcs8_der(),
+            KeyPair::P384(k) => k.to_pkcs8_der(),
+            KeyPair::Rsa(k) => k.to_pkcs8_der(),
+        }
+        .map_err(crypto_err)
+    }
+
+    /// DER in the traditional format (PKCS#1 for RSA, SEC1 for EC).
+    pub fn to_der(&self) -> Result<Vec<u8>> {
+        Ok(match self {
+            KeyPair::P256(k) => k.to_sec1_der().map_err(crypto_err)?.to_vec(),
+            KeyPair::P384(k) => k.to_sec1_der().map_err(crypto_err)?.to_vec(),
+            KeyPair::Rsa(k) => k.to_pkcs1_der().map_err(crypto_err)?.as_bytes().to_vec(),
+        })
+    }
+
+    /// DER encoded `SubjectPublicKeyInfo`.
+    pub fn public_key_der(&self) -> Result<Vec<u8>> {
+        let doc = match self {
+            KeyPair::P256(k) => k.public_key().to_public_key_der(),
+            KeyPair::P384(k) => k.public_key().to_public_key_der(),
+            KeyPair::Rsa(k) => k.to_public_key().to_public_key_der(),
+        }
+        .map_err(crypto_err)?;
+        Ok(doc.into_vec())
+    }
+
+    /// The public key as a JWK for the signatures of `sign_jws`.
+    pub fn jwk(&self) -> Result<Jwk> {
+        let (point, p384) = match self {
+            KeyPair::P256(k) => (k.public_key().to_encoded_point(false).to_bytes(), false),
+            KeyPair::P384(k) => (k.public_key().to_encoded_point(false).to_bytes(), true),
+            KeyPair::Rsa(k) => return Ok(Jwk::rsa(&k.n().to_bytes_be(), &k.e().to_bytes_be())),
+        };
+        // uncompressed point, 0x04 || x || y
+        let (x, y) = point[1..].split_at((point.len() - 1) / 2);
+        Ok(if p384 {
+            Jwk::p384(x, y)
+        } else {
+            Jwk::p256(x, y)
+        })
+    }
+
+    /// JWS signature, `r || s` for ES256 and ES384 or PKCS#1 v1.5 for RS256.
+    pub fn sign_jws(&self, data: &[u8]) -> Result<Vec<u8>> {
+        Ok(match self {
+            KeyPair::P256(k) => {
+                let signer = p256::ecdsa::SigningKey::from(k);
+                let sig: p256::ecdsa::Signature = signer.try_sign(data).map_err(crypto_err)?;
+                sig.to_bytes().to_vec()
+            }
+            KeyPair::P384(k) => {
+                let signer = p384::ecdsa::SigningKey::from(k);
+                let sig: p384::ecdsa::Signature = signer.try_sign(data).map_err(crypto_err)?;
+                sig.to_bytes().to_vec()
+            }
+            KeyPair::Rsa(k) => {
+                let signer = rsa::pkcs1v15::SigningKey::<Sha256>::new((**k).clone());
+                signer.try_sign(data).map_err(crypto_err)?.to_vec()
+            }
+        })
+    }
+
+    pub fn sign_csr(&self, data: &[u8]) -> Result<(SignatureAlgorithm, Vec<u8>)> {
+        Ok(match self {
+            KeyPair::P256(k) => {
+                let signer = p256::ecdsa::SigningKey::from(k);
+                let sig: p256::ecdsa::Signature = signer.try_sign(data).map_err(crypto_err)?;
+                (
+                    SignatureAlgorithm::EcdsaSha256,
+                    sig.to_der().as_bytes().to_vec(),
+                )
+            }
+            KeyPair::P384(k) => {
+                let signer = p384::ecdsa::SigningKey::from(k);
+                let sig: p384::ecdsa::Signature = signer.try_sign(data).map_err(crypto_err)?;
+                (
+                    SignatureAlgorithm::EcdsaSha384,
+                    sig.to_der().as_bytes().to_vec(),
+                )
+            }
+            KeyPair::Rsa(k) => {
+                let signer = rsa::pkcs1v15::SigningKey::<Sha256>::new((**k).clone());
+                let sig = signer.try_sign(data).map_err(crypto_err)?;
+                (SignatureAlgorithm::RsaSha256, sig.to_vec())
+            }
+        })
+    }
+}
+
+fn first_certificate(pem: &[u8]) -> Result<x509_cert::Certificate> {
+    x509_cert::Certificate::load_pem_chain(pem)
+        .map_err(crypto_err)?
+        .into_iter()
+        .next()
+        .ok_or_else(|| crypto_err("No certificate in PEM"))
+}
+
+/// DER of the first certificate in the PEM.
+pub(crate) fn certificate_der(pem: &[u8]) -> Result<Vec<u8>> {
+    first_certificate(pem)?.to_der().map_err(crypto_err)
+}
+
+/// The end of the validity of the first certificate in the PEM.
+pub(crate) fn certificate_not_after(pem: &[u8]) -> Result<OffsetDateTime> {
+    let cert = first_certificate(pem)?;
+    to_date_time(cert.tbs_certificate.validity.not_after)
+}
+
+fn to_date_time(time: Time) -> Result<OffsetDateTime> {
+    let secs = i64::try_from(time.to_unix_duration().as_secs()).map_err(crypto_err)?;
+    OffsetDateTime::from_unix_timestamp(secs).map_err(crypto_err)
+}
+
+const OID_RSA: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.113549.1.1.1");
+const OID_EC: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.10045.2.1");
+const OID_ED25519: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.3.101.112");
+
+/// Whether the certificate is signed by the key of the issuer, both as DER.
+///
+/// Supports ECDSA with P-256 or P-384 keys and RSA PKCS#1 v1.5, with SHA-256, SHA-384
+/// or SHA-512, which is what the public ACME CAs use.
+pub(crate) fn verify_signature(der: &[u8], issuer_der: &[u8]) -> Result<bool> {
+    let cert = x509_cert::Certificate::from_der(der).map_err(crypto_err)?;
+    let issuer = x509_cert::Certificate::from_der(issuer_der).map_err(crypto_err)?;
+    let tbs = cert.tbs_certificate.to_der().map_err(crypto_err)?;
+    let spki = issuer
+        .tbs_certificate
+        .subject_public_key_info
+        .to_der()
+        .map_err(crypto_err)?;
+    let Some(signature) = cert.signature.as_bytes() else {
+        return Ok(false);
+    };
+
+    let algorithm = cert.signature_algorithm.oid;
+    let Some((kind, digest)) = signature_algorithm(algorithm.as_bytes()) else {
+        return Err(crate::Error::Crypto(format!(
+            "Unsupported signature algorithm: {}",
+            algorithm
+        )));
+    };
+    verify_signed(&spki, kind, digest, &tbs, signature)
+}
+
+/// Whether the signature of the data is made by the key of the DER encoded
+/// `SubjectPublicKeyInfo`, with the type of key and the digest. The key is RSA, P-256
+/// or P-384.
+pub(crate) fn verify_signed(
+    public_key_der: &[u8],
+    kind: SignatureKey,
+    digest: SignatureDigest,
+    data: &[u8],
+    signature: &[u8],
+) -> Result<bool> {
+    let hashed = match digest {
+        SignatureDigest::Sha256 => Sha256::digest(data).to_vec(),
+        SignatureDigest::Sha384 => Sha384::digest(data).to_vec(),
+        SignatureDigest::Sha512 => Sha512::digest(data).to_vec(),
+    };
+
+    // a signature of another type than the key is never accepted
+    let valid = if let Ok(key) = RsaPublicKey::from_public_key_der(public_key_der) {
+        let scheme = match digest {
+            SignatureDigest::Sha256 => Pkcs1v15Sign::new::<Sha256>(),
+            SignatureDigest::Sha384 => Pkcs1v15Sign::new::<Sha384>(),
+            SignatureDigest::Sha512 => Pkcs1v15Sign::new::<Sha512>(),
+        };
+        kind == SignatureKey::Rsa && key.verify(scheme, &hashed, signature).is_ok()
+    } else if let Ok(key) = p256::ecdsa::VerifyingKey::from_public_key_der(public_key_der) {
+        kind == SignatureKey::Ecdsa
+            && p256::ecdsa::Signature::from_der(signature)
+                .and_then(|sig| key.verify_prehash(&hashed, &sig))
+                .is_ok()
+    } else if let Ok(key) = p384::ecdsa::VerifyingKey::from_public_key_der(public_key_der) {
+        kind == SignatureKey::Ecdsa
+            && p384::ecdsa::Signature::from_der(signature)
+                .and_then(|sig| key.verify_prehash(&hashed, &sig))
+                .is_ok()
+    } else {
+        return Err("Unsupported key for signature".into());
+    };
+    Ok(valid)
+}
+
+const OID_DATA: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.113549.1.7.1");
+const OID_ENCRYPTED_DATA: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.113549.1.7.6");
+const OID_FRIENDLY_NAME: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.113549.1.9.20");
+const OID_LOCAL_KEY_ID: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.113549.1.9.21");
+const OID_OCSP: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.3.6.1.5.5.7.48.1");
+const OID_SHA256: ObjectIdentifier = ObjectIdentifier::new_unwrap("2.16.840.1.101.3.4.2.1");
+
+/// Iterations of the key derivations in a PKCS#12, the same as openssl uses.
+const PKCS12_ITERATIONS: u32 = 2048;
+
+/// A password protected PKCS#12 of the key and the certificates as DER, the issued
+/// certificate first. Encrypted with AES-256-CBC and a SHA-256 MAC.
+pub(crate) fn create_pkcs12(
+    key: &KeyPair,
+    certs: &[Vec<u8>],
+    friendly_name: &str,
+    password: &str,
+) -> Result<Vec<u8>> {
+    let leaf = certs.first().ok_or("No certificate for PKCS#12")?;
+    // ties the key to the issued certificate
+    let attributes = bag_attributes(friendly_name, &sha256(leaf))?;
+
+    let mut cert_bags = vec![];
+    for (i, der) in certs.iter().enumerate() {
+        let bag = CertBag {
+            cert_id: PKCS_12_X509_CERT_OID,
+            cert_value: OctetString::new(der.clone()).map_err(crypto_err)?,
+        };
+        cert_bags.push(SafeBag {
+            bag_id: PKCS_12_CERT_BAG_OID,
+            bag_value: bag.to_der().map_err(crypto_err)?,
+            bag_attributes: if i == 0 {
+                Some(attributes.clone())
+            } else {
+                None
+            },
+        });
+    }
+    let (salt, iv) = (random_array::<16>()?, random_array::<16>()?);
+    let params = pbes2::Parameters::pbkdf2_sha256_aes256cbc(PKCS12_ITERATIONS, &salt, &iv)
+        .map_err(crypto_err)?;
+    let encrypted = params
+        .encrypt(password, &cert_bags.to_der().map_err(crypto_err)?)
+        .map_err(crypto_err)?;
+    let encrypted_data = EncryptedData {
+        version: CmsVersion::V0,
+        enc_content_info: EncryptedContentInfo {
+            content_type: OID_DATA,
+            content_enc_alg: AlgorithmIdentifierOwned::from_der(
+                &EncryptionScheme::from(params)
+                    .to_der()
+                    .map_err(crypto_err)?,
+            )
+            .map_err(crypto_err)?,
+            encrypted_content: Some(OctetString::new(encrypted).map_err(crypto_err)?),
+        },
+        unprotected_attrs: None,
+    };
+
+    let (salt, iv) = (random_array::<16>()?, random_array::<16>()?);
+    let params = pbes2::Parameters::pbkdf2_sha256_aes256cbc(PKCS12_ITERATIONS, &salt, &iv)
+        .map_err(crypto_err)?;
+    let pkcs8 = key.to_pkcs8_der()?;
+    let shrouded = PrivateKeyInfo::try_from(pkcs8.as_bytes())
+        .and_then(|info| info.encrypt_with_params(params, password))
+        .map_err(crypto_err)?;
+    let key_bags = vec![SafeBag {
+        bag_id: PKCS_12_PKCS8_KEY_BAG_OID,
+        bag_value: shrouded.as_bytes().to_vec(),
+        bag_attributes: Some(attributes),
+    }];
+
+    let auth_safe = vec![
+        ContentInfo {
+            content_type: OID_ENCRYPTED_DATA,
+            content: Any::encode_from(&encrypted_data).map_err(crypto_err)?,
+        },
+        data_content(key_bags.to_der().map_err(crypto_err)?)?,
+    ]
+    .to_der()
+    .map_err(crypto_err)?;
+
+    let mac_salt = random_array::<16>()?;
+    let mac_key = derive_key_utf8::<Sha256>(
+        password,
+        &mac_salt,
+        Pkcs12KeyType::Mac,
+        PKCS12_ITERATIONS as i32,
+        32,
+    )
+    .map_err(crypto_err)?;
+    let mut mac = Hmac::<Sha256>::new_from_slice(&mac_key).map_err(crypto_err)?;
+    mac.update(&auth_safe);
+    let mac_data = MacData {
+        mac: DigestInfo {
+            algorithm: AlgorithmIdentifierOwned {
+                oid: OID_SHA256,
+                parameters: Some(Any::null()),
+            },
+            digest: OctetString::new(mac.finalize().into_bytes().to_vec()).map_err(crypto_err)?,
+        },
+        mac_salt: OctetString::new(mac_salt.to_vec()).map_err(crypto_err)?,
+        iterations: PKCS12_ITERATIONS as i32,
+    };
+
+    let pfx = Pfx {
+        version: pkcs12::pfx::Version::V3,
+        auth_safe: data_content(auth_safe)?,
+        mac_data: Some(mac_data),
+    };
+    pfx.to_der().map_err(crypto_err)
+}
+
+fn random_array<const N: usize>() -> Result<[u8; N]> {
+    let mut buf = [0; N];
+    random_bytes(&mut buf)?;
+    Ok(buf)
+}
+
+fn data_content(der: Vec<u8>) -> Result<ContentInfo> {
+    let content = Any::encode_from(&OctetString::new(der).map_err(crypto_err)?);
+    Ok(ContentInfo {
+        content_type: OID_DATA,
+        content: content.map_err(crypto_err)?,
+    })
+}
+
+/// The friendly name and local key id attributes of a bag.
+fn bag_attributes(friendly_name: &str, local_key_id: &[u8]) -> Result<Attributes> {
+    let attribute = |oid, value: Any| -> Result<Attribute> {
+        Ok(Attribute {
+            oid,
+            values: SetOfVec::try_from(vec![value]).map_err(crypto_err)?,
+        })
+    };
+    let name = BmpString::from_utf8(friendly_name).map_err(crypto_err)?;
+    let id = OctetString::new(local_key_id.to_vec()).map_err(crypto_err)?;
+    let attributes = vec![
+        attribute(
+            OID_FRIENDLY_NAME,
+            Any::encode_from(&name).map_err(crypto_err)?,
+        )?,
+        attribute(OID_LOCAL_KEY_ID, Any::encode_from(&id).map_err(crypto_err)?)?,
+    ];
+    SetOfVec::try_from(attributes).map_err(crypto_err)
+}
+
+/// Parse the DER of a certificate.
+pub(crate) fn certificate_info(der: &[u8]) -> Result<CertificateInfo> {
+    let cert = x509_cert::Certificate::from_der(der).map_err(crypto_err)?;
+    let tbs = &cert.tbs_certificate;
+
+    let mut dns_names = vec![];
+    let mut ip_addresses = vec![];
+    if let Some((_, san)) = tbs.get::<SubjectAltName>().map_err(crypto_err)? {
+        for name in san.0 {
+            match name {
+                GeneralName::DnsName(dns) => dns_names.push(dns.to_string()),
+                GeneralName::IpAddress(ip) => ip_addresses.extend(ip_address(ip.as_bytes())),
+                _ => {}
+            }
+        }
+    }
+
+    let spki = &tbs.subject_public_key_info;
+    let (key_algorithm, key_bits) = match spki.algorithm.oid {
+        OID_RSA => {
+            let spki_der = spki.to_der().map_err(crypto_err)?;
+            let key = RsaPublicKey::from_public_key_der(&spki_der).map_err(crypto_err)?;
+            (KeyAlgorithm::Rsa, key.n().bits() as u32)
+        }
+        OID_EC => {
+            let curve = spki
+                .algorithm
+                .parameters
+                .as_ref()
+                .and_then(|p| p.decode_as::<ObjectIdentifier>().ok())
+                .map(|oid| oid.to_string());
+            let bits = match curve.as_deref() {
+                Some("1.2.840.10045.3.1.7") => 256,
+                Some("1.3.132.0.34") => 384,
+                Some("1.3.132.0.35") => 521,
+                _ => 0,
+            };
+            (KeyAlgorithm::Ec, bits)
+        }
+        OID_ED25519 => (KeyAlgorithm::Ed25519, 256),
+        _ => (KeyAlgorithm::Other, 0),
+    };
+
+    let subject_key_id = tbs
+        .get::<SubjectKeyIdentifier>()
+        .map_err(crypto_err)?
+        .map(|(_, id)| id.0.as_bytes().to_vec());
+    let authority_key_id = tbs
+        .get::<AuthorityKeyIdentifier>()
+        .map_err(crypto_err)?
+        .and_then(|(_, id)| id.key_identifier)
+        .map(|id| id.as_bytes().to_vec());
+
+    let mut ocsp_urls = vec![];
+    if let Some((_, aia)) = tbs.get::<AuthorityInfoAccessSyntax>().map_err(crypto_err)? {
+        for access in aia.0 {
+            if let (OID_OCSP, GeneralName::UniformResourceIdentifier(uri)) =
+                (access.access_method, access.access_location)
+            {
+                ocsp_urls.push(uri.to_string());
+            }
+        }
+    }
+
+    Ok(CertificateInfo {
+        der: der.to_vec(),
+        public_key_der: spki.to_der().map_err(crypto_err)?,
+        serial_number: serial_hex(tbs.serial_number.as_bytes()),
+        subject: name_string(&tbs.subject),
+        issuer: name_string(&tbs.issuer),
+        not_before: to_date_time(tbs.validity.not_before)?,
+        not_after: to_date_time(tbs.validity.not_after)?,
+        dns_names,
+        ip_addresses,
+        key_algorithm,
+        key_bits,
+        subject_key_id,
+        authority_key_id,
+        ocsp_urls,
+    })
+}
+
+fn name_string(name: &Name) -> String {
+    let mut attributes = vec![];
+    for atv in name.0.iter().flat_map(|rdn| rdn.0.iter()) {
+        // the short names openssl uses
+        let oid = atv.oid.to_string();
+        let short = match oid.as_str() {
+            "2.5.4.3" => "CN",
+            "2.5.4.4" => "SN",
+            "2.5.4.5" => "serialNumber",
+            "2.5.4.6" => "C",
+            "2.5.4.7" => "L",
+            "2.5.4.8" => "ST",
+            "2.5.4.9" => "street",
+            "2.5.4.10" => "O",
+            "2.5.4.11" => "OU",
+            "2.5.4.12" => "title",
+            "2.5.4.42" => "GN",
+            "1.2.840.113549.1.9.1" => "emailAddress",
+            "0.9.2342.19200300.100.1.1" => "UID",
+            "0.9.2342.19200300.100.1.25" => "DC",
+            _ => &oid,
+        };
+        let bytes = atv.value.value();
+        let value = match atv.value.tag() {
+            Tag::BmpString => {
+                let units: Vec<u16> = bytes
+                    .chunks(2)
+                    .map(|c| u16::from_be_bytes([c[0], *c.get(1).unwrap_or(&0)]))
+                    .collect();
+                String::from_utf16_lossy(&units)
+            }
+            _ => String::from_utf8_lossy(bytes).to_string(),
+        };
+        attributes.push((short.to_string(), value));
+    }
+    format_dn(&attributes)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use p256::ecdsa::signature::Verifier;
+    use x509_cert::{der::Decode, request::CertReq};
+
+    #[test]
+    fn test_csr_verifies() -> Result<()> {
+        let key = KeyPair::p256()?;
+        let der = key.create_csr(&["example.com", "www.example.com"])?;
+        let req = CertReq::from_der(&der).map_err(crypto_err)?;
+        let info = req.info.to_der().map_err(crypto_err)?;
+        let sig =
+            p256::ecdsa::DerSignature::try_from(req.signature.raw_bytes()).map_err(crypto_err)?;
+        let KeyPair::P256(k) = &key else {
+            unreachable!()
+        };
+        let verifier = p256::ecdsa::VerifyingKey::from(k.public_key());
+        assert!(verifier.verify(&info, &sig).is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn test_pem_roundtrip() -> Result<()> {
+        let key = KeyPair::p384()?;
+        let again = KeyPair::from_pem(&key.to_pem()?)?;
+        assert_eq!(key.public_key_der()?, again.public_key_der()?);
+
+        let acc = EcP256Key::generate()?;
+        let again = EcP256Key::from_pem(&acc.to_pem()?)?;
+        assert_eq!(acc.public_xy()?, again.public_xy()?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_jws_signature() -> Result<()> {
+        let key = KeyPair::p256()?;
+        let sig =
+            p256::ecdsa::Signature::from_slice(&key.sign_jws(b"data")?).map_err(crypto_err)?;
+        let KeyPair::P256(k) = &key else {
+            unreachable!()
+        };
+        let verifier = p256::ecdsa::VerifyingKey::from(k.public_key());
+        assert!(verifier.verify(b"data", &sig).is_ok());
+        assert_eq!(key.jwk()?.alg(), "ES256");
+        let (der, public_key) = (sig.to_der(), key.public_key_der()?);
+        let verify = |kind| {
+            verify_signed(
+                &public_key,
+                kind,
+                SignatureDigest::Sha256,
+                b"data",
+                der.as_bytes(),
+            )
+        };
+        assert!(verify(SignatureKey::Ecdsa)?);
+        assert!(!verify(SignatureKey::Rsa)?);
+
+        let key = KeyPair::p384()?;
+        let sig =
+            p384::ecdsa::Signature::from_slice(&key.sign_jws(b"data")?).map_err(crypto_err)?;
+        let KeyPair::P384(k) = &key else {
+            unreachable!()
+        };
+        let verifier = p384::ecdsa::VerifyingKey::from(k.public_key());
+        assert!(verifier.verify(b"data", &sig).is_ok());
+        assert_eq!(key.jwk()?.alg(), "ES384");
+
+        let key = KeyPair::rsa(2048)?;
+        let sig = key.sign_jws(b"data")?;
+        let public_key = key.public_key_der()?;
+        assert!(verify_signed(
+            &public_key,
+            SignatureKey::Rsa,
+            SignatureDigest::Sha256,
+            b"data",
+            &sig
+        )?);
+        // the signature algorithm must be of the type of the key
+        assert!(!verify_signed(
+            &public_key,
+            SignatureKey::Ecdsa,
+            SignatureDigest::Sha256,
+            b"data",
+            &sig
+        )?);
+        assert_eq!(key.jwk()?.alg(), "RS256");
+        Ok(())
+    }
+
+    #[test]
+    fn test_pkcs12() -> Result<()> {
+        use pkcs8::EncryptedPrivateKeyInfo;
+
+        let key = KeyPair::from_pem(crate::test::EXAMPLE_KEY.as_bytes())?;
+        let certs: Vec<_> =
+            x509_cert::Certificate::load_pem_chain(crate::test::EXAMPLE_CHAIN.as_bytes())
+                .map_err(crypto_err)?
+                .iter()
+                .map(|c| c.to_der())
+                .collect::<std::result::Result<_, _>>()
+                .map_err(crypto_err)?;
+        let der = create_pkcs12(&key, &certs, "example.com", "secret")?;
+
+        let pfx = Pfx::from_der(&der).map_err(crypto_err)?;
+        let data = |info: &ContentInfo| -> Result<Vec<u8>> {
+            assert_eq!(info.content_type, OID_DATA);
+            let octets = info
+                .content
+                .decode_as::<OctetString>()
+                .map_err(crypto_err)?;
+            Ok(octets.into_bytes())
+        };
+        let auth_safe = data(&pfx.auth_safe)?;
+
+        let mac_data = pfx.mac_data.unwrap();
+        let mac_key = derive_key_utf8::<Sha256>(
+            "secret",
+            mac_data.mac_salt.as_bytes(),
+            Pkcs12KeyType::Mac,
+            mac_data.iterations,
+            32,
+        )
+        .map_err(crypto_err)?;
+        let mut mac = Hmac::<Sha256>::new_from_slice(&mac_key).map_err(crypto_err)?;
+        mac.update(&auth_safe);
+        assert!(mac.verify_slice(mac_data.mac.digest.as_bytes()).is_ok());
+
+        // decoding keeps the explicit tag around the value of a bag
+        let bag_value = |bag: &SafeBag| -> Result<Vec<u8>> {
+            let any = x509_cert::der::AnyRef::from_der(&bag.bag_value).map_err(crypto_err)?;
+            Ok(any.value().to_vec())
+        };
+
+        let infos = Vec::<ContentInfo>::from_der(&auth_safe).map_err(crypto_err)?;
+        assert_eq!(infos.len(), 2);
+
+        assert_eq!(infos[0].content_type, OID_ENCRYPTED_DATA);
+        let encrypted: EncryptedData = infos[0].content.decode_as().map_err(crypto_err)?;
+        let info = encrypted.enc_content_info;
+        let algorithm = info.content_enc_alg.to_der().map_err(crypto_err)?;
+        let scheme = EncryptionScheme::from_der(&algorithm).map_err(crypto_err)?;
+        let ciphertext = info.encrypted_content.unwrap();
+        assert!(scheme.decrypt("wrong", ciphertext.as_bytes()).is_err());
+        let plain = scheme
+            .decrypt("secret", ciphertext.as_bytes())
+            .map_err(crypto_err)?;
+        let cert_bags = Vec::<SafeBag>::from_der(&plain).map_err(crypto_err)?;
+        assert_eq!(cert_bags.len(), 2);
+        for (bag, cert) in cert_bags.iter().zip(&certs) {
+            let cert_bag = CertBag::from_der(&bag_value(bag)?).map_err(crypto_err)?;
+            assert_eq!(cert_bag.cert_value.as_bytes(), &cert[..]);
+        }
+        assert!(cert_bags[0].bag_attributes.is_some());
+        assert!(cert_bags[1].bag_attributes.is_none());
+
+        let key_bags = Vec::<SafeBag>::from_der(&data(&infos[1])?).map_err(crypto_err)?;
+        assert_eq!(key_bags[0].bag_id, PKCS_12_PKCS8_KEY_BAG_OID);
+        assert_eq!(key_bags[0].bag_attributes, cert_bags[0].bag_attributes);
+        let shrouded_der = bag_value(&key_bags[0])?;
+        let shrouded = EncryptedPrivateKeyInfo::try_from(&shrouded_der[..]).map_err(crypto_err)?;
+        let decrypted = shrouded.decrypt("secret").map_err(crypto_err)?;
+        assert_eq!(decrypted.as_bytes(), key.to_pkcs8_der()?.as_bytes());
+        Ok(())
+    }
+}
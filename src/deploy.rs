@@ -0,0 +1,542 @@
+//! Hooks run when a new certificate is saved, see [`DeployHooks`].
+//!
+//! [`DeployHooks`]: struct.DeployHooks.html
+use std::{
+    ffi::{OsStr, OsString},
+    fmt,
+    io::Read,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    sync::{mpsc, Arc},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{export::CertbotExport, Certificate, Error, Result};
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+
+type Callback = Arc<dyn Fn(&Deployment) -> Result<()> + Send + Sync>;
+
+#[derive(Clone)]
+enum Action {
+    Callback(Callback),
+    Command(OsString, Vec<OsString>),
+}
+
+#[derive(Clone)]
+struct Hook {
+    name: String,
+    action: Action,
+}
+
+/// What is done with a certificate once it's saved, i.e. reloading the web server or
+/// pushing it to a load balancer.
+///
+/// The hooks are callbacks and external commands, run one after the other in the order
+/// they were added. A failing hook doesn't stop the others. A command that runs longer
+/// than the [`timeout`] is killed and fails.
+///
+/// Commands get the names of the certificate in environment variables:
+///
+/// * `ACME_PRIMARY_NAME`, the primary name.
+/// * `ACME_NAMES`, the DNS names of the certificate separated by spaces.
+///
+/// Paths to the files of the certificate need an [`export`]. It's done before the hooks
+/// run, and they are skipped if it fails as they would deploy old files. The commands
+/// then also get:
+///
+/// * `ACME_LIVE_DIR`, the `live/` directory of the certificate.
+/// * `ACME_CERT_PATH`, `ACME_CHAIN_PATH` and `ACME_FULLCHAIN_PATH`, the PEM files.
+/// * `ACME_KEY_PATH`, the private key, unless it's held externally.
+/// * `RENEWED_LINEAGE` and `RENEWED_DOMAINS`, as certbot sets them for its deploy
+///   hooks.
+///
+/// ```no_run
+/// use acme_lib::deploy::DeployHooks;
+/// use acme_lib::export::CertbotExport;
+///
+/// let hooks = DeployHooks::new()
+///     .export(CertbotExport::new("/etc/letsencrypt"))
+///     .command("systemctl", ["reload", "nginx"])
+///     .callback("log", |d| {
+///         println!("Deployed {} to {:?}", d.primary_name(), d.live_dir());
+///         Ok(())
+///     });
+/// ```
+///
+/// [`export`]: struct.DeployHooks.html#method.export
+/// [`timeout`]: struct.DeployHooks.html#method.timeout
+#[derive(Clone)]
+pub struct DeployHooks {
+    export: Option<CertbotExport>,
+    hooks: Vec<Hook>,
+    timeout: Duration,
+}
+
+impl Default for DeployHooks {
+    fn default() -> Self {
+        DeployHooks {
+            export: None,
+            hooks: vec![],
+            timeout: DEFAULT_TIMEOUT,
+        }
+    }
+}
+
+impl DeployHooks {
+    /// No hooks.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Export the certificate in the certbot layout before running the hooks, named by
+    /// the primary name.
+    pub fn export(mut self, export: CertbotExport) -> Self {
+        self.export = Some(export);
+        self
+    }
+
+    /// How long each command may run before it's killed. Defaults to 5 minutes,
+    /// `Duration::MAX` means no limit.
+    ///
+    /// Callbacks can't be interrupted and aren't limited.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Call a function. The name identifies the hook when it fails.
+    pub fn callback<F>(mut self, name: &str, f: F) -> Self
+    where
+        F: Fn(&Deployment) -> Result<()> + Send + Sync + 'static,
+    {
+        self.hooks.push(Hook {
+            name: name.to_string(),
+            action: Action::Callback(Arc::new(f)),
+        });
+        self
+    }
+
+    /// Run a program with the arguments, without a shell. The hook fails if the program
+    /// exits with a non-zero status.
+    pub fn command<S, I>(mut self, program: S, args: I) -> Self
+    where
+        S: AsRef<OsStr>,
+        I: IntoIterator,
+        I::Item: AsRef<OsStr>,
+    {
+        let program = program.as_ref().to_os_string();
+        let args: Vec<OsString> = args.into_iter().map(|a| a.as_ref().into()).collect();
+        let name = std::iter::once(&program)
+            .chain(&args)
+            .map(|a| a.to_string_lossy())
+            .collect::<Vec<_>>()
+            .join(" ");
+        self.hooks.push(Hook {
+            name,
+            action: Action::Command(program, args),
+        });
+        self
+    }
+
+    /// Export and run the hooks for the certificate, whether it changed or not, i.e.
+    /// after [`Account::activate_certificate`] or to retry failed hooks. Returns the
+    /// failures.
+    ///
+    /// [`Account::activate_certificate`]: ../struct.Account.html#method.activate_certificate
+    pub fn deploy(&self, primary_name: &str, certificate: &Certificate) -> Vec<HookFailure> {
+        let live_dir = match &self.export {
+            Some(export) => match export.export(primary_name, certificate) {
+                Ok(_) => Some(export.live_dir(primary_name)),
+                Err(error) => {
+                    info!("Exporting {} failed, no hooks run: {}", primary_name, error);
+                    return vec![HookFailure {
+                        hook: "export".into(),
+                        error,
+                    }];
+                }
+            },
+            None => None,
+        };
+        let names = certificate
+            .info()
+            .map(|i| i.dns_names().to_vec())
+            .unwrap_or_else(|_| vec![primary_name.to_string()]);
+        let deployment = Deployment {
+            primary_name,
+            names,
+            certificate,
+            live_dir,
+        };
+
+        let mut failures = vec![];
+        for hook in &self.hooks {
+            debug!("Run deploy hook {}: {}", hook.name, primary_name);
+            let result = match &hook.action {
+                Action::Callback(f) => f(&deployment),
+                Action::Command(program, args) => {
+                    run_command(program, args, &deployment, self.timeout)
+                }
+            };
+            if let Err(error) = result {
+                info!(
+                    "Deploy hook {} failed for {}: {}",
+                    hook.name, primary_name, error
+                );
+                failures.push(HookFailure {
+                    hook: hook.name.clone(),
+                    error,
+                });
+            }
+        }
+        failures
+    }
+
+    /// Deploy a newly saved certificate if it differs from the one it replaced.
+    pub(crate) fn deploy_if_changed(
+        &self,
+        primary_name: &str,
+        certificate: Certificate,
+        changed: bool,
+    ) -> DeployReport {
+        let failures = if changed {
+            self.deploy(primary_name, &certificate)
+        } else {
+            debug!("Certificate unchanged, no deploy: {}", primary_name);
+            vec![]
+        };
+        DeployReport {
+            certificate,
+            changed,
+            failures,
+        }
+    }
+}
+
+impl fmt::Debug for DeployHooks {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let hooks: Vec<_> = self.hooks.iter().map(|h| &h.name).collect();
+        f.debug_struct("DeployHooks")
+            .field("export", &self.export)
+            .field("hooks", &hooks)
+            .field("timeout", &self.timeout)
+            .finish()
+    }
+}
+
+fn run_command(
+    program: &OsStr,
+    args: &[OsString],
+    deployment: &Deployment,
+    timeout: Duration,
+) -> Result<()> {
+    let mut child = Command::new(program)
+        .args(args)
+        .envs(deployment.env())
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    // Read the pipes while waiting, so a chatty command doesn't block on a full pipe.
+    let stdout = read_pipe(child.stdout.take());
+    let stderr = read_pipe(child.stderr.take());
+
+    // none if it's too far off to be a limit
+    let deadline = Instant::now().checked_add(timeout);
+    let status = loop {
+        let Some(deadline) = deadline else {
+            break child.wait()?;
+        };
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        let now = Instant::now();
+        if now >= deadline {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(format!("Timed out after {:?}", timeout).into());
+        }
+        thread::sleep((deadline - now).min(Duration::from_millis(50)));
+    };
+
+    // A process the command left running may hold the pipes open, so the output is
+    // only awaited until the deadline.
+    let output = |rx: mpsc::Receiver<Vec<u8>>| match deadline {
+        Some(deadline) => rx
+            .recv_timeout(deadline.saturating_duration_since(Instant::now()))
+            .unwrap_or_default(),
+        None => rx.recv().unwrap_or_default(),
+    };
+    let stdout = output(stdout);
+    let stderr = output(stderr);
+    if !stdout.is_empty() {
+        debug!("{}", String::from_utf8_lossy(&stdout).trim_end());
+    }
+    if status.success() {
+        Ok(())
+    } else {
+        let stderr = String::from_utf8_lossy(&stderr);
+        Err(format!("{}: {}", status, stderr.trim()).into())
+    }
+}
+
+/// Read a pipe of a child process to the end in a thread.
+fn read_pipe<R: Read + Send + 'static>(pipe: Option<R>) -> mpsc::Receiver<Vec<u8>> {
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let mut buf = vec![];
+        if let Some(mut pipe) = pipe {
+            let _ = pipe.read_to_end(&mut buf);
+        }
+        let _ = tx.send(buf);
+    });
+    rx
+}
+
+/// The certificate the hooks deploy.
+#[derive(Debug)]
+pub struct Deployment<'a> {
+    primary_name: &'a str,
+    names: Vec<String>,
+    certificate: &'a Certificate,
+    live_dir: Option<PathBuf>,
+}
+
+impl<'a> Deployment<'a> {
+    /// The primary name of the certificate.
+    pub fn primary_name(&self) -> &str {
+        self.primary_name
+    }
+
+    /// The DNS names of the certificate.
+    pub fn names(&self) -> &[String] {
+        &self.names
+    }
+
+    /// The certificate.
+    pub fn certificate(&self) -> &Certificate {
+        self.certificate
+    }
+
+    /// The `live/` directory the certificate was exported to, if the hooks export.
+    pub fn live_dir(&self) -> Option<&Path> {
+        self.live_dir.as_deref()
+    }
+
+    /// The environment variables of the commands.
+    fn env(&self) -> Vec<(&'static str, OsString)> {
+        let names = self.names.join(" ");
+        let mut env = vec![
+            ("ACME_PRIMARY_NAME", self.primary_name.into()),
+            ("ACME_NAMES", names.clone().into()),
+        ];
+        if let Some(live) = &self.live_dir {
+            env.push(("ACME_LIVE_DIR", live.into()));
+            env.push(("ACME_CERT_PATH", live.join("cert.pem").into()));
+            env.push(("ACME_CHAIN_PATH", live.join("chain.pem").into()));
+            env.push(("ACME_FULLCHAIN_PATH", live.join("fullchain.pem").into()));
+            if self.certificate.private_key().is_some() {
+                env.push(("ACME_KEY_PATH", live.join("privkey.pem").into()));
+            }
+            env.push(("RENEWED_LINEAGE", live.into()));
+            env.push(("RENEWED_DOMAINS", names.into()));
+        }
+        env
+    }
+}
+
+/// A hook that failed.
+#[derive(Debug)]
+pub struct HookFailure {
+    hook: String,
+    error: Error,
+}
+
+impl HookFailure {
+    /// The name of the callback, the command line or `export`.
+    pub fn hook(&self) -> &str {
+        &self.hook
+    }
+
+    /// Why it failed.
+    pub fn error(&self) -> &Error {
+        &self.error
+    }
+}
+
+/// A certificate that was saved and the hooks run for it.
+///
+/// The certificate is in the persistence even when hooks failed.
+#[derive(Debug)]
+pub struct DeployReport {
+    certificate: Certificate,
+    changed: bool,
+    failures: Vec<HookFailure>,
+}
+
+impl DeployReport {
+    /// The saved certificate.
+    pub fn certificate(&self) -> &Certificate {
+        &self.certificate
+    }
+
+    /// Turn the report into the saved certificate.
+    pub fn into_certificate(self) -> Certificate {
+        self.certificate
+    }
+
+    /// Whether the certificate differs from the one it replaced. The hooks only run
+    /// when it does.
+    pub fn is_changed(&self) -> bool {
+        self.changed
+    }
+
+    /// The hooks that failed.
+    pub fn failures(&self) -> &[HookFailure] {
+        &self.failures
+    }
+
+    /// Whether all hooks that ran succeeded.
+    pub fn is_ok(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::persist::*;
+    use crate::test::*;
+    use crate::{order::CsrOrder, Directory, DirectoryUrl};
+    use std::{fs, sync::Mutex};
+
+    #[test]
+    fn test_deploy_hooks() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("acme-lib-deploy-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let cert = Certificate::new(Some(EXAMPLE_KEY.into()), EXAMPLE_CHAIN.into());
+        let seen = Arc::new(Mutex::new(vec![]));
+        let seen2 = seen.clone();
+
+        let hooks = DeployHooks::new()
+            .export(CertbotExport::new(&dir))
+            .callback("record", move |d| {
+                let fullchain = fs::read_to_string(d.live_dir().unwrap().join("fullchain.pem"))?;
+                assert_eq!(fullchain, d.certificate().certificate());
+                seen2.lock().unwrap().push(d.names().to_vec());
+                Ok(())
+            })
+            .callback("broken", |_| Err("broken".into()));
+        #[cfg(unix)]
+        let hooks = hooks.command("sh", ["-c", "exit 3"]).command(
+            "sh",
+            [
+                "-c",
+                r#"echo "$ACME_NAMES $ACME_KEY_PATH" > "$RENEWED_LINEAGE/../out""#,
+            ],
+        );
+
+        let report = hooks.deploy_if_changed("example.com", cert.clone(), true);
+        assert!(report.is_changed());
+        let failed: Vec<_> = report.failures().iter().map(|f| f.hook()).collect();
+        #[cfg(unix)]
+        {
+            assert_eq!(failed, ["broken", "sh -c exit 3"]);
+            let out = fs::read_to_string(dir.join("live/out"))?;
+            let key = dir.join("live/example.com/privkey.pem");
+            assert_eq!(
+                out,
+                format!(
+                    "example.com www.example.com *.example.com {}\n",
+                    key.display()
+                )
+            );
+        }
+        #[cfg(not(unix))]
+        assert_eq!(failed, ["broken"]);
+        assert_eq!(
+            *seen.lock().unwrap(),
+            [["example.com", "www.example.com", "*.example.com"]]
+        );
+
+        // a command that hangs is killed
+        #[cfg(unix)]
+        {
+            let started = Instant::now();
+            let failures = DeployHooks::new()
+                .timeout(Duration::from_millis(200))
+                .command("sh", ["-c", "exec sleep 5"])
+                .deploy("example.com", &cert);
+            assert!(started.elapsed() < Duration::from_secs(4));
+            assert_eq!(failures.len(), 1);
+            assert_eq!(failures[0].hook(), "sh -c exec sleep 5");
+            assert!(failures[0].error().to_string().contains("Timed out"));
+
+            // nor waits for what the command left running
+            let started = Instant::now();
+            let failures = DeployHooks::new()
+                .timeout(Duration::from_millis(500))
+                .command("sh", ["-c", "sleep 5 &"])
+                .deploy("example.com", &cert);
+            assert!(started.elapsed() < Duration::from_secs(4));
+            assert!(failures.is_empty());
+
+            let failures = DeployHooks::new()
+                .timeout(Duration::MAX)
+                .command("sh", ["-c", "echo done"])
+                .deploy("example.com", &cert);
+            assert!(failures.is_empty());
+        }
+
+        // nothing runs for the same certificate
+        let report = hooks.deploy_if_changed("example.com", cert.clone(), false);
+        assert!(report.is_ok());
+        assert_eq!(seen.lock().unwrap().len(), 1);
+
+        // the files would be stale
+        let report = hooks.deploy_if_changed("../example.com", cert, true);
+        let failed: Vec<_> = report.failures().iter().map(|f| f.hook()).collect();
+        assert_eq!(failed, ["export"]);
+        assert_eq!(seen.lock().unwrap().len(), 1);
+
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_download_and_deploy_cert() -> Result<()> {
+        let server = with_directory_server();
+        let url = DirectoryUrl::Other(&server.dir_url);
+        let dir = Directory::from_url(MemoryPersist::new(), url)?;
+        let acc = dir.account("foo@bar.com")?;
+        let runs = Arc::new(Mutex::new(0));
+        let runs2 = runs.clone();
+        let hooks = DeployHooks::new().callback("count", move |_| {
+            *runs2.lock().unwrap() += 1;
+            Err("unreachable".into())
+        });
+
+        let mut reports = vec![];
+        for _ in 0..2 {
+            let ord = acc.new_order("acmetest.example.com", &[])?;
+            let ord = CsrOrder { order: ord.order };
+            let report = ord
+                .finalize_pkey(example_key(), 1)?
+                .download_and_deploy_cert(&hooks)?;
+            reports.push(report);
+        }
+
+        // the test server issues the same certificate again
+        assert!(reports[0].is_changed());
+        assert_eq!(reports[0].failures()[0].hook(), "count");
+        assert!(!reports[1].is_changed());
+        assert!(reports[1].is_ok());
+        assert_eq!(*runs.lock().unwrap(), 1);
+
+        // a failed hook doesn't lose the certificate
+        let saved = acc.certificate("acmetest.example.com")?.unwrap();
+        assert_eq!(&saved, reports[0].certificate());
+        Ok(())
+    }
+}
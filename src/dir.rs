@@ -1,14 +1,16 @@
 //
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
 
 use crate::{
-    acc::AcmeKey,
+    acc::{revocation_body, revocation_outcome, AcmeKey},
     api::{ApiAccount, ApiDirectory},
-    persist::{Persist, PersistKey, PersistKind},
-    req::{get, ExtractHeader},
-    trans::{NoncePool, Transport},
+    http::{certificates_from_pem, HttpClient, UreqClient, DEFAULT_TIMEOUT},
+    persist::{Persist, PersistKey, PersistKind, ACCOUNT_KEY},
+    req::{check_status, get, ExtractHeader},
+    signer::JwsSigner,
+    trans::{call_signed, jws_with_cert_key, NoncePool, Transport},
     util::read_json,
-    Account, Result,
+    Account, Certificate, Result, RevocationOutcome, RevocationReason,
 };
 
 const LETSENCRYPT: &str = "https://acme-v02.api.letsencrypt.org/directory";
@@ -28,7 +30,7 @@ pub enum DirectoryUrl<'a> {
 }
 
 impl<'a> DirectoryUrl<'a> {
-    fn to_url(&self) -> &str {
+    pub(crate) fn to_url(&self) -> &str {
         match self {
             DirectoryUrl::LetsEncrypt => LETSENCRYPT,
             DirectoryUrl::LetsEncryptStaging => LETSENCRYPT_STAGING,
@@ -41,21 +43,61 @@ impl<'a> DirectoryUrl<'a> {
 #[derive(Clone)]
 pub struct Directory<P: Persist> {
     persist: P,
+    http: Arc<dyn HttpClient>,
     nonce_pool: Arc<NoncePool>,
     api_directory: ApiDirectory,
+    history: usize,
 }
 
 impl<P: Persist> Directory<P> {
     /// Create a directory over a persistence implementation and directory url.
+    ///
+    /// This uses the default HTTP client. See [`builder`] for more options.
+    ///
+    /// [`builder`]: struct.Directory.html#method.builder
     pub fn from_url(persist: P, url: DirectoryUrl) -> Result<Directory<P>> {
+        Directory::builder(persist, url).build()
+    }
+
+    /// Builder for a directory with a custom HTTP client, trust roots or timeout.
+    ///
+    /// ```no_run
+    /// use acme_lib::{Directory, DirectoryUrl, Error};
+    /// use acme_lib::persist::FilePersist;
+    /// use std::time::Duration;
+    ///
+    /// fn private_ca() -> Result<(), Error> {
+    ///   let url = DirectoryUrl::Other("https://ca.internal:9000/acme/acme/directory");
+    ///   let roots = std::fs::read("/etc/step/certs/root_ca.crt")?;
+    ///   let dir = Directory::builder(FilePersist::new("."), url)
+    ///     .trust_root_pem(&roots)
+    ///     .timeout(Duration::from_secs(10))
+    ///     .build()?;
+    ///   Ok(())
+    /// }
+    /// ```
+    pub fn builder(persist: P, url: DirectoryUrl) -> DirectoryBuilder<P> {
+        DirectoryBuilder {
+            persist,
+            url,
+            http: None,
+            trust_roots_pem: vec![],
+            timeout: DEFAULT_TIMEOUT,
+            history: 0,
+        }
+    }
+
+    fn with_http(persist: P, url: DirectoryUrl, http: Arc<dyn HttpClient>) -> Result<Self> {
         let dir_url = url.to_url();
-        let res = get(dir_url)?;
+        let res = check_status(get(&*http, dir_url)?)?;
         let api_directory: ApiDirectory = read_json(res)?;
         let nonce_pool = Arc::new(NoncePool::new(&api_directory.newNonce));
         Ok(Directory {
             persist,
+            http,
             nonce_pool,
             api_directory,
+            history: 0,
         })
     }
 
@@ -99,33 +141,55 @@ impl<P: Persist> Directory<P> {
         realm: &str,
         contact: Option<Vec<String>>,
     ) -> Result<Account<P>> {
-        // key in persistence for acme account private key
-        let pem_key = PersistKey::new(realm, PersistKind::AccountPrivateKey, "acme_account");
-
-        // Get the key from a saved PEM, or from creating a new
-        let mut is_new = false;
-        let pem = self.persist().get(&pem_key)?;
-        let acme_key = if let Some(pem) = pem {
-            // we got a persisted private key. read it.
-            debug!("Read persisted acme account key");
-            AcmeKey::from_pem(&pem)?
-        } else {
-            // create a new key (and new account)
-            debug!("Create new acme account key");
-            is_new = true;
-            AcmeKey::new()
+        self.account_with_key(realm, contact, None)
+    }
+
+    /// Access an account where the account key is held by an external signer,
+    /// such as an HSM, a cloud KMS or a PKCS#11 token.
+    ///
+    /// This works like [`account_with_realm`], but nothing about the account key
+    /// is read from or saved to the persistence. The `realm` is still used for the
+    /// certificates of the account.
+    ///
+    /// [`account_with_realm`]: struct.Directory.html#method.account_with_realm
+    pub fn account_with_signer(
+        &self,
+        realm: &str,
+        contact: Option<Vec<String>>,
+        signer: impl JwsSigner + 'static,
+    ) -> Result<Account<P>> {
+        let acme_key = AcmeKey::from_signer(Arc::new(signer))?;
+        self.account_with_key(realm, contact, Some(acme_key))
+    }
+
+    /// Access the account with the given key, or the key in the persistence.
+    fn account_with_key(
+        &self,
+        realm: &str,
+        contact: Option<Vec<String>>,
+        acme_key: Option<AcmeKey>,
+    ) -> Result<Account<P>> {
+        // Serializes creating the account with other processes, so they don't
+        // both create a new key.
+        let lock_key = PersistKey::new(realm, PersistKind::AccountPrivateKey, ACCOUNT_KEY);
+        let _lock = self.persist.lock(&lock_key)?;
+
+        let migrated = self.persist.migrate_realm(realm)?;
+        if migrated > 0 {
+            info!("Migrated {} persisted values of realm {}", migrated, realm);
+        }
+
+        let (acme_key, is_new) = match acme_key {
+            Some(acme_key) => (acme_key, false),
+            None => load_acme_key(self.persist(), realm)?,
         };
 
         // Prepare making a call to newAccount. This is fine to do both for
         // new keys and existing. For existing the spec says to return a 200
         // with the Location header set to the key id (kid).
-        let acc = ApiAccount {
-            contact,
-            termsOfServiceAgreed: Some(true),
-            ..Default::default()
-        };
+        let acc = new_account(contact);
 
-        let mut transport = Transport::new(&self.nonce_pool, acme_key);
+        let mut transport = Transport::new(&self.http, &self.nonce_pool, acme_key);
         let res = transport.call_jwk(&self.api_directory.newAccount, &acc)?;
         let kid = res.extract_header("location")?;
         debug!("Key id is: {}", kid);
@@ -136,9 +200,7 @@ impl<P: Persist> Directory<P> {
 
         // If we did create a new key, save it back to the persistence.
         if is_new {
-            debug!("Persist acme account key");
-            let pem = transport.acme_key().to_pem();
-            self.persist().put(&pem_key, &pem)?;
+            save_acme_key(self.persist(), realm, transport.acme_key())?;
         }
 
         // The finished account
@@ -148,9 +210,36 @@ impl<P: Persist> Directory<P> {
             realm,
             api_account,
             self.api_directory.clone(),
+            self.history,
         ))
     }
 
+    /// Revoke a certificate for the reason given, signing the request with the private
+    /// key of the certificate instead of an account key.
+    ///
+    /// The ACME API lets whoever holds the private key of a certificate revoke it, so
+    /// this needs no account, i.e. for when the account key is lost but the certificate
+    /// key has been compromised. The key is RSA, P-256 or P-384 as created by this
+    /// library. Fails if the private key is held externally.
+    ///
+    /// The outcomes are those of [`Account::revoke_certificate`], but without an account
+    /// nothing is recorded in the persistence.
+    ///
+    /// [`Account::revoke_certificate`]: struct.Account.html#method.revoke_certificate
+    pub fn revoke_certificate_with_key(
+        &self,
+        cert: &Certificate,
+        reason: RevocationReason,
+    ) -> Result<RevocationOutcome> {
+        let key = cert.key_pair()?;
+        let revoc = revocation_body(cert, reason)?;
+
+        let url = &self.api_directory.revokeCert;
+        revocation_outcome(call_signed(&*self.http, &self.nonce_pool, url, |nonce| {
+            jws_with_cert_key(url, nonce, &key, &revoc)
+        }))
+    }
+
     /// Access the underlying JSON object for debugging.
     pub fn api_directory(&self) -> &ApiDirectory {
         &self.api_directory
@@ -161,6 +250,134 @@ impl<P: Persist> Directory<P> {
     }
 }
 
+/// Read the account key from the persistence, or create a new one.
+///
+/// Returns whether the key is new.
+pub(crate) fn load_acme_key<P: Persist>(persist: &P, realm: &str) -> Result<(AcmeKey, bool)> {
+    // key in persistence for acme account private key
+    let pem_key = PersistKey::new(realm, PersistKind::AccountPrivateKey, ACCOUNT_KEY);
+
+    // Get the key from a saved PEM, or from creating a new
+    let pem = persist.get(&pem_key)?;
+    if let Some(pem) = pem {
+        // we got a persisted private key. read it.
+        debug!("Read persisted acme account key");
+        Ok((AcmeKey::from_pem(&pem)?, false))
+    } else {
+        // create a new key (and new account)
+        debug!("Create new acme account key");
+        Ok((AcmeKey::new()?, true))
+    }
+}
+
+/// Save a new account key to the persistence.
+pub(crate) fn save_acme_key<P: Persist>(persist: &P, realm: &str, key: &AcmeKey) -> Result<()> {
+    let pem_key = PersistKey::new(realm, PersistKind::AccountPrivateKey, ACCOUNT_KEY);
+    debug!("Persist acme account key");
+    let pem = key.to_pem()?;
+    persist.put(&pem_key, &pem)
+}
+
+/// Certificate history lists saved versions, so fail when building the
+/// directory rather than at the first saved certificate.
+pub(crate) fn check_history_support<P: Persist>(persist: &P, history: usize) -> Result<()> {
+    if history == 0 {
+        return Ok(());
+    }
+    match persist.list("", PersistKind::Certificate) {
+        Ok(_) => Ok(()),
+        Err(e) => Err(format!(
+            "Certificate history requires a Persist that implements list and delete: {}",
+            e
+        )
+        .into()),
+    }
+}
+
+/// The body of a newAccount call.
+pub(crate) fn new_account(contact: Option<Vec<String>>) -> ApiAccount {
+    ApiAccount {
+        contact,
+        termsOfServiceAgreed: Some(true),
+        ..Default::default()
+    }
+}
+
+/// Builder for a [`Directory`] created by [`Directory::builder`].
+///
+/// [`Directory`]: struct.Directory.html
+/// [`Directory::builder`]: struct.Directory.html#method.builder
+pub struct DirectoryBuilder<'a, P: Persist> {
+    persist: P,
+    url: DirectoryUrl<'a>,
+    http: Option<Arc<dyn HttpClient>>,
+    trust_roots_pem: Vec<Vec<u8>>,
+    timeout: Duration,
+    history: usize,
+}
+
+impl<'a, P: Persist> DirectoryBuilder<'a, P> {
+    /// Use a custom HTTP client for all calls to the ACME API.
+    ///
+    /// When set, the [`trust_root_pem`] and [`timeout`] settings are not used
+    /// since they configure the default client.
+    ///
+    /// [`trust_root_pem`]: struct.DirectoryBuilder.html#method.trust_root_pem
+    /// [`timeout`]: struct.DirectoryBuilder.html#method.timeout
+    pub fn http_client<H: HttpClient + 'static>(mut self, http: H) -> Self {
+        self.http = Some(Arc::new(http));
+        self
+    }
+
+    /// Trust the root certificate(s) in the PEM in addition to the default
+    /// webpki roots. Typically used for private ACME servers.
+    pub fn trust_root_pem(mut self, pem: &[u8]) -> Self {
+        self.trust_roots_pem.push(pem.to_vec());
+        self
+    }
+
+    /// Timeout for each call to the ACME API. Defaults to 30 seconds.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Keep the last number of saved certificates and their private keys, including
+    /// the current one, for [`Account::certificate_history`]. Defaults to 0, no history.
+    ///
+    /// Requires a [`Persist`] that implements `list` and `delete`, [`build`] fails
+    /// if `list` isn't supported.
+    ///
+    /// [`build`]: struct.DirectoryBuilder.html#method.build
+    /// [`Account::certificate_history`]: struct.Account.html#method.certificate_history
+    /// [`Persist`]: persist/trait.Persist.html
+    pub fn certificate_history(mut self, versions: usize) -> Self {
+        self.history = versions;
+        self
+    }
+
+    /// Create the directory. This reads the directory from the ACME API.
+    pub fn build(self) -> Result<Directory<P>> {
+        check_history_support(&self.persist, self.history)?;
+        let http: Arc<dyn HttpClient> = match self.http {
+            Some(http) => http,
+            None if self.trust_roots_pem.is_empty() && self.timeout == DEFAULT_TIMEOUT => {
+                Arc::new(UreqClient::new())
+            }
+            None => {
+                let mut roots = vec![];
+                for pem in &self.trust_roots_pem {
+                    roots.extend(certificates_from_pem(pem)?);
+                }
+                Arc::new(UreqClient::with_roots(&roots, self.timeout)?)
+            }
+        };
+        let mut dir = Directory::with_http(self.persist, self.url, http)?;
+        dir.history = self.history;
+        Ok(dir)
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -174,6 +391,130 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn test_custom_http_client() -> Result<()> {
+        use crate::http::{HttpRequest, HttpResponse};
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        struct Counting(Arc<AtomicUsize>, UreqClient);
+        impl HttpClient for Counting {
+            fn request(&self, req: &HttpRequest) -> Result<HttpResponse> {
+                self.0.fetch_add(1, Ordering::SeqCst);
+                self.1.request(req)
+            }
+        }
+
+        let server = crate::test::with_directory_server();
+        let url = DirectoryUrl::Other(&server.dir_url);
+        let count = Arc::new(AtomicUsize::new(0));
+        let dir = Directory::builder(MemoryPersist::new(), url)
+            .http_client(Counting(count.clone(), UreqClient::new()))
+            .build()?;
+        let _ = dir.account("foo@bar.com")?;
+        // directory, nonce and new account
+        assert_eq!(count.load(Ordering::SeqCst), 3);
+        Ok(())
+    }
+
+    #[test]
+    fn test_builder_bad_trust_root() {
+        let server = crate::test::with_directory_server();
+        let url = DirectoryUrl::Other(&server.dir_url);
+        let res = Directory::builder(MemoryPersist::new(), url)
+            .trust_root_pem(b"not a pem")
+            .build();
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn test_builder_history_without_list() -> Result<()> {
+        // only the required methods, list falls back to the default error
+        #[derive(Clone)]
+        struct GetPut(MemoryPersist);
+        impl Persist for GetPut {
+            fn put(&self, key: &PersistKey, value: &[u8]) -> Result<()> {
+                self.0.put(key, value)
+            }
+            fn get(&self, key: &PersistKey) -> Result<Option<Vec<u8>>> {
+                self.0.get(key)
+            }
+        }
+
+        let server = crate::test::with_directory_server();
+        let url = DirectoryUrl::Other(&server.dir_url);
+        let res = Directory::builder(GetPut(MemoryPersist::new()), url)
+            .certificate_history(2)
+            .build();
+        let err = res.err().expect("history without list");
+        assert!(err.to_string().contains("list"));
+
+        let url = DirectoryUrl::Other(&server.dir_url);
+        let _ = Directory::builder(GetPut(MemoryPersist::new()), url).build()?;
+        let url = DirectoryUrl::Other(&server.dir_url);
+        let _ = Directory::builder(MemoryPersist::new(), url)
+            .certificate_history(2)
+            .build()?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_revoke_certificate_with_key() -> Result<()> {
+        use crate::http::{HttpRequest, HttpResponse};
+        use crate::test::{EXAMPLE_CHAIN, EXAMPLE_KEY};
+        use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+        use std::sync::Mutex;
+
+        /// Keeps the bodies POSTed to the revocation URL.
+        struct Recording(Arc<Mutex<Vec<Vec<u8>>>>, UreqClient);
+        impl HttpClient for Recording {
+            fn request(&self, req: &HttpRequest) -> Result<HttpResponse> {
+                if req.url.ends_with("/acme/revoke-cert") {
+                    self.0.lock().unwrap().push(req.body.to_vec());
+                }
+                self.1.request(req)
+            }
+        }
+
+        let server = crate::test::with_directory_server();
+        let url = DirectoryUrl::Other(&server.dir_url);
+        let bodies = Arc::new(Mutex::new(vec![]));
+        let dir = Directory::builder(MemoryPersist::new(), url)
+            .http_client(Recording(bodies.clone(), UreqClient::new()))
+            .build()?;
+
+        let cert = Certificate::new(Some(EXAMPLE_KEY.into()), EXAMPLE_CHAIN.into());
+        let outcome = dir.revoke_certificate_with_key(&cert, RevocationReason::KeyCompromise)?;
+        assert_eq!(outcome, RevocationOutcome::Revoked);
+
+        let bodies = bodies.lock().unwrap();
+        assert_eq!(bodies.len(), 1);
+        let decode = |v: &serde_json::Value| -> serde_json::Value {
+            let json = URL_SAFE_NO_PAD.decode(v.as_str().unwrap()).unwrap();
+            serde_json::from_slice(&json).unwrap()
+        };
+        let jws: serde_json::Value = serde_json::from_slice(&bodies[0])?;
+        let protected = decode(&jws["protected"]);
+        let jwk = serde_json::to_value(cert.key_pair()?.jwk()?)?;
+        assert_eq!(protected["alg"], "ES256");
+        assert_eq!(protected["jwk"], jwk);
+        assert!(protected.get("kid").is_none());
+        assert_eq!(protected["url"], dir.api_directory().revokeCert);
+
+        let payload = decode(&jws["payload"]);
+        assert_eq!(payload["reason"], 1);
+        assert_eq!(
+            payload["certificate"],
+            URL_SAFE_NO_PAD.encode(cert.certificate_der()?)
+        );
+
+        // the key is needed to sign
+        let external = Certificate::new(None, EXAMPLE_CHAIN.into());
+        assert!(dir
+            .revoke_certificate_with_key(&external, RevocationReason::KeyCompromise)
+            .is_err());
+        Ok(())
+    }
+
     #[test]
     fn test_create_acount() -> Result<()> {
         let server = crate::test::with_directory_server();
@@ -193,8 +534,8 @@ mod test {
         let acc1 = dir.account("foo@bar.com")?;
         let acc2 = dir.account("foo@bar.com")?;
         let acc3 = dir.account("karlfoo@bar.com")?;
-        assert_eq!(acc1.acme_private_key_pem(), acc2.acme_private_key_pem());
-        assert!(acc1.acme_private_key_pem() != acc3.acme_private_key_pem());
+        assert_eq!(acc1.acme_private_key_pem()?, acc2.acme_private_key_pem()?);
+        assert!(acc1.acme_private_key_pem()? != acc3.acme_private_key_pem()?);
         Ok(())
     }
 
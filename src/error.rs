@@ -1,7 +1,7 @@
 //
-use std::{fmt, io};
+use std::{fmt, io, time::Duration};
 
-use crate::{api::ApiProblem, req::ExtractBody};
+use crate::api::ApiProblem;
 
 /// acme-lib result.
 pub type Result<T> = std::result::Result<T, Error>;
@@ -11,7 +11,7 @@ pub type Result<T> = std::result::Result<T, Error>;
 pub enum Error {
     /// An API call failed.
     ApiProblem(ApiProblem),
-    /// An API call failed.
+    /// An HTTP call failed on the transport level (connection, TLS, timeout etc).
     Call(String),
     /// Base64 decoding failed.
     Base64Decode(base64::DecodeError),
@@ -19,12 +19,163 @@ pub enum Error {
     Json(serde_json::Error),
     /// std::io error.
     Io(io::Error),
+    /// A cryptographic operation failed, typically due to malformed keys or certificates.
+    Crypto(String),
+    /// The authorization doesn't offer the requested type of challenge, i.e. `http-01`.
+    ChallengeNotOffered(String),
+    /// Polling the API for a status change gave up according to the [`PollPolicy`].
+    ///
+    /// [`PollPolicy`]: struct.PollPolicy.html
+    Timeout(String),
+    /// A certificate failed [`Certificate::verify`], i.e. it doesn't match its key or
+    /// the chain doesn't link up, or an OCSP response for it isn't properly signed or
+    /// current.
+    ///
+    /// [`Certificate::verify`]: struct.Certificate.html#method.verify
+    Verification(String),
     /// Some other error. Notice that `Error` is
     /// `From<String>` and `From<&str>` and it becomes `Other`.
     Other(String),
 }
 impl std::error::Error for Error {}
 
+impl Error {
+    /// The problem reported by the ACME API, if this is an API error.
+    pub fn api_problem(&self) -> Option<&ApiProblem> {
+        match self {
+            Error::ApiProblem(p) => Some(p),
+            _ => None,
+        }
+    }
+
+    /// The type of problem reported by the ACME API, if this is an API error.
+    pub fn problem_type(&self) -> Option<ProblemType> {
+        self.api_problem().map(|p| p.problem_type())
+    }
+
+    /// How long the ACME API asked us to wait before retrying, if it said so.
+    pub fn retry_after(&self) -> Option<Duration> {
+        self.api_problem().and_then(|p| p.retry_after)
+    }
+}
+
+/// Prefix of the problem types defined by RFC 8555.
+const ACME_ERROR_URN: &str = "urn:ietf:params:acme:error:";
+/// Prefix used by the pre-RFC drafts.
+const ACME_ERROR_URN_DRAFT: &str = "urn:acme:error:";
+
+/// The types of problems an ACME API can report.
+///
+/// These are the error types registered in [RFC 8555 section 6.7][rfc] and correspond to the
+/// `type` field of an [`ApiProblem`]. Types outside the ACME namespace end up as `Other`.
+///
+/// [rfc]: https://tools.ietf.org/html/rfc8555#section-6.7
+/// [`ApiProblem`]: api/struct.ApiProblem.html
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum ProblemType {
+    /// The request specified an account that does not exist.
+    AccountDoesNotExist,
+    /// The request specified a certificate to be revoked that has already been revoked.
+    AlreadyRevoked,
+    /// The CSR is unacceptable (e.g., due to a short key).
+    BadCSR,
+    /// The client sent an unacceptable anti-replay nonce.
+    BadNonce,
+    /// The JWS was signed by a public key the server does not support.
+    BadPublicKey,
+    /// The revocation reason provided is not allowed by the server.
+    BadRevocationReason,
+    /// The JWS was signed with an algorithm the server does not support.
+    BadSignatureAlgorithm,
+    /// Certification Authority Authorization (CAA) records forbid the CA from issuing
+    /// a certificate.
+    Caa,
+    /// Specific error conditions are indicated in the subproblems.
+    Compound,
+    /// The server could not connect to validation target.
+    Connection,
+    /// There was a problem with a DNS query during identifier validation.
+    Dns,
+    /// The request must include a value for the "externalAccountBinding" field.
+    ExternalAccountRequired,
+    /// Response received didn't match the challenge's requirements.
+    IncorrectResponse,
+    /// A contact URL for an account was invalid.
+    InvalidContact,
+    /// The request message was malformed.
+    Malformed,
+    /// The request attempted to finalize an order that is not ready to be finalized.
+    OrderNotReady,
+    /// The request exceeds a rate limit.
+    RateLimited,
+    /// The server will not issue certificates for the identifier.
+    RejectedIdentifier,
+    /// The server experienced an internal error.
+    ServerInternal,
+    /// The server received a TLS error during validation.
+    Tls,
+    /// The client lacks sufficient authorization.
+    Unauthorized,
+    /// A contact URL for an account used an unsupported protocol scheme.
+    UnsupportedContact,
+    /// An identifier is of an unsupported type.
+    UnsupportedIdentifier,
+    /// Visit the "instance" URL and take actions specified there.
+    UserActionRequired,
+    /// Any other problem type, such as HTTP errors not originating from the ACME API.
+    Other(String),
+}
+
+impl ProblemType {
+    /// Parse the `type` field of a problem document.
+    ///
+    /// Accepts the full URN (`urn:ietf:params:acme:error:badNonce`), the draft
+    /// URN (`urn:acme:error:badNonce`) as well as the bare name (`badNonce`).
+    pub fn from_type(t: &str) -> ProblemType {
+        let name = t
+            .strip_prefix(ACME_ERROR_URN)
+            .or_else(|| t.strip_prefix(ACME_ERROR_URN_DRAFT))
+            .unwrap_or(t);
+        match name {
+            "accountDoesNotExist" => ProblemType::AccountDoesNotExist,
+            "alreadyRevoked" => ProblemType::AlreadyRevoked,
+            "badCSR" => ProblemType::BadCSR,
+            "badNonce" => ProblemType::BadNonce,
+            "badPublicKey" => ProblemType::BadPublicKey,
+            "badRevocationReason" => ProblemType::BadRevocationReason,
+            "badSignatureAlgorithm" => ProblemType::BadSignatureAlgorithm,
+            "caa" => ProblemType::Caa,
+            "compound" => ProblemType::Compound,
+            "connection" => ProblemType::Connection,
+            "dns" => ProblemType::Dns,
+            "externalAccountRequired" => ProblemType::ExternalAccountRequired,
+            "incorrectResponse" => ProblemType::IncorrectResponse,
+            "invalidContact" => ProblemType::InvalidContact,
+            "malformed" => ProblemType::Malformed,
+            "orderNotReady" => ProblemType::OrderNotReady,
+            "rateLimited" => ProblemType::RateLimited,
+            "rejectedIdentifier" => ProblemType::RejectedIdentifier,
+            "serverInternal" => ProblemType::ServerInternal,
+            "tls" => ProblemType::Tls,
+            "unauthorized" => ProblemType::Unauthorized,
+            "unsupportedContact" => ProblemType::UnsupportedContact,
+            "unsupportedIdentifier" => ProblemType::UnsupportedIdentifier,
+            "userActionRequired" => ProblemType::UserActionRequired,
+            _ => ProblemType::Other(t.to_string()),
+        }
+    }
+
+    /// Whether retrying the same request later might succeed.
+    ///
+    /// This is the case for rate limits, bad nonces and server side errors.
+    pub fn is_transient(&self) -> bool {
+        matches!(
+            self,
+            ProblemType::RateLimited | ProblemType::BadNonce | ProblemType::ServerInternal
+        )
+    }
+}
+
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
@@ -33,6 +184,10 @@ impl fmt::Display for Error {
             Error::Base64Decode(e) => write!(f, "{}", e),
             Error::Json(e) => write!(f, "{}", e),
             Error::Io(e) => write!(f, "{}", e),
+            Error::Crypto(s) => write!(f, "{}", s),
+            Error::ChallengeNotOffered(t) => write!(f, "Challenge not offered: {}", t),
+            Error::Timeout(s) => write!(f, "{}", s),
+            Error::Verification(s) => write!(f, "Certificate verification failed: {}", s),
             Error::Other(s) => write!(f, "{}", s),
         }
     }
@@ -56,61 +211,28 @@ impl From<io::Error> for Error {
     }
 }
 
-impl From<String> for Error {
-    fn from(s: String) -> Self {
-        Error::Other(s)
+#[cfg(feature = "openssl")]
+impl From<openssl::error::ErrorStack> for Error {
+    fn from(e: openssl::error::ErrorStack) -> Self {
+        Error::Crypto(e.to_string())
     }
 }
 
-impl From<&str> for Error {
-    fn from(s: &str) -> Self {
-        Error::Other(s.to_string())
+#[cfg(feature = "sqlite")]
+impl From<rusqlite::Error> for Error {
+    fn from(e: rusqlite::Error) -> Self {
+        Error::Other(format!("SQLite: {}", e))
     }
 }
 
-impl From<ureq::Error> for Error {
-    fn from(value: ureq::Error) -> Self {
-        let res = match value {
-            ureq::Error::Status(_, res) => res,
-            ureq::Error::Transport(_) => {
-                return Error::ApiProblem(ApiProblem {
-                    _type: "httpReqError".into(),
-                    detail: Some("Transport error".into()),
-                    subproblems: None,
-                })
-            }
-        };
-
-        let problem = if res.content_type() == "application/problem+json" {
-            // if we were sent a problem+json, deserialize it
-            let body = res.extract_body();
-            serde_json::from_str(&body).unwrap_or_else(|e| ApiProblem {
-                _type: "problemJsonFail".into(),
-                detail: Some(format!(
-                    "Failed to deserialize application/problem+json ({}) body: {}",
-                    e, body
-                )),
-                subproblems: None,
-            })
-        } else {
-            // some other problem
-            let status = format!("{} {}", res.status(), res.status_text());
-            let body = res.extract_body();
-            let detail = format!("{} body: {}", status, body);
-
-            ApiProblem {
-                _type: "httpReqError".into(),
-                detail: Some(detail),
-                subproblems: None,
-            }
-        };
-
-        Error::ApiProblem(problem)
+impl From<String> for Error {
+    fn from(s: String) -> Self {
+        Error::Other(s)
     }
 }
 
-impl From<Box<ureq::Error>> for Error {
-    fn from(value: Box<ureq::Error>) -> Self {
-        Error::from(*value)
+impl From<&str> for Error {
+    fn from(s: &str) -> Self {
+        Error::Other(s.to_string())
     }
 }
@@ -0,0 +1,234 @@
+//! Export of certificates for other software, i.e. web servers.
+use std::{
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::{Certificate, Result};
+
+/// The files of a version in the certbot layout, without the version number.
+const FILES: [&str; 4] = ["cert", "chain", "fullchain", "privkey"];
+
+/// Export to the directory layout of [certbot], so web server configurations made
+/// for certbot keep working.
+///
+/// Every export writes a new numbered version of the files to `archive/<name>/` and
+/// points the symlinks in `live/<name>/` to it:
+///
+/// ```text
+/// live/example.com/cert.pem      -> ../../archive/example.com/cert2.pem
+/// live/example.com/chain.pem     -> ../../archive/example.com/chain2.pem
+/// live/example.com/fullchain.pem -> ../../archive/example.com/fullchain2.pem
+/// live/example.com/privkey.pem   -> ../../archive/example.com/privkey2.pem
+/// ```
+///
+/// `cert.pem` is only the issued certificate, `chain.pem` the intermediates and
+/// `fullchain.pem` both. When the private key is held externally, there is no
+/// `privkey.pem`. On platforms without symlinks the files in `live/` are copies.
+///
+/// ```no_run
+/// use acme_lib::export::CertbotExport;
+/// # fn main() -> acme_lib::Result<()> {
+/// # let cert: acme_lib::Certificate = unimplemented!();
+/// let export = CertbotExport::new("/etc/letsencrypt").keep_versions(5);
+/// export.export("example.com", &cert)?;
+/// # Ok(()) }
+/// ```
+///
+/// [certbot]: https://eff-certbot.readthedocs.io/en/stable/using.html#where-are-my-certificates
+#[derive(Clone, Debug)]
+pub struct CertbotExport {
+    dir: PathBuf,
+    keep_versions: Option<u32>,
+}
+
+impl CertbotExport {
+    /// Export to the directory, i.e. `/etc/letsencrypt`.
+    pub fn new<P: AsRef<Path>>(dir: P) -> Self {
+        CertbotExport {
+            dir: dir.as_ref().to_path_buf(),
+            keep_versions: None,
+        }
+    }
+
+    /// Remove all but the latest number of versions from the archive after exporting.
+    /// By default all versions are kept, like certbot does.
+    pub fn keep_versions(mut self, versions: u32) -> Self {
+        self.keep_versions = Some(versions.max(1));
+        self
+    }
+
+    /// The directory with the symlinks of the name, i.e. `live/example.com`.
+    pub fn live_dir(&self, name: &str) -> PathBuf {
+        self.dir.join("live").join(name)
+    }
+
+    /// The directory with the versions of the name, i.e. `archive/example.com`.
+    pub fn archive_dir(&self, name: &str) -> PathBuf {
+        self.dir.join("archive").join(name)
+    }
+
+    /// Write the certificate as a new version under the name, usually the primary name
+    /// of the certificate, and point the files in `live/` to it. Returns the version.
+    pub fn export(&self, name: &str, cert: &Certificate) -> Result<u32> {
+        if name.is_empty() || name.starts_with('.') || name.contains(['/', '\\']) {
+            return Err(format!("Invalid name for export: {}", name).into());
+        }
+        let leaf = cert.leaf_pem()?;
+        let chain = cert.chain_pem();
+        let fullchain = format!("{}{}", leaf, chain);
+
+        let archive = self.archive_dir(name);
+        let live = self.live_dir(name);
+        fs::create_dir_all(&archive)?;
+        fs::create_dir_all(&live)?;
+
+        let version = self.versions(name)?.last().map(|v| v + 1).unwrap_or(1);
+        let contents = [
+            Some(leaf.as_str()),
+            Some(&chain),
+            Some(&fullchain),
+            cert.private_key(),
+        ];
+        for (file, content) in FILES.iter().zip(contents) {
+            let link = live.join(format!("{}.pem", file));
+            let Some(content) = content else {
+                // a private key of an earlier version doesn't belong to this certificate
+                remove_file(&link)?;
+                continue;
+            };
+            let target = format!("{}{}.pem", file, version);
+            write_new(
+                &archive.join(&target),
+                content.as_bytes(),
+                *file == "privkey",
+            )?;
+            replace_link(&link, &Path::new("../../archive").join(name).join(&target))?;
+        }
+        debug!("Exported {} version {} to {:?}", name, version, self.dir);
+
+        if let Some(keep) = self.keep_versions {
+            for old in self
+                .versions(name)?
+                .into_iter()
+                .filter(|v| v + keep <= version)
+            {
+                for file in FILES {
+                    remove_file(&archive.join(format!("{}{}.pem", file, old)))?;
+                }
+            }
+        }
+        Ok(version)
+    }
+
+    /// The versions in the archive of the name, oldest first.
+    pub fn versions(&self, name: &str) -> Result<Vec<u32>> {
+        let dir = match fs::read_dir(self.archive_dir(name)) {
+            Ok(d) => d,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => return Err(e.into()),
+        };
+        let mut versions = vec![];
+        for entry in dir {
+            let file_name = entry?.file_name();
+            let version = file_name
+                .to_str()
+                .and_then(|n| n.strip_prefix("cert"))
+                .and_then(|n| n.strip_suffix(".pem"))
+                .and_then(|n| n.parse().ok());
+            if let Some(v) = version {
+                versions.push(v);
+            }
+        }
+        versions.sort_unstable();
+        Ok(versions)
+    }
+}
+
+fn remove_file(path: &Path) -> Result<()> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
+/// Write a file that must not exist, readable only by the owner if `private`.
+fn write_new(path: &Path, content: &[u8], private: bool) -> Result<()> {
+    let mut opts = fs::OpenOptions::new();
+    opts.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        opts.mode(if private { 0o600 } else { 0o644 });
+    }
+    #[cfg(not(unix))]
+    let _ = private;
+    let mut file = opts.open(path)?;
+    file.write_all(content)?;
+    file.sync_all()?;
+    Ok(())
+}
+
+/// Point the link to the target, relative to the directory of the link, replacing
+/// any existing link in one rename.
+#[cfg(unix)]
+fn replace_link(link: &Path, target: &Path) -> Result<()> {
+    let mut tmp = link.as_os_str().to_owned();
+    tmp.push(".tmp");
+    remove_file(Path::new(&tmp))?;
+    std::os::unix::fs::symlink(target, &tmp)?;
+    fs::rename(&tmp, link)?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn replace_link(link: &Path, target: &Path) -> Result<()> {
+    let source = link.parent().unwrap_or(link).join(target);
+    let mut tmp = link.as_os_str().to_owned();
+    tmp.push(".tmp");
+    fs::copy(source, &tmp)?;
+    fs::rename(&tmp, link)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const LEAF: &str = "-----BEGIN CERTIFICATE-----\nLEAF\n-----END CERTIFICATE-----\n";
+    const CHAIN: &str = "-----BEGIN CERTIFICATE-----\nCA\n-----END CERTIFICATE-----\n";
+
+    #[test]
+    fn test_certbot_export() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("acme-lib-certbot-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let export = CertbotExport::new(&dir).keep_versions(2);
+        let cert = Certificate::new(Some("KEY".into()), format!("{}{}", LEAF, CHAIN));
+        let read = |file: &str| fs::read_to_string(export.live_dir("example.com").join(file));
+
+        assert_eq!(export.export("example.com", &cert)?, 1);
+        assert_eq!(read("cert.pem")?, LEAF);
+        assert_eq!(read("chain.pem")?, CHAIN);
+        assert_eq!(read("fullchain.pem")?, format!("{}{}", LEAF, CHAIN));
+        assert_eq!(read("privkey.pem")?, "KEY");
+
+        let external = Certificate::new(None, LEAF.into());
+        assert_eq!(export.export("example.com", &external)?, 2);
+        assert_eq!(read("chain.pem")?, "");
+        assert!(read("privkey.pem").is_err());
+        #[cfg(unix)]
+        assert_eq!(
+            fs::read_link(export.live_dir("example.com").join("cert.pem"))?,
+            Path::new("../../archive/example.com/cert2.pem")
+        );
+
+        assert_eq!(export.export("example.com", &cert)?, 3);
+        assert_eq!(export.versions("example.com")?, [2, 3]);
+        assert_eq!(read("privkey.pem")?, "KEY");
+        assert!(export.export("../etc", &cert).is_err());
+
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,327 @@
+//! Pluggable HTTP client.
+//!
+//! All calls to the ACME API go through an [`HttpClient`]. The default is [`UreqClient`],
+//! which can be configured with custom trust roots and timeout using the
+//! [`DirectoryBuilder`]. For proxies, mTLS or an instrumented client, implement the trait
+//! and provide it to the builder.
+//!
+//! [`HttpClient`]: trait.HttpClient.html
+//! [`UreqClient`]: struct.UreqClient.html
+//! [`DirectoryBuilder`]: ../struct.DirectoryBuilder.html
+
+use rustls::{pki_types::CertificateDer, ClientConfig, RootCertStore};
+use rustls_pki_types::pem::PemObject;
+use std::{fmt, io::Read, sync::Arc, time::Duration};
+
+use crate::{Error, Result};
+
+/// Default timeout for calls to the ACME API.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// HTTP methods used against the ACME API.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Method {
+    Get,
+    Head,
+    Post,
+}
+
+impl Method {
+    /// The method as used in the request line.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Method::Get => "GET",
+            Method::Head => "HEAD",
+            Method::Post => "POST",
+        }
+    }
+}
+
+/// A request to be sent by an [`HttpClient`].
+///
+/// [`HttpClient`]: trait.HttpClient.html
+#[derive(Debug, Clone)]
+pub struct HttpRequest<'a> {
+    /// Request method.
+    pub method: Method,
+    /// Full URL of the request.
+    pub url: &'a str,
+    /// The `Content-Type` header, when there is a body.
+    pub content_type: Option<&'a str>,
+    /// Request body. Empty for `GET` and `HEAD`.
+    pub body: &'a [u8],
+}
+
+/// A response received by an [`HttpClient`].
+///
+/// [`HttpClient`]: trait.HttpClient.html
+#[derive(Clone, PartialEq, Eq)]
+pub struct HttpResponse {
+    status: u16,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl HttpResponse {
+    /// Create a response from the status code, the headers and the body.
+    pub fn new(status: u16, headers: Vec<(String, String)>, body: Vec<u8>) -> Self {
+        HttpResponse {
+            status,
+            headers,
+            body,
+        }
+    }
+
+    /// The HTTP status code.
+    pub fn status(&self) -> u16 {
+        self.status
+    }
+
+    /// Whether the status code is an error (4xx or 5xx).
+    pub fn is_error(&self) -> bool {
+        self.status >= 400
+    }
+
+    /// The first value of a header. Header names are case insensitive.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| &v[..])
+    }
+
+    /// The content type without any parameters.
+    pub fn content_type(&self) -> &str {
+        self.header("content-type")
+            .and_then(|c| c.split(';').next())
+            .map(|c| c.trim())
+            .unwrap_or("")
+    }
+
+    /// The response body.
+    pub fn body(&self) -> &[u8] {
+        &self.body
+    }
+
+    /// Consume the response into the body.
+    pub fn into_body(self) -> Vec<u8> {
+        self.body
+    }
+
+    /// Consume the response into the body as a string.
+    pub fn into_string(self) -> Result<String> {
+        String::from_utf8(self.body).map_err(|e| format!("Body is not UTF-8: {}", e).into())
+    }
+}
+
+impl fmt::Debug for HttpResponse {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("HttpResponse")
+            .field("status", &self.status)
+            .field("headers", &self.headers)
+            .field("body_len", &self.body.len())
+            .finish()
+    }
+}
+
+/// Trait for an HTTP client used to talk to the ACME API.
+///
+/// Responses with error status codes (4xx, 5xx) must be returned as `Ok`, since they
+/// carry problem documents and nonces. `Err` is for transport errors, preferably
+/// [`Error::Call`].
+///
+/// [`Error::Call`]: ../enum.Error.html#variant.Call
+pub trait HttpClient: Send + Sync {
+    /// Send the request and read the entire response.
+    fn request(&self, req: &HttpRequest) -> Result<HttpResponse>;
+}
+
+/// The default [`HttpClient`] backed by [ureq](https://docs.rs/ureq/).
+///
+/// [`HttpClient`]: trait.HttpClient.html
+#[derive(Clone)]
+pub struct UreqClient {
+    agent: ureq::Agent,
+}
+
+impl UreqClient {
+    /// Client with the default timeout, trusting the webpki roots.
+    pub fn new() -> Self {
+        Self::with_agent(ureq::AgentBuilder::new().timeout(DEFAULT_TIMEOUT).build())
+    }
+
+    /// Client with the given timeout that trusts the webpki roots and in addition the
+    /// given DER encoded root certificates.
+    pub fn with_roots(roots: &[Vec<u8>], timeout: Duration) -> Result<Self> {
+        let config = tls_config(roots)?;
+        Ok(Self::with_agent(
+            ureq::AgentBuilder::new()
+                .timeout(timeout)
+                .tls_config(Arc::new(config))
+                .build(),
+        ))
+    }
+
+    /// Client using an already configured ureq agent (for proxies, mTLS etc).
+    pub fn with_agent(agent: ureq::Agent) -> Self {
+        UreqClient { agent }
+    }
+}
+
+impl Default for UreqClient {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl HttpClient for UreqClient {
+    fn request(&self, req: &HttpRequest) -> Result<HttpResponse> {
+        let mut r = self.agent.request(req.method.as_str(), req.url);
+        if let Some(content_type) = req.content_type {
+            r = r.set("content-type", content_type);
+        }
+
+        let result = if req.method == Method::Post {
+            r.send_bytes(req.body)
+        } else {
+            r.call()
+        };
+
+        let res = match result {
+            Ok(res) => res,
+            Err(ureq::Error::Status(_, res)) => res,
+            Err(ureq::Error::Transport(t)) => return Err(Error::Call(t.to_string())),
+        };
+
+        let status = res.status();
+        let headers = res
+            .headers_names()
+            .into_iter()
+            .filter_map(|n| res.header(&n).map(|v| (n.clone(), v.to_string())))
+            .collect();
+
+        let mut body = vec![];
+        // letsencrypt sometimes closes the TLS abruptly causing io error
+        // even though we did capture the body.
+        res.into_reader().read_to_end(&mut body).ok();
+
+        Ok(HttpResponse::new(status, headers, body))
+    }
+}
+
+/// TLS config trusting the webpki roots and the given DER encoded root certificates.
+fn tls_config(roots: &[Vec<u8>]) -> Result<ClientConfig> {
+    let mut store = RootCertStore::empty();
+    store.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+    for root in roots {
+        store
+            .add(CertificateDer::from(root.clone()))
+            .map_err(|e| format!("Invalid trust root: {}", e))?;
+    }
+
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let config = ClientConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()
+        .map_err(|e| format!("TLS config: {}", e))?
+        .with_root_certificates(store)
+        .with_no_client_auth();
+    Ok(config)
+}
+
+/// Boxed future returned by an [`AsyncHttpClient`].
+///
+/// [`AsyncHttpClient`]: trait.AsyncHttpClient.html
+#[cfg(feature = "async")]
+pub type BoxFuture<'a, T> = std::pin::Pin<Box<dyn std::future::Future<Output = T> + Send + 'a>>;
+
+/// Trait for an async HTTP client used by the [`nonblocking`] API.
+///
+/// The same rules as for [`HttpClient`] apply: error status codes are `Ok` responses and
+/// `Err` is for transport errors.
+///
+/// [`nonblocking`]: ../nonblocking/index.html
+/// [`HttpClient`]: trait.HttpClient.html
+#[cfg(feature = "async")]
+pub trait AsyncHttpClient: Send + Sync {
+    /// Send the request and read the entire response.
+    fn request<'a>(&'a self, req: HttpRequest<'a>) -> BoxFuture<'a, Result<HttpResponse>>;
+}
+
+/// The default [`AsyncHttpClient`] backed by [reqwest](https://docs.rs/reqwest/).
+///
+/// [`AsyncHttpClient`]: trait.AsyncHttpClient.html
+#[cfg(feature = "async")]
+#[derive(Clone)]
+pub struct ReqwestClient {
+    client: reqwest::Client,
+}
+
+#[cfg(feature = "async")]
+impl ReqwestClient {
+    /// Client with the default timeout, trusting the webpki roots.
+    pub fn new() -> Result<Self> {
+        Self::with_roots(&[], DEFAULT_TIMEOUT)
+    }
+
+    /// Client with the given timeout that trusts the webpki roots and in addition the
+    /// given DER encoded root certificates.
+    pub fn with_roots(roots: &[Vec<u8>], timeout: Duration) -> Result<Self> {
+        let config = tls_config(roots)?;
+        let client = reqwest::Client::builder()
+            .use_preconfigured_tls(config)
+            .timeout(timeout)
+            .build()
+            .map_err(|e| Error::Call(e.to_string()))?;
+        Ok(Self::with_client(client))
+    }
+
+    /// Client using an already configured reqwest client (for proxies, mTLS etc).
+    pub fn with_client(client: reqwest::Client) -> Self {
+        ReqwestClient { client }
+    }
+}
+
+#[cfg(feature = "async")]
+impl AsyncHttpClient for ReqwestClient {
+    fn request<'a>(&'a self, req: HttpRequest<'a>) -> BoxFuture<'a, Result<HttpResponse>> {
+        Box::pin(async move {
+            let method = match req.method {
+                Method::Get => reqwest::Method::GET,
+                Method::Head => reqwest::Method::HEAD,
+                Method::Post => reqwest::Method::POST,
+            };
+            let mut r = self.client.request(method, req.url);
+            if let Some(content_type) = req.content_type {
+                r = r.header("content-type", content_type);
+            }
+            if req.method == Method::Post {
+                r = r.body(req.body.to_vec());
+            }
+
+            let res = r.send().await.map_err(|e| Error::Call(e.to_string()))?;
+
+            let status = res.status().as_u16();
+            let headers = res
+                .headers()
+                .iter()
+                .filter_map(|(n, v)| v.to_str().ok().map(|v| (n.to_string(), v.to_string())))
+                .collect();
+            // like for ureq, a body cut short is still used for what it's worth.
+            let body = res.bytes().await.map(|b| b.to_vec()).unwrap_or_default();
+
+            Ok(HttpResponse::new(status, headers, body))
+        })
+    }
+}
+
+/// Read all certificates in a PEM as DER.
+pub(crate) fn certificates_from_pem(pem: &[u8]) -> Result<Vec<Vec<u8>>> {
+    let certs = CertificateDer::pem_slice_iter(pem)
+        .map(|c| c.map(|c| c.to_vec()))
+        .collect::<std::result::Result<Vec<_>, _>>()
+        .map_err(|e| format!("Failed to read PEM certificates: {}", e))?;
+    if certs.is_empty() {
+        return Err("No certificates in PEM".into());
+    }
+    Ok(certs)
+}
@@ -1,6 +1,6 @@
 use serde::{Deserialize, Serialize};
 
-use crate::{acc::AcmeKey, cert::EC_GROUP_P256, util::base64url};
+use crate::util::base64url;
 
 #[derive(Debug, Serialize, Deserialize, Default)]
 pub(crate) struct JwsProtected {
@@ -16,7 +16,7 @@ pub(crate) struct JwsProtected {
 impl JwsProtected {
     pub(crate) fn new_jwk(jwk: Jwk, url: &str, nonce: String) -> Self {
         JwsProtected {
-            alg: "ES256".into(),
+            alg: jwk.alg.clone(),
             url: url.into(),
             nonce,
             jwk: Some(jwk),
@@ -34,53 +34,104 @@ impl JwsProtected {
     }
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
-pub(crate) struct Jwk {
+/// Public key of a [`JwsSigner`] as a JSON Web Key.
+///
+/// [`JwsSigner`]: trait.JwsSigner.html
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct Jwk {
     alg: String,
-    crv: String,
     kty: String,
     #[serde(rename = "use")]
     _use: String,
-    x: String,
-    y: String,
+    #[serde(flatten)]
+    key: JwkKey,
+}
+
+/// The parameters of the key type.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(untagged)]
+enum JwkKey {
+    Ec { crv: String, x: String, y: String },
+    Rsa { n: String, e: String },
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(untagged)]
 // LEXICAL ORDER OF FIELDS MATTER!
-pub(crate) struct JwkThumb {
-    crv: String,
-    kty: String,
-    x: String,
-    y: String,
+pub(crate) enum JwkThumb {
+    Ec {
+        crv: String,
+        kty: String,
+        x: String,
+        y: String,
+    },
+    Rsa {
+        e: String,
+        kty: String,
+        n: String,
+    },
 }
 
-impl From<&AcmeKey> for Jwk {
-    fn from(a: &AcmeKey) -> Self {
-        let mut ctx = openssl::bn::BigNumContext::new().expect("BigNumContext");
-        let mut x = openssl::bn::BigNum::new().expect("BigNum");
-        let mut y = openssl::bn::BigNum::new().expect("BigNum");
-        a.private_key()
-            .public_key()
-            .affine_coordinates_gfp(&EC_GROUP_P256, &mut x, &mut y, &mut ctx)
-            .expect("affine_coordinates_gfp");
+impl Jwk {
+    /// JWK of a P-256 public key for `ES256` signatures from the 32 bytes big endian
+    /// affine coordinates.
+    pub fn p256(x: &[u8], y: &[u8]) -> Jwk {
+        Jwk::ec("ES256", "P-256", x, y)
+    }
+
+    /// JWK of a P-384 public key for `ES384` signatures from the 48 bytes big endian
+    /// affine coordinates.
+    pub(crate) fn p384(x: &[u8], y: &[u8]) -> Jwk {
+        Jwk::ec("ES384", "P-384", x, y)
+    }
+
+    fn ec(alg: &str, crv: &str, x: &[u8], y: &[u8]) -> Jwk {
         Jwk {
-            alg: "ES256".into(),
+            alg: alg.into(),
             kty: "EC".into(),
-            crv: "P-256".into(),
             _use: "sig".into(),
-            x: base64url(&x.to_vec()),
-            y: base64url(&y.to_vec()),
+            key: JwkKey::Ec {
+                crv: crv.into(),
+                x: base64url(x),
+                y: base64url(y),
+            },
+        }
+    }
+
+    /// JWK of an RSA public key for `RS256` signatures from the big endian modulus
+    /// and exponent.
+    pub(crate) fn rsa(n: &[u8], e: &[u8]) -> Jwk {
+        Jwk {
+            alg: "RS256".into(),
+            kty: "RSA".into(),
+            _use: "sig".into(),
+            key: JwkKey::Rsa {
+                n: base64url(n),
+                e: base64url(e),
+            },
         }
     }
+
+    /// The JWS algorithm, i.e. `ES256`.
+    pub fn alg(&self) -> &str {
+        &self.alg
+    }
 }
 
 impl From<&Jwk> for JwkThumb {
     fn from(a: &Jwk) -> Self {
-        JwkThumb {
-            crv: a.crv.clone(),
-            kty: a.kty.clone(),
-            x: a.x.clone(),
-            y: a.y.clone(),
+        match &a.key {
+            JwkKey::Ec { crv, x, y } => JwkThumb::Ec {
+                crv: crv.clone(),
+                kty: a.kty.clone(),
+                x: x.clone(),
+                y: y.clone(),
+            },
+            JwkKey::Rsa { n, e } => JwkThumb::Rsa {
+                e: e.clone(),
+                kty: a.kty.clone(),
+                n: n.clone(),
+            },
         }
     }
 }
@@ -53,7 +53,7 @@
 //!     // certificate for:
 //!     //
 //!     // http://mydomain.io/.well-known/acme-challenge/<token>
-//!     let chall = auths[0].http_challenge();
+//!     let chall = auths[0].http_challenge()?;
 //!
 //!     // The token is the filename.
 //!     let token = chall.http_token();
@@ -83,7 +83,7 @@
 //! // Ownership is proven. Create a private key for
 //! // the certificate. These are provided for convenience, you
 //! // can provide your own keypair instead if you want.
-//! let pkey_pri = create_p384_key();
+//! let pkey_pri = create_p384_key()?;
 //!
 //! // Submit the CSR. This causes the ACME provider to enter a
 //! // state of "processing" that must be polled until the
@@ -117,18 +117,24 @@
 //! be part of the certificate. The ACME API requires you to prove ownership of each such
 //! domain. See [`authorizations`].
 //!
+//! For orders with many domains, [`validate_authorizations`] fetches, triggers and polls
+//! all authorizations together and reports the outcome per domain.
+//!
 //! [`http_challenge`]: https://docs.rs/acme-lib/latest/acme_lib/order/struct.Auth.html#method.http_challenge
 //! [`dns_challenge`]: https://docs.rs/acme-lib/latest/acme_lib/order/struct.Auth.html#method.dns_challenge
 //! [`authorizations`]: https://docs.rs/acme-lib/latest/acme_lib/order/struct.NewOrder.html#method.authorizations
+//! [`validate_authorizations`]: https://docs.rs/acme-lib/latest/acme_lib/order/struct.NewOrder.html#method.validate_authorizations
 //!
 //! ## Rate limits
 //!
 //! The ACME API provider Let's Encrypt uses [rate limits] to ensure the API i not being
-//! abused. It might be tempting to put the `delay_millis` really low in some of this
+//! abused. It might be tempting to put the delays really low in some of this
 //! libraries' polling calls, but balance this against the real risk of having access
-//! cut off.
+//! cut off. The polling calls take a [`PollPolicy`] with exponential backoff, and always
+//! respect a `Retry-After` sent by the ACME API provider.
 //!
 //! [rate limits]: https://letsencrypt.org/docs/rate-limits/
+//! [`PollPolicy`]: struct.PollPolicy.html
 //!
 //! ### Use staging for dev!
 //!
@@ -141,36 +147,131 @@
 //!
 //! ## Implementation details
 //!
-//! The library tries to pull in as few dependencies as possible. (For now) that means using
-//! synchronous I/O and blocking cals. This doesn't rule out a futures based version later.
+//! The default API uses synchronous I/O and blocking calls. The ACME API is called with
+//! [ureq] over rustls, trusting the webpki roots, and those crates are always compiled in.
+//! A custom HTTP client replaces ureq at runtime, but doesn't remove the dependencies.
+//!
+//! [ureq]: https://docs.rs/ureq/
+//!
+//! With the `async` cargo feature, the [`nonblocking`] module offers the same API with
+//! `async fn`s, backed by reqwest and tokio timers.
+//!
+//! [`nonblocking`]: nonblocking/index.html
 //!
 //! It is written by following the
-//! [ACME draft spec 18](https://tools.ietf.org/html/draft-ietf-acme-acme-18), and relies
-//! heavily on the [openssl](https://docs.rs/openssl/) crate to make JWK/JWT and sign requests
-//! to the API.
+//! [ACME draft spec 18](https://tools.ietf.org/html/draft-ietf-acme-acme-18). By default it
+//! relies on the [openssl](https://docs.rs/openssl/) crate to make JWK/JWT and sign requests
+//! to the API. For static musl builds or cross-compilation, disable the default features and
+//! enable `rustcrypto` to use pure Rust crates (p256, p384, rsa and x509-cert) instead:
+//!
+//! ```toml
+//! acme-lib = { version = "0.8", default-features = false, features = ["rustcrypto"] }
+//! ```
+//!
+//! The public types are the same with either backend. Keys for certificates are
+//! [`PrivateKey`], which with openssl also converts from a `PKey<Private>`.
+//!
+//! [`PrivateKey`]: struct.PrivateKey.html
+//!
+//! The account key can be kept outside the library with a [`JwsSigner`], for example in an
+//! HSM. The `pkcs11` feature adds a signer for PKCS#11 tokens.
+//!
+//! [`JwsSigner`]: signer/trait.JwsSigner.html
+//!
+//! The `encryption` feature adds [`EncryptedPersist`], which encrypts the private keys
+//! before they reach any persistence.
+//!
+//! [`EncryptedPersist`]: persist/struct.EncryptedPersist.html
+//!
+//! The `sqlite` feature adds [`SqlitePersist`], which keeps everything in one table of a
+//! SQLite database and can list the certificates expiring soon.
+//!
+//! [`SqlitePersist`]: persist/struct.SqlitePersist.html
+//!
+//! To switch from certbot without changing web server configurations, [`CertbotExport`]
+//! writes certificates in its `live/` and `archive/` directory layout. A [`Certificate`]
+//! also converts to a password protected PKCS#12, to DER and to the single PEM file of
+//! key and chain that haproxy reads.
+//!
+//! [`CertbotExport`]: export/struct.CertbotExport.html
+//! [`Certificate`]: struct.Certificate.html
+//!
+//! Downloaded certificates are checked before they are saved: the key must match, the
+//! chain must link up and the names of the order must be covered. [`Certificate::verify`]
+//! does the same checks on demand, optionally against a set of trusted roots.
+//!
+//! [`Certificate::verify`]: struct.Certificate.html#method.verify
+//!
+//! [`Account::ocsp_response`] fetches the OCSP response of a saved certificate from the
+//! responder in the certificate, verifies it and persists it for stapling until halfway to
+//! its next update. The status tells whether the certificate has been revoked.
+//!
+//! [`Account::ocsp_response`]: struct.Account.html#method.ocsp_response
+//!
+//! A certificate can be revoked by the account that ordered it, or with
+//! [`Directory::revoke_certificate_with_key`] by whoever holds its private key, i.e. when
+//! the account key is lost. The account records revocations in the persistence, as it does
+//! revoked OCSP statuses, and [`Certificate::is_revoked`] then tells the certificate must be
+//! replaced.
+//!
+//! [`Directory::revoke_certificate_with_key`]: struct.Directory.html#method.revoke_certificate_with_key
+//! [`Certificate::is_revoked`]: struct.Certificate.html#method.is_revoked
+//!
+//! [`RenewalManager`] keeps a set of certificates fresh. Each [`CertSpec`] names the
+//! domains, the key type and a [`ChallengeSolver`] that places the proofs. Certificates are
+//! renewed ahead of expiry with jitter, or in the window of the CA's renewal information
+//! (ARI). Failed renewals back off per certificate, and callbacks report every result.
+//!
+//! [`RenewalManager`]: renew/struct.RenewalManager.html
+//! [`CertSpec`]: renew/struct.CertSpec.html
+//! [`ChallengeSolver`]: renew/trait.ChallengeSolver.html
+//!
+//! [`DeployHooks`] run when a new certificate is saved, by [`download_and_deploy_cert`] or
+//! the renewal manager: callbacks and external commands that get the names and, with an
+//! export, the file paths in environment variables. Hooks only run when the certificate
+//! changed, and their failures are reported while the certificate stays saved.
+//!
+//! [`DeployHooks`]: deploy/struct.DeployHooks.html
+//! [`download_and_deploy_cert`]: order/struct.CertOrder.html#method.download_and_deploy_cert
 //!
 #[macro_use]
 extern crate log;
 
 mod acc;
 mod cert;
+mod crypto;
 mod dir;
 mod error;
 mod jwt;
+mod ocsp;
+mod poll;
 mod req;
 mod trans;
 mod util;
 
 pub mod api;
+pub mod deploy;
+pub mod export;
+pub mod http;
 pub mod order;
 pub mod persist;
+pub mod renew;
+pub mod signer;
+
+#[cfg(feature = "async")]
+pub mod nonblocking;
 
 #[cfg(test)]
 mod test;
 
 pub use crate::{
-    acc::{Account, RevocationReason},
-    cert::{create_p256_key, create_p384_key, create_rsa_key, Certificate},
-    dir::{Directory, DirectoryUrl},
-    error::{Error, Result},
+    acc::{Account, RevocationOutcome, RevocationReason},
+    cert::{
+        create_p256_key, create_p384_key, create_rsa_key, Certificate, CertificateInfo,
+        CertificateVersion, KeyAlgorithm, PrivateKey, Revocation, VerifyOptions,
+    },
+    dir::{Directory, DirectoryBuilder, DirectoryUrl},
+    error::{Error, ProblemType, Result},
+    ocsp::{OcspResponse, OcspStatus},
+    poll::PollPolicy,
 };
@@ -0,0 +1,538 @@
+//! Async API (requires the `async` feature).
+//!
+//! Mirrors the blocking API with the same flow and names, but every call against the ACME
+//! API is an `async fn`. The HTTP calls go through an [`AsyncHttpClient`] (by default
+//! [`ReqwestClient`]) and the polling waits with tokio timers instead of blocking the thread.
+//!
+//! The persistence is still the synchronous [`Persist`] trait. Its calls block the task,
+//! but its locks are never held across a request to the ACME API.
+//!
+//! ```no_run
+//! use acme_lib::nonblocking::Directory;
+//! use acme_lib::persist::FilePersist;
+//! use acme_lib::{create_p384_key, DirectoryUrl, Error};
+//!
+//! async fn request_cert() -> Result<(), Error> {
+//!     let url = DirectoryUrl::LetsEncrypt;
+//!     let dir = Directory::from_url(FilePersist::new("."), url).await?;
+//!     let acc = dir.account("foo@bar.com").await?;
+//!     let mut ord_new = acc.new_order("mydomain.io", &[]).await?;
+//!
+//!     let ord_csr = loop {
+//!         if let Some(ord_csr) = ord_new.confirm_validations() {
+//!             break ord_csr;
+//!         }
+//!         let auths = ord_new.authorizations().await?;
+//!         let chall = auths[0].http_challenge()?;
+//!         // update_my_web_server(chall.http_token(), &chall.http_proof()).await;
+//!         chall.validate(5000).await?;
+//!         ord_new.refresh().await?;
+//!     };
+//!
+//!     let ord_cert = ord_csr.finalize_pkey(create_p384_key()?, 5000).await?;
+//!     let cert = ord_cert.download_and_save_cert().await?;
+//!     Ok(())
+//! }
+//! ```
+//!
+//! [`AsyncHttpClient`]: ../http/trait.AsyncHttpClient.html
+//! [`ReqwestClient`]: ../http/struct.ReqwestClient.html
+//! [`Persist`]: ../persist/trait.Persist.html
+use std::{sync::Arc, time::Duration};
+
+use crate::{
+    acc::{
+        activate_certificate, cached_ocsp_response, certificate_history, list_certificates,
+        load_certificate, new_order_body, ocsp_url, record_outcome, remove_certificate,
+        revocation_body, revocation_outcome, save_ocsp_response, AcmeKey,
+    },
+    api::{ApiAccount, ApiDirectory, ApiOrder},
+    dir::{check_history_support, load_acme_key, new_account, save_acme_key},
+    http::{certificates_from_pem, AsyncHttpClient, Method, ReqwestClient, DEFAULT_TIMEOUT},
+    persist::{Persist, PersistKey, PersistKind, ACCOUNT_KEY},
+    renew::{renewal_info_of, renewal_info_url, RenewalInfo},
+    req::{check_status, ExtractHeader},
+    signer::JwsSigner,
+    trans::{jws_with_cert_key, NoncePool},
+    util::read_json,
+    Certificate, CertificateVersion, DirectoryUrl, OcspResponse, Result, RevocationOutcome,
+    RevocationReason,
+};
+
+mod order;
+mod trans;
+
+pub use self::order::{Auth, CertOrder, Challenge, CsrOrder, NewOrder};
+
+use self::order::Order;
+use self::trans::{call_signed, get_or_head, post_ocsp, Transport};
+
+/// Async entry point for accessing an ACME API.
+///
+/// See the blocking [`Directory`](../struct.Directory.html) for details.
+#[derive(Clone)]
+pub struct Directory<P: Persist> {
+    persist: P,
+    http: Arc<dyn AsyncHttpClient>,
+    nonce_pool: Arc<NoncePool>,
+    api_directory: ApiDirectory,
+    history: usize,
+}
+
+impl<P: Persist> Directory<P> {
+    /// Create a directory over a persistence implementation and directory url.
+    ///
+    /// This uses the default HTTP client. See [`builder`] for more options.
+    ///
+    /// [`builder`]: struct.Directory.html#method.builder
+    pub async fn from_url(persist: P, url: DirectoryUrl<'_>) -> Result<Directory<P>> {
+        Directory::builder(persist, url).build().await
+    }
+
+    /// Builder for a directory with a custom HTTP client, trust roots or timeout.
+    pub fn builder(persist: P, url: DirectoryUrl) -> DirectoryBuilder<P> {
+        DirectoryBuilder {
+            persist,
+            url,
+            http: None,
+            trust_roots_pem: vec![],
+            timeout: DEFAULT_TIMEOUT,
+            history: 0,
+        }
+    }
+
+    async fn with_http(
+        persist: P,
+        url: DirectoryUrl<'_>,
+        http: Arc<dyn AsyncHttpClient>,
+    ) -> Result<Self> {
+        let res = check_status(get_or_head(&*http, Method::Get, url.to_url()).await?)?;
+        let api_directory: ApiDirectory = read_json(res)?;
+        let nonce_pool = Arc::new(NoncePool::new(&api_directory.newNonce));
+        Ok(Directory {
+            persist,
+            http,
+            nonce_pool,
+            api_directory,
+            history: 0,
+        })
+    }
+
+    /// Access an account identified by a contact email.
+    ///
+    /// See the blocking [`Directory::account`](../struct.Directory.html#method.account).
+    pub async fn account(&self, contact_email: &str) -> Result<Account<P>> {
+        let contact = vec![format!("mailto:{}", contact_email)];
+        self.account_with_realm(contact_email, Some(contact)).await
+    }
+
+    /// Access an account using a lower level method.
+    ///
+    /// See the blocking
+    /// [`Directory::account_with_realm`](../struct.Directory.html#method.account_with_realm).
+    pub async fn account_with_realm(
+        &self,
+        realm: &str,
+        contact: Option<Vec<String>>,
+    ) -> Result<Account<P>> {
+        self.account_with_key(realm, contact, None).await
+    }
+
+    /// Access an account where the account key is held by an external signer.
+    ///
+    /// See the blocking
+    /// [`Directory::account_with_signer`](../struct.Directory.html#method.account_with_signer).
+    pub async fn account_with_signer(
+        &self,
+        realm: &str,
+        contact: Option<Vec<String>>,
+        signer: impl JwsSigner + 'static,
+    ) -> Result<Account<P>> {
+        let acme_key = AcmeKey::from_signer(Arc::new(signer))?;
+        self.account_with_key(realm, contact, Some(acme_key)).await
+    }
+
+    async fn account_with_key(
+        &self,
+        realm: &str,
+        contact: Option<Vec<String>>,
+        acme_key: Option<AcmeKey>,
+    ) -> Result<Account<P>> {
+        // Serializes creating the account key with other processes, so they don't both
+        // create one. Unlike the blocking API, a new key is saved before it's registered,
+        // so the lock isn't held across the request. An unregistered saved key is
+        // registered by the next call.
+        let acme_key = {
+            let lock_key = PersistKey::new(realm, PersistKind::AccountPrivateKey, ACCOUNT_KEY);
+            let _lock = self.persist.lock(&lock_key)?;
+
+            let migrated = self.persist.migrate_realm(realm)?;
+            if migrated > 0 {
+                info!("Migrated {} persisted values of realm {}", migrated, realm);
+            }
+
+            match acme_key {
+                Some(acme_key) => acme_key,
+                None => {
+                    let (acme_key, is_new) = load_acme_key(&self.persist, realm)?;
+                    if is_new {
+                        save_acme_key(&self.persist, realm, &acme_key)?;
+                    }
+                    acme_key
+                }
+            }
+        };
+
+        let acc = new_account(contact);
+
+        let mut transport = Transport::new(&self.http, &self.nonce_pool, acme_key);
+        let res = transport
+            .call_jwk(&self.api_directory.newAccount, &acc)
+            .await?;
+        let kid = res.extract_header("location")?;
+        debug!("Key id is: {}", kid);
+        let api_account: ApiAccount = read_json(res)?;
+
+        transport.set_key_id(kid);
+
+        Ok(Account {
+            inner: Arc::new(AccountInner {
+                persist: self.persist.clone(),
+                transport,
+                realm: realm.to_string(),
+                api_account,
+                api_directory: self.api_directory.clone(),
+                history: self.history,
+            }),
+        })
+    }
+
+    /// Revoke a certificate for the reason given, signing the request with the private
+    /// key of the certificate instead of an account key.
+    ///
+    /// See the blocking
+    /// [`Directory::revoke_certificate_with_key`](../struct.Directory.html#method.revoke_certificate_with_key).
+    pub async fn revoke_certificate_with_key(
+        &self,
+        cert: &Certificate,
+        reason: RevocationReason,
+    ) -> Result<RevocationOutcome> {
+        let key = cert.key_pair()?;
+        let revoc = revocation_body(cert, reason)?;
+
+        let url = &self.api_directory.revokeCert;
+        revocation_outcome(
+            call_signed(&*self.http, &self.nonce_pool, url, |nonce| {
+                jws_with_cert_key(url, nonce, &key, &revoc)
+            })
+            .await,
+        )
+    }
+
+    /// Access the underlying JSON object for debugging.
+    pub fn api_directory(&self) -> &ApiDirectory {
+        &self.api_directory
+    }
+}
+
+/// Builder for an async [`Directory`] created by [`Directory::builder`].
+///
+/// [`Directory`]: struct.Directory.html
+/// [`Directory::builder`]: struct.Directory.html#method.builder
+pub struct DirectoryBuilder<'a, P: Persist> {
+    persist: P,
+    url: DirectoryUrl<'a>,
+    http: Option<Arc<dyn AsyncHttpClient>>,
+    trust_roots_pem: Vec<Vec<u8>>,
+    timeout: Duration,
+    history: usize,
+}
+
+impl<'a, P: Persist> DirectoryBuilder<'a, P> {
+    /// Use a custom HTTP client for all calls to the ACME API.
+    ///
+    /// When set, the [`trust_root_pem`] and [`timeout`] settings are not used
+    /// since they configure the default client.
+    ///
+    /// [`trust_root_pem`]: struct.DirectoryBuilder.html#method.trust_root_pem
+    /// [`timeout`]: struct.DirectoryBuilder.html#method.timeout
+    pub fn http_client<H: AsyncHttpClient + 'static>(mut self, http: H) -> Self {
+        self.http = Some(Arc::new(http));
+        self
+    }
+
+    /// Trust the root certificate(s) in the PEM in addition to the default
+    /// webpki roots. Typically used for private ACME servers.
+    pub fn trust_root_pem(mut self, pem: &[u8]) -> Self {
+        self.trust_roots_pem.push(pem.to_vec());
+        self
+    }
+
+    /// Timeout for each call to the ACME API. Defaults to 30 seconds.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Keep the last number of saved certificates. See the blocking
+    /// [`DirectoryBuilder::certificate_history`](../struct.DirectoryBuilder.html#method.certificate_history).
+    pub fn certificate_history(mut self, versions: usize) -> Self {
+        self.history = versions;
+        self
+    }
+
+    /// Create the directory. This reads the directory from the ACME API.
+    pub async fn build(self) -> Result<Directory<P>> {
+        check_history_support(&self.persist, self.history)?;
+        let http: Arc<dyn AsyncHttpClient> = match self.http {
+            Some(http) => http,
+            None => {
+                let mut roots = vec![];
+                for pem in &self.trust_roots_pem {
+                    roots.extend(certificates_from_pem(pem)?);
+                }
+                Arc::new(ReqwestClient::with_roots(&roots, self.timeout)?)
+            }
+        };
+        let mut dir = Directory::with_http(self.persist, self.url, http).await?;
+        dir.history = self.history;
+        Ok(dir)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct AccountInner<P: Persist> {
+    pub persist: P,
+    pub transport: Transport,
+    pub realm: String,
+    pub api_account: ApiAccount,
+    pub api_directory: ApiDirectory,
+    pub history: usize,
+}
+
+/// Async account with an ACME provider.
+///
+/// See the blocking [`Account`](../struct.Account.html) for details.
+#[derive(Clone)]
+pub struct Account<P: Persist> {
+    inner: Arc<AccountInner<P>>,
+}
+
+impl<P: Persist> Account<P> {
+    /// Private key for this account.
+    pub fn acme_private_key_pem(&self) -> Result<String> {
+        let pem = self.inner.transport.acme_key().to_pem()?;
+        Ok(String::from_utf8_lossy(&pem).to_string())
+    }
+
+    /// Get an already issued and downloaded certificate from the persistence.
+    pub fn certificate(&self, primary_name: &str) -> Result<Option<Certificate>> {
+        load_certificate(&self.inner.persist, &self.inner.realm, primary_name)
+    }
+
+    /// The primary names of all certificates in the persistence for this account.
+    pub fn certificates(&self) -> Result<Vec<String>> {
+        list_certificates(&self.inner.persist, &self.inner.realm)
+    }
+
+    /// The earlier saved versions of a certificate, oldest first.
+    ///
+    /// See the blocking
+    /// [`Account::certificate_history`](../struct.Account.html#method.certificate_history).
+    pub fn certificate_history(&self, primary_name: &str) -> Result<Vec<CertificateVersion>> {
+        certificate_history(&self.inner.persist, &self.inner.realm, primary_name)
+    }
+
+    /// Make a version from the history the current certificate again.
+    ///
+    /// See the blocking
+    /// [`Account::activate_certificate`](../struct.Account.html#method.activate_certificate).
+    pub fn activate_certificate(&self, primary_name: &str, version: u32) -> Result<Certificate> {
+        activate_certificate(
+            &self.inner.persist,
+            &self.inner.realm,
+            primary_name,
+            version,
+        )
+    }
+
+    /// The OCSP response for a saved certificate, persisted and reused until halfway to
+    /// its next update.
+    ///
+    /// See the blocking
+    /// [`Account::ocsp_response`](../struct.Account.html#method.ocsp_response).
+    pub async fn ocsp_response(&self, primary_name: &str) -> Result<OcspResponse> {
+        let persist = &self.inner.persist;
+        let realm = &self.inner.realm;
+        let (cert, cached) = cached_ocsp_response(persist, realm, primary_name)?;
+        if let Some(response) = cached {
+            return Ok(response);
+        }
+
+        let url = ocsp_url(&cert)?;
+        debug!("Fetch OCSP response: {}", url);
+        let http = self.inner.transport.http();
+        let res = check_status(post_ocsp(http, &url, &cert.ocsp_request()?).await?)?;
+        save_ocsp_response(persist, realm, primary_name, &cert, res.body())
+    }
+
+    /// The renewal window the CA suggests for a certificate.
+    ///
+    /// See the blocking
+    /// [`Account::renewal_info`](../struct.Account.html#method.renewal_info).
+    pub async fn renewal_info(&self, cert: &Certificate) -> Result<Option<RenewalInfo>> {
+        let Some(base) = &self.inner.api_directory.renewalInfo else {
+            return Ok(None);
+        };
+        let url = renewal_info_url(base, cert)?;
+        debug!("Fetch renewal info: {}", url);
+        let http = self.inner.transport.http();
+        let res = check_status(get_or_head(http, Method::Get, &url).await?)?;
+        renewal_info_of(res).map(Some)
+    }
+
+    /// Remove a certificate, its private key, OCSP response and revocation from the
+    /// persistence.
+    pub fn remove_certificate(&self, primary_name: &str) -> Result<()> {
+        remove_certificate(&self.inner.persist, &self.inner.realm, primary_name)
+    }
+
+    /// Create a new order to issue a certificate for this account.
+    ///
+    /// See the blocking [`Account::new_order`](../struct.Account.html#method.new_order).
+    pub async fn new_order(&self, primary_name: &str, alt_names: &[&str]) -> Result<NewOrder<P>> {
+        let order = new_order_body(primary_name, alt_names);
+
+        let new_order_url = &self.inner.api_directory.newOrder;
+
+        let res = self.inner.transport.call(new_order_url, &order).await?;
+        let order_url = res.extract_header("location")?;
+        let api_order: ApiOrder = read_json(res)?;
+
+        let order = Order::new(&self.inner, api_order, order_url);
+        Ok(NewOrder { order })
+    }
+
+    /// Revoke a certificate for the reason given.
+    pub async fn revoke_certificate(
+        &self,
+        cert: &Certificate,
+        reason: RevocationReason,
+    ) -> Result<RevocationOutcome> {
+        let revoc = revocation_body(cert, reason)?;
+
+        let url = &self.inner.api_directory.revokeCert;
+        let outcome = revocation_outcome(self.inner.transport.call(url, &revoc).await)?;
+
+        record_outcome(
+            &self.inner.persist,
+            &self.inner.realm,
+            cert,
+            reason,
+            &outcome,
+        )?;
+        Ok(outcome)
+    }
+
+    /// Access the underlying JSON object for debugging.
+    pub fn api_account(&self) -> &ApiAccount {
+        &self.inner.api_account
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::persist::*;
+    use std::future::Future;
+
+    fn block_on<F: Future>(f: F) -> F::Output {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(f)
+    }
+
+    #[test]
+    fn test_async_persisted_account() -> Result<()> {
+        let server = crate::test::with_directory_server();
+        block_on(async {
+            let url = DirectoryUrl::Other(&server.dir_url);
+            let dir = Directory::from_url(MemoryPersist::new(), url).await?;
+            let acc1 = dir.account("foo@bar.com").await?;
+            let acc2 = dir.account("foo@bar.com").await?;
+            assert_eq!(acc1.acme_private_key_pem()?, acc2.acme_private_key_pem()?);
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_async_whole_flow() -> Result<()> {
+        let server = crate::test::with_directory_server();
+        block_on(async {
+            let url = DirectoryUrl::Other(&server.dir_url);
+            let dir = Directory::from_url(MemoryPersist::new(), url).await?;
+            let acc = dir.account("foo@bar.com").await?;
+            let ord = acc.new_order("acmetest.example.com", &[]).await?;
+
+            let auths = ord.authorizations().await?;
+            assert_eq!(auths.len(), 1);
+            let chall = auths[0].http_challenge()?;
+            assert!(chall.need_validate());
+            chall.validate(1).await?;
+
+            // shortcut the order status
+            let ord = CsrOrder { order: ord.order };
+            let ord = ord.finalize_pkey(crate::test::example_key(), 1).await?;
+            let cert = ord.download_and_save_cert().await?;
+            assert_eq!(crate::test::EXAMPLE_CHAIN, cert.certificate());
+
+            let cert2 = acc.certificate("acmetest.example.com")?.unwrap();
+            assert_eq!(cert.private_key(), cert2.private_key());
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_async_download_and_deploy_cert() -> Result<()> {
+        let server = crate::test::with_directory_server();
+        block_on(async {
+            let url = DirectoryUrl::Other(&server.dir_url);
+            let dir = Directory::from_url(MemoryPersist::new(), url).await?;
+            let acc = dir.account("foo@bar.com").await?;
+            let hooks = crate::deploy::DeployHooks::new().callback("fail", |d| {
+                Err(format!("cannot deploy {}", d.primary_name()).into())
+            });
+
+            let ord = acc.new_order("acmetest.example.com", &[]).await?;
+            let ord = CsrOrder { order: ord.order };
+            let ord = ord.finalize_pkey(crate::test::example_key(), 1).await?;
+            let report = ord.download_and_deploy_cert(&hooks).await?;
+            assert!(report.is_changed());
+            assert_eq!(report.failures()[0].hook(), "fail");
+            assert!(acc.certificate("acmetest.example.com")?.is_some());
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_async_revoke_certificate_with_key() -> Result<()> {
+        let server = crate::test::with_directory_server();
+        block_on(async {
+            let url = DirectoryUrl::Other(&server.dir_url);
+            let dir = Directory::from_url(MemoryPersist::new(), url).await?;
+            let chain = crate::test::EXAMPLE_CHAIN.to_string();
+            let cert = Certificate::new(Some(crate::test::EXAMPLE_KEY.into()), chain.clone());
+            let reason = RevocationReason::Superseded;
+            let outcome = dir.revoke_certificate_with_key(&cert, reason).await?;
+            assert_eq!(outcome, RevocationOutcome::Revoked);
+            let external = Certificate::new(None, chain);
+            assert!(dir
+                .revoke_certificate_with_key(&external, reason)
+                .await
+                .is_err());
+            Ok(())
+        })
+    }
+}
@@ -0,0 +1,387 @@
+//
+use futures_util::future::join_all;
+use std::{marker::PhantomData, sync::Arc, time::Duration};
+
+use crate::{
+    api::{ApiAuth, ApiChallenge, ApiEmptyObject, ApiEmptyString, ApiOrder},
+    cert::{CertKey, Certificate, PrivateKey},
+    crypto::{create_external_csr, sha256},
+    deploy::{DeployHooks, DeployReport},
+    order::{
+        api_order_of, auth_result, certificate_url, check_order_valid, finalize_body,
+        key_authorization, save_certificate, verify_issued, Dns, Http, TlsAlpn,
+    },
+    persist::Persist,
+    poll::PollPolicy,
+    req::retry_after,
+    signer::CsrSigner,
+    util::read_json,
+    Error, Result,
+};
+
+use super::AccountInner;
+
+/// The order wrapped with an outer façade.
+pub(crate) struct Order<P: Persist> {
+    inner: Arc<AccountInner<P>>,
+    api_order: ApiOrder,
+    url: String,
+}
+
+impl<P: Persist> Order<P> {
+    pub(crate) fn new(inner: &Arc<AccountInner<P>>, api_order: ApiOrder, url: String) -> Self {
+        Order {
+            inner: inner.clone(),
+            api_order,
+            url,
+        }
+    }
+
+    fn duplicate(&self) -> Self {
+        Order::new(&self.inner, self.api_order.clone(), self.url.clone())
+    }
+}
+
+/// Refresh an order status (POST-as-GET), also returning the `Retry-After`.
+async fn refresh_order<P: Persist>(
+    inner: &Arc<AccountInner<P>>,
+    url: String,
+    want_status: &'static str,
+) -> Result<(Order<P>, Option<Duration>)> {
+    let res = inner.transport.call(&url, &ApiEmptyString).await?;
+    let retry_after = retry_after(&res);
+
+    // api_order_of is different for test compilation
+    let api_order = api_order_of(res, want_status)?;
+
+    Ok((Order::new(inner, api_order, url), retry_after))
+}
+
+/// Async version of [`order::NewOrder`](../order/struct.NewOrder.html).
+pub struct NewOrder<P: Persist> {
+    pub(crate) order: Order<P>,
+}
+
+impl<P: Persist> NewOrder<P> {
+    /// Tell if the domains in this order have been authorized.
+    ///
+    /// This doesn't do any calls against the API. You must manually call [`refresh`].
+    ///
+    /// [`refresh`]: struct.NewOrder.html#method.refresh
+    pub fn is_validated(&self) -> bool {
+        self.order.api_order.is_status_ready() || self.order.api_order.is_status_valid()
+    }
+
+    /// If the order [`is_validated`] progress it to a [`CsrOrder`].
+    ///
+    /// [`is_validated`]: struct.NewOrder.html#method.is_validated
+    /// [`CsrOrder`]: struct.CsrOrder.html
+    pub fn confirm_validations(&self) -> Option<CsrOrder<P>> {
+        if self.is_validated() {
+            Some(CsrOrder {
+                order: self.order.duplicate(),
+            })
+        } else {
+            None
+        }
+    }
+
+    /// Refresh the order state against the ACME API.
+    pub async fn refresh(&mut self) -> Result<()> {
+        let url = self.order.url.clone();
+        let (order, _) = refresh_order(&self.order.inner, url, "ready").await?;
+        self.order = order;
+        Ok(())
+    }
+
+    /// Provide the authorizations, one per domain in the order.
+    ///
+    /// The authorizations are fetched concurrently.
+    pub async fn authorizations(&self) -> Result<Vec<Auth<P>>> {
+        let auth_urls = match &self.order.api_order.authorizations {
+            Some(a) => &a[..],
+            None => &[],
+        };
+        let transport = &self.order.inner.transport;
+        let api_auths = join_all(auth_urls.iter().map(|auth_url| async move {
+            let res = transport.call(auth_url, &ApiEmptyString).await?;
+            read_json::<ApiAuth>(res)
+        }))
+        .await;
+        let mut result = vec![];
+        for (auth_url, api_auth) in auth_urls.iter().zip(api_auths) {
+            result.push(Auth {
+                inner: self.order.inner.clone(),
+                api_auth: api_auth?,
+                auth_url: auth_url.clone(),
+            });
+        }
+        Ok(result)
+    }
+
+    /// Access the underlying JSON object for debugging.
+    pub fn api_order(&self) -> &ApiOrder {
+        &self.order.api_order
+    }
+}
+
+/// Async version of [`order::CsrOrder`](../order/struct.CsrOrder.html).
+pub struct CsrOrder<P: Persist> {
+    pub(crate) order: Order<P>,
+}
+
+impl<P: Persist> CsrOrder<P> {
+    /// Finalize the order by providing a private key as PEM.
+    ///
+    /// See [`finalize_pkey`].
+    ///
+    /// [`finalize_pkey`]: struct.CsrOrder.html#method.finalize_pkey
+    pub async fn finalize(
+        self,
+        private_key_pem: &str,
+        poll: impl Into<PollPolicy>,
+    ) -> Result<CertOrder<P>> {
+        let pkey_pri = PrivateKey::from_pem(private_key_pem.as_bytes())?;
+        self.finalize_pkey(pkey_pri, poll).await
+    }
+
+    /// Submit the CSR for the domains in the order and poll, without blocking,
+    /// until the order is no longer `processing`.
+    pub async fn finalize_pkey(
+        self,
+        private_key: impl Into<PrivateKey>,
+        poll: impl Into<PollPolicy>,
+    ) -> Result<CertOrder<P>> {
+        let private_key = private_key.into();
+        let csr = private_key.0.create_csr(&self.order.api_order.domains())?;
+        self.finalize_csr(csr, CertKey::Private(private_key), poll.into())
+            .await
+    }
+
+    /// Finalize with the CSR signed by a private key held externally.
+    ///
+    /// See the blocking
+    /// [`CsrOrder::finalize_signer`](../order/struct.CsrOrder.html#method.finalize_signer).
+    pub async fn finalize_signer(
+        self,
+        signer: &dyn CsrSigner,
+        poll: impl Into<PollPolicy>,
+    ) -> Result<CertOrder<P>> {
+        let public_key_der = signer.public_key_der()?;
+        let domains = self.order.api_order.domains();
+        let csr = create_external_csr(signer, &public_key_der, &domains)?;
+        self.finalize_csr(csr, CertKey::External(public_key_der), poll.into())
+            .await
+    }
+
+    async fn finalize_csr(
+        self,
+        csr_der: Vec<u8>,
+        key: CertKey,
+        poll: PollPolicy,
+    ) -> Result<CertOrder<P>> {
+        let finalize = finalize_body(&csr_der);
+
+        let inner = self.order.inner;
+        let order_url = self.order.url;
+        let finalize_url = &self.order.api_order.finalize;
+
+        inner.transport.call(finalize_url, &finalize).await?;
+
+        let mut poller = poll.start("order status");
+        let order = loop {
+            let (order, retry_after) = refresh_order(&inner, order_url.clone(), "valid").await?;
+            if !order.api_order.is_status_processing() {
+                break order;
+            }
+            poller.wait_async(retry_after).await?;
+        };
+        check_order_valid(&order.api_order)?;
+
+        Ok(CertOrder { key, order })
+    }
+
+    /// Access the underlying JSON object for debugging.
+    pub fn api_order(&self) -> &ApiOrder {
+        &self.order.api_order
+    }
+}
+
+/// Async version of [`order::CertOrder`](../order/struct.CertOrder.html).
+pub struct CertOrder<P: Persist> {
+    key: CertKey,
+    order: Order<P>,
+}
+
+impl<P: Persist> CertOrder<P> {
+    /// Request download of the issued certificate and save it with the key
+    /// in the persistence.
+    pub async fn download_and_save_cert(self) -> Result<Certificate> {
+        self.download_and_save().await.map(|(_, cert, _)| cert)
+    }
+
+    /// Download and save the issued certificate, then run the hooks if it changed.
+    /// The hooks block the task while they run.
+    ///
+    /// See the blocking [`CertOrder::download_and_deploy_cert`](../order/struct.CertOrder.html#method.download_and_deploy_cert).
+    pub async fn download_and_deploy_cert(self, hooks: &DeployHooks) -> Result<DeployReport> {
+        let (primary_name, cert, changed) = self.download_and_save().await?;
+        Ok(hooks.deploy_if_changed(&primary_name, cert, changed))
+    }
+
+    async fn download_and_save(self) -> Result<(String, Certificate, bool)> {
+        let primary_name = self
+            .order
+            .api_order
+            .domains()
+            .first()
+            .ok_or("Order has no identifiers")?
+            .to_string();
+        let url = certificate_url(&self.order.api_order)?;
+        let inner = self.order.inner;
+
+        let res = inner.transport.call(&url, &ApiEmptyString).await?;
+        let cert = res.into_string()?;
+        verify_issued(&self.key, &cert, &self.order.api_order.domains())?;
+
+        let (cert, changed) = save_certificate(
+            &inner.persist,
+            &inner.realm,
+            &primary_name,
+            &self.key,
+            cert,
+            inner.history,
+        )?;
+        Ok((primary_name, cert, changed))
+    }
+
+    /// Access the underlying JSON object for debugging.
+    pub fn api_order(&self) -> &ApiOrder {
+        &self.order.api_order
+    }
+}
+
+/// Async version of [`order::Auth`](../order/struct.Auth.html).
+#[derive(Debug)]
+pub struct Auth<P: Persist> {
+    inner: Arc<AccountInner<P>>,
+    api_auth: ApiAuth,
+    auth_url: String,
+}
+
+impl<P: Persist> Auth<P> {
+    /// Domain name for this authorization.
+    pub fn domain_name(&self) -> &str {
+        &self.api_auth.identifier.value
+    }
+
+    /// Whether we actually need to do the authorization.
+    pub fn need_challenge(&self) -> bool {
+        !self.api_auth.is_status_valid()
+    }
+
+    /// Get the http challenge.
+    pub fn http_challenge(&self) -> Result<Challenge<P, Http>> {
+        self.challenge(self.api_auth.http_challenge(), "http-01")
+    }
+
+    /// Get the dns challenge.
+    pub fn dns_challenge(&self) -> Result<Challenge<P, Dns>> {
+        self.challenge(self.api_auth.dns_challenge(), "dns-01")
+    }
+
+    /// Get the TLS ALPN challenge.
+    pub fn tls_alpn_challenge(&self) -> Result<Challenge<P, TlsAlpn>> {
+        self.challenge(self.api_auth.tls_alpn_challenge(), "tls-alpn-01")
+    }
+
+    fn challenge<A>(&self, c: Option<&ApiChallenge>, name: &str) -> Result<Challenge<P, A>> {
+        let c = c.ok_or_else(|| Error::ChallengeNotOffered(name.into()))?;
+        Ok(Challenge {
+            inner: self.inner.clone(),
+            api_challenge: c.clone(),
+            auth_url: self.auth_url.clone(),
+            _ph: PhantomData,
+        })
+    }
+
+    /// Access the underlying JSON object for debugging.
+    pub fn api_auth(&self) -> &ApiAuth {
+        &self.api_auth
+    }
+}
+
+/// Async version of [`order::Challenge`](../order/struct.Challenge.html).
+pub struct Challenge<P: Persist, A> {
+    inner: Arc<AccountInner<P>>,
+    api_challenge: ApiChallenge,
+    auth_url: String,
+    _ph: PhantomData<A>,
+}
+
+impl<P: Persist> Challenge<P, Http> {
+    /// The `token` is a unique identifier of the challenge. It is the file name in the
+    /// http challenge.
+    pub fn http_token(&self) -> &str {
+        &self.api_challenge.token
+    }
+
+    /// The `proof` is the contents of the file at the token path.
+    pub fn http_proof(&self) -> String {
+        let acme_key = self.inner.transport.acme_key();
+        key_authorization(&self.api_challenge.token, acme_key, false)
+    }
+}
+
+impl<P: Persist> Challenge<P, Dns> {
+    /// The `proof` is the `TXT` record for `_acme-challenge.<domain-to-be-proven>.`.
+    pub fn dns_proof(&self) -> String {
+        let acme_key = self.inner.transport.acme_key();
+        key_authorization(&self.api_challenge.token, acme_key, true)
+    }
+}
+
+impl<P: Persist> Challenge<P, TlsAlpn> {
+    /// The `proof` is the contents of the ACME extension of the self-signed certificate.
+    pub fn tls_alpn_proof(&self) -> [u8; 32] {
+        let acme_key = self.inner.transport.acme_key();
+        sha256(key_authorization(&self.api_challenge.token, acme_key, false).as_bytes())
+    }
+}
+
+impl<P: Persist, A> Challenge<P, A> {
+    /// Check whether this challenge really need validation.
+    pub fn need_validate(&self) -> bool {
+        self.api_challenge.is_status_pending()
+    }
+
+    /// Tell the ACME API to attempt validating the proof of this challenge and poll,
+    /// without blocking, until the authorization is no longer `pending`.
+    pub async fn validate(self, poll: impl Into<PollPolicy>) -> Result<()> {
+        let poll = poll.into();
+        let transport = &self.inner.transport;
+
+        let res = transport
+            .call(&self.api_challenge.url, &ApiEmptyObject)
+            .await?;
+        let _: ApiChallenge = read_json(res)?;
+
+        let mut poller = poll.start("authorization");
+        let auth = loop {
+            let res = transport.call(&self.auth_url, &ApiEmptyString).await?;
+            let retry_after = retry_after(&res);
+            let auth: ApiAuth = read_json(res)?;
+            if !auth.is_status_pending() {
+                break auth;
+            }
+            poller.wait_async(retry_after).await?;
+        };
+
+        auth_result(&auth)
+    }
+
+    /// Access the underlying JSON object for debugging.
+    pub fn api_challenge(&self) -> &ApiChallenge {
+        &self.api_challenge
+    }
+}
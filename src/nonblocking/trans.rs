@@ -0,0 +1,161 @@
+//
+use serde::Serialize;
+use std::{fmt, sync::Arc};
+
+use crate::{
+    acc::AcmeKey,
+    http::{AsyncHttpClient, HttpRequest, HttpResponse, Method},
+    req::{check_status, ExtractHeader},
+    trans::{jws_with_jwk, jws_with_kid, should_retry, NoncePool},
+    Result,
+};
+
+/// Async counterpart of the blocking transport. The JWS signing and nonce
+/// pool are shared with the blocking API, only the I/O differs.
+#[derive(Clone)]
+pub(crate) struct Transport {
+    http: Arc<dyn AsyncHttpClient>,
+    acme_key: AcmeKey,
+    nonce_pool: Arc<NoncePool>,
+}
+
+impl Transport {
+    pub fn new(
+        http: &Arc<dyn AsyncHttpClient>,
+        nonce_pool: &Arc<NoncePool>,
+        acme_key: AcmeKey,
+    ) -> Self {
+        Transport {
+            http: http.clone(),
+            acme_key,
+            nonce_pool: nonce_pool.clone(),
+        }
+    }
+
+    /// Update the key id once it is known (part of setting up the transport).
+    pub fn set_key_id(&mut self, kid: String) {
+        self.acme_key.set_key_id(kid);
+    }
+
+    /// The key used in the transport
+    pub fn acme_key(&self) -> &AcmeKey {
+        &self.acme_key
+    }
+
+    /// The HTTP client used for the calls.
+    pub fn http(&self) -> &dyn AsyncHttpClient {
+        &*self.http
+    }
+
+    /// Make call using the full jwk. Only for the first newAccount request.
+    pub async fn call_jwk<T: Serialize + ?Sized>(
+        &self,
+        url: &str,
+        body: &T,
+    ) -> Result<HttpResponse> {
+        self.do_call(url, body, jws_with_jwk).await
+    }
+
+    /// Make call using the key id
+    pub async fn call<T: Serialize + ?Sized>(&self, url: &str, body: &T) -> Result<HttpResponse> {
+        self.do_call(url, body, jws_with_kid).await
+    }
+
+    async fn do_call<T: Serialize + ?Sized, F: Fn(&str, String, &AcmeKey, &T) -> Result<String>>(
+        &self,
+        url: &str,
+        body: &T,
+        make_body: F,
+    ) -> Result<HttpResponse> {
+        call_signed(&*self.http, &self.nonce_pool, url, |nonce| {
+            make_body(url, nonce, &self.acme_key, body)
+        })
+        .await
+    }
+}
+
+/// POST a JWS made by `make_body` from a nonce. Same loop as the blocking
+/// `call_signed`: retry until the server accepts the nonce.
+pub(crate) async fn call_signed<F: Fn(String) -> Result<String>>(
+    http: &dyn AsyncHttpClient,
+    nonce_pool: &NoncePool,
+    url: &str,
+    make_body: F,
+) -> Result<HttpResponse> {
+    loop {
+        let nonce = match nonce_pool.pop_nonce() {
+            Some(nonce) => nonce,
+            None => request_nonce(http, nonce_pool).await?,
+        };
+
+        let body = make_body(nonce)?;
+
+        debug!("Call endpoint {}", url);
+
+        let req = HttpRequest {
+            method: Method::Post,
+            url,
+            content_type: Some("application/jose+json"),
+            body: body.as_bytes(),
+        };
+        trace!("{:?} {}", req, body);
+        let response = http.request(req).await?;
+
+        nonce_pool.extract_nonce(&response);
+
+        let result = check_status(response);
+
+        if should_retry(&result) {
+            continue;
+        }
+
+        return result;
+    }
+}
+
+async fn request_nonce(http: &dyn AsyncHttpClient, nonce_pool: &NoncePool) -> Result<String> {
+    debug!("Request new nonce");
+    let res = get_or_head(http, Method::Head, nonce_pool.nonce_url()).await?;
+    Ok(res.extract_header("replay-nonce")?)
+}
+
+impl fmt::Debug for Transport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Transport")
+            .field("acme_key", &self.acme_key)
+            .field("nonce_pool", &self.nonce_pool)
+            .finish()
+    }
+}
+
+/// A request without body.
+pub(crate) async fn get_or_head(
+    http: &dyn AsyncHttpClient,
+    method: Method,
+    url: &str,
+) -> Result<HttpResponse> {
+    let req = HttpRequest {
+        method,
+        url,
+        content_type: None,
+        body: &[],
+    };
+    trace!("{:?}", req);
+    http.request(req).await
+}
+
+/// POST an OCSP request, which isn't an ACME API call and needs no JWS.
+pub(crate) async fn post_ocsp(
+    http: &dyn AsyncHttpClient,
+    url: &str,
+    body: &[u8],
+) -> Result<HttpResponse> {
+    let req = HttpRequest {
+        method: Method::Post,
+        url,
+        content_type: Some("application/ocsp-request"),
+        body,
+    };
+    trace!("{:?}", req);
+    http.request(req).await
+}
@@ -0,0 +1,223 @@
+//
+use time::OffsetDateTime;
+
+use crate::{
+    crypto::{
+        certificate_info,
+        ocsp::{is_ocsp_signer, BasicResponse, CertId},
+        verify_signature, verify_signed,
+    },
+    Error, Result, RevocationReason,
+};
+
+/// The revocation status of a certificate according to its OCSP responder.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OcspStatus {
+    /// The certificate is not revoked.
+    Good,
+    /// The certificate is revoked since the time, for the reason if the responder gave one.
+    Revoked {
+        time: OffsetDateTime,
+        reason: Option<RevocationReason>,
+    },
+    /// The responder doesn't know the certificate.
+    Unknown,
+}
+
+/// A verified OCSP response about a certificate.
+///
+/// Obtained from [`Account::ocsp_response`] or [`Certificate::check_ocsp_response`]. The
+/// DER is what TLS servers staple, i.e. the `ssl_stapling_file` of nginx or the `.ocsp`
+/// file next to the certificate for haproxy.
+///
+/// [`Account::ocsp_response`]: struct.Account.html#method.ocsp_response
+/// [`Certificate::check_ocsp_response`]: struct.Certificate.html#method.check_ocsp_response
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OcspResponse {
+    der: Vec<u8>,
+    status: OcspStatus,
+    produced_at: OffsetDateTime,
+    this_update: OffsetDateTime,
+    next_update: Option<OffsetDateTime>,
+}
+
+impl OcspResponse {
+    /// The response as DER.
+    pub fn der(&self) -> &[u8] {
+        &self.der
+    }
+
+    /// The status of the certificate.
+    pub fn status(&self) -> OcspStatus {
+        self.status
+    }
+
+    /// When the responder signed the response.
+    pub fn produced_at(&self) -> OffsetDateTime {
+        self.produced_at
+    }
+
+    /// The time the status is known to be correct.
+    pub fn this_update(&self) -> OffsetDateTime {
+        self.this_update
+    }
+
+    /// When newer information will be available. The response is not valid after this.
+    pub fn next_update(&self) -> Option<OffsetDateTime> {
+        self.next_update
+    }
+
+    /// When to fetch a new response: halfway to the next update, like the CAs recommend,
+    /// or right away if the responder doesn't say.
+    pub(crate) fn refresh_at(&self) -> OffsetDateTime {
+        match self.next_update {
+            Some(next) => self.this_update + (next - self.this_update) / 2,
+            None => self.this_update,
+        }
+    }
+}
+
+/// Verify the DER OCSP response about the certificate, both it and the issuer as DER.
+///
+/// The response must be signed by the issuer, or by a responder with a certificate
+/// of the issuer for signing OCSP responses. It must be current at the time.
+pub(crate) fn check_response(
+    der: &[u8],
+    cert_der: &[u8],
+    issuer_der: &[u8],
+    time: OffsetDateTime,
+) -> Result<OcspResponse> {
+    let basic = BasicResponse::from_der(der).map_err(|e| match e {
+        Error::Crypto(e) => Error::Verification(format!("Unreadable OCSP response: {}", e)),
+        e => e,
+    })?;
+
+    let issuer = certificate_info(issuer_der)?;
+    let mut signed = verify_signed(
+        issuer.public_key_der(),
+        basic.key,
+        basic.digest,
+        basic.tbs,
+        basic.signature,
+    )?;
+    for delegate in &basic.certs {
+        if signed {
+            break;
+        }
+        let info = certificate_info(delegate)?;
+        signed = info.issuer() == issuer.subject()
+            && time >= info.not_before()
+            && time <= info.not_after()
+            && is_ocsp_signer(delegate)?
+            && verify_signature(delegate, issuer_der)?
+            && verify_signed(
+                info.public_key_der(),
+                basic.key,
+                basic.digest,
+                basic.tbs,
+                basic.signature,
+            )?;
+    }
+    if !signed {
+        return Err(Error::Verification(format!(
+            "The OCSP response is not signed by {} or a responder it delegated to",
+            issuer.subject()
+        )));
+    }
+
+    let cert_id = CertId::new(cert_der, issuer_der)?;
+    let single = basic.find(&cert_id)?.ok_or_else(|| {
+        Error::Verification("The OCSP response is not about the certificate".into())
+    })?;
+    if time < single.this_update {
+        return Err(Error::Verification(format!(
+            "The OCSP response is not valid until {}",
+            single.this_update
+        )));
+    }
+    if let Some(next) = single.next_update {
+        if time > next {
+            return Err(Error::Verification(format!(
+                "The OCSP response expired at {}",
+                next
+            )));
+        }
+    }
+
+    Ok(OcspResponse {
+        der: der.to_vec(),
+        status: single.status,
+        produced_at: basic.produced_at,
+        this_update: single.this_update,
+        next_update: single.next_update,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{test::*, Certificate};
+    use time::macros::datetime;
+
+    #[test]
+    fn test_check_response() -> Result<()> {
+        let cert = Certificate::new(None, EXAMPLE_CHAIN.into());
+
+        let good = cert.check_ocsp_response(OCSP_GOOD)?;
+        assert_eq!(good.status(), OcspStatus::Good);
+        assert_eq!(good.der(), OCSP_GOOD);
+        assert_eq!(good.this_update(), datetime!(2024-02-01 00:00:00 UTC));
+        assert_eq!(good.next_update(), Some(datetime!(2048-01-01 00:00:00 UTC)));
+        assert_eq!(good.refresh_at(), datetime!(2036-01-16 12:00:00 UTC));
+
+        let revoked = cert.check_ocsp_response(OCSP_REVOKED)?;
+        assert_eq!(
+            revoked.status(),
+            OcspStatus::Revoked {
+                time: datetime!(2025-01-01 00:00:00 UTC),
+                reason: Some(RevocationReason::KeyCompromise),
+            }
+        );
+
+        let delegated = cert.check_ocsp_response(OCSP_DELEGATED)?;
+        assert_eq!(delegated.status(), OcspStatus::Good);
+
+        let failed = |der: &[u8], time| {
+            let chain = cert.chain().unwrap();
+            matches!(
+                check_response(der, chain[0].der(), chain[1].der(), time),
+                Err(Error::Verification(_))
+            )
+        };
+        let now = OffsetDateTime::now_utc();
+        assert!(failed(OCSP_FORGED, now));
+
+        // each fails for its own reason, not because it's unreadable
+        let reason = |der: &[u8]| {
+            let chain = cert.chain().unwrap();
+            match check_response(der, chain[0].der(), chain[1].der(), now) {
+                Err(Error::Verification(e)) => e,
+                r => panic!("expected a verification error: {:?}", r),
+            }
+        };
+        let mut tampered = OCSP_GOOD.to_vec();
+        *tampered.last_mut().unwrap() ^= 0x01;
+        assert!(reason(&tampered).contains("not signed"));
+        assert!(reason(OCSP_NOT_SIGNER).contains("not signed"));
+        assert!(reason(OCSP_OTHER_SERIAL).contains("not about the certificate"));
+        assert!(reason(OCSP_FUTURE).contains("not valid until"));
+        assert!(!failed(OCSP_FUTURE, datetime!(2047-01-01 00:00:00 UTC)));
+        assert!(failed(OCSP_GOOD, datetime!(2024-01-31 23:59:59 UTC)));
+        assert!(failed(OCSP_GOOD, datetime!(2048-01-01 00:00:01 UTC)));
+        assert!(failed(&OCSP_GOOD[..OCSP_GOOD.len() - 1], now));
+
+        // tryLater
+        assert!(cert
+            .check_ocsp_response(&[0x30, 0x03, 0x0a, 0x01, 0x03])
+            .is_err());
+        // a response about another certificate
+        let other = Certificate::new(None, format!("{}{}", cert.chain_pem(), EXAMPLE_ROOT));
+        assert!(other.check_ocsp_response(OCSP_GOOD).is_err());
+        Ok(())
+    }
+}
@@ -1,14 +1,16 @@
 //
-use openssl::sha::sha256;
-use std::{sync::Arc, thread, time::Duration};
+use std::sync::Arc;
 
 use crate::{
     acc::{AccountInner, AcmeKey},
     api::{ApiAuth, ApiChallenge, ApiEmptyObject, ApiEmptyString},
+    crypto::sha256,
     jwt::*,
     persist::Persist,
+    poll::PollPolicy,
+    req::retry_after,
     util::{base64url, read_json},
-    Result,
+    Error, Result,
 };
 
 /// An authorization ([ownership proof]) for a domain name.
@@ -76,7 +78,7 @@ impl<P: Persist> Auth<P> {
     /// };
     ///
     /// fn web_authorize<P: Persist>(auth: &Auth<P>) -> Result<(), Error> {
-    ///   let challenge = auth.http_challenge();
+    ///   let challenge = auth.http_challenge()?;
     ///   // Assuming our web server's root is under /var/www
     ///   let path = {
     ///     let token = challenge.http_token();
@@ -88,11 +90,16 @@ impl<P: Persist> Auth<P> {
     ///   Ok(())
     /// }
     /// ```
-    pub fn http_challenge(&self) -> Challenge<P, Http> {
+    ///
+    /// Fails with [`Error::ChallengeNotOffered`] if the ACME API doesn't offer
+    /// an http challenge for this domain.
+    ///
+    /// [`Error::ChallengeNotOffered`]: ../enum.Error.html#variant.ChallengeNotOffered
+    pub fn http_challenge(&self) -> Result<Challenge<P, Http>> {
         self.api_auth
             .http_challenge()
             .map(|c| Challenge::new(&self.inner, c.clone(), &self.auth_url))
-            .expect("http-challenge")
+            .ok_or_else(|| Error::ChallengeNotOffered("http-01".into()))
     }
 
     /// Get the dns challenge.
@@ -113,7 +120,7 @@ impl<P: Persist> Auth<P> {
     /// };
     ///
     /// fn dns_authorize<P: Persist>(auth: &Auth<P>) -> Result<(), Error> {
-    ///   let challenge = auth.dns_challenge();
+    ///   let challenge = auth.dns_challenge()?;
     ///   let record = format!("_acme-challenge.{}.", auth.domain_name());
     ///   // route_53_set_record(&record, "TXT", challenge.dns_proof());
     ///   challenge.validate(5000)?;
@@ -122,11 +129,16 @@ impl<P: Persist> Auth<P> {
     /// ```
     ///
     /// The dns proof is not the same as the http proof.
-    pub fn dns_challenge(&self) -> Challenge<P, Dns> {
+    ///
+    /// Fails with [`Error::ChallengeNotOffered`] if the ACME API doesn't offer
+    /// a dns challenge for this domain.
+    ///
+    /// [`Error::ChallengeNotOffered`]: ../enum.Error.html#variant.ChallengeNotOffered
+    pub fn dns_challenge(&self) -> Result<Challenge<P, Dns>> {
         self.api_auth
             .dns_challenge()
             .map(|c| Challenge::new(&self.inner, c.clone(), &self.auth_url))
-            .expect("dns-challenge")
+            .ok_or_else(|| Error::ChallengeNotOffered("dns-01".into()))
     }
 
     /// Get the TLS ALPN challenge.
@@ -136,11 +148,16 @@ impl<P: Persist> Auth<P> {
     /// must contain a single dNSName SAN containing the domain being
     /// validated, as well as an ACME extension containing the SHA256 of the
     /// key authorization.
-    pub fn tls_alpn_challenge(&self) -> Challenge<P, TlsAlpn> {
+    ///
+    /// Fails with [`Error::ChallengeNotOffered`] if the ACME API doesn't offer
+    /// a TLS ALPN challenge for this domain.
+    ///
+    /// [`Error::ChallengeNotOffered`]: ../enum.Error.html#variant.ChallengeNotOffered
+    pub fn tls_alpn_challenge(&self) -> Result<Challenge<P, TlsAlpn>> {
         self.api_auth
             .tls_alpn_challenge()
             .map(|c| Challenge::new(&self.inner, c.clone(), &self.auth_url))
-            .expect("tls-alpn-challenge")
+            .ok_or_else(|| Error::ChallengeNotOffered("tls-alpn-01".into()))
     }
 
     /// Access the underlying JSON object for debugging. We don't
@@ -232,42 +249,58 @@ impl<P: Persist, A> Challenge<P, A> {
     ///
     /// The user must first update the DNS record or HTTP web server depending
     /// on the type challenge being validated.
-    pub fn validate(self, delay_millis: u64) -> Result<()> {
+    ///
+    /// The authorization is then polled until it's no longer pending. The [`PollPolicy`]
+    /// decides how long to wait between each poll and when to give up. A plain `u64` is
+    /// the initial wait in milliseconds.
+    ///
+    /// [`PollPolicy`]: ../struct.PollPolicy.html
+    pub fn validate(self, poll: impl Into<PollPolicy>) -> Result<()> {
         let url_call = &self.api_challenge.url;
         let res = self.inner.transport.call(url_call, &ApiEmptyObject)?;
         let _: ApiChallenge = read_json(res)?;
 
-        let auth = wait_for_auth_status(&self.inner, &self.auth_url, delay_millis)?;
-
-        if !auth.is_status_valid() {
-            let error = auth
-                .challenges
-                .iter()
-                .filter_map(|c| c.error.as_ref())
-                .next();
-            let reason = if let Some(error) = error {
-                format!(
-                    "Failed: {}",
-                    error.detail.clone().unwrap_or_else(|| error._type.clone())
-                )
-            } else {
-                "Validation failed and no error found".into()
-            };
-            return Err(reason.into());
-        }
+        let auth = wait_for_auth_status(&self.inner, &self.auth_url, &poll.into())?;
 
-        Ok(())
+        auth_result(&auth)
     }
 
     /// Access the underlying JSON object for debugging.
     pub fn api_challenge(&self) -> &ApiChallenge {
         &self.api_challenge
     }
+
+    pub(crate) fn auth_url(&self) -> &str {
+        &self.auth_url
+    }
+}
+
+/// Turn a finished (not pending) authorization into a result.
+pub(crate) fn auth_result(auth: &ApiAuth) -> Result<()> {
+    if auth.is_status_valid() {
+        return Ok(());
+    }
+
+    // the challenge that failed has the problem reported by the ACME API.
+    let error = auth
+        .challenges
+        .iter()
+        .filter_map(|c| c.error.as_ref())
+        .next();
+    if let Some(error) = error {
+        Err(Error::ApiProblem(error.clone()))
+    } else {
+        Err(format!(
+            "Validation failed with status {:?} and no error found",
+            auth.status
+        )
+        .into())
+    }
 }
 
-fn key_authorization(token: &str, key: &AcmeKey, extra_sha256: bool) -> String {
-    let jwk = Jwk::from(key);
-    let jwk_thumb = JwkThumb::from(&jwk);
+pub(crate) fn key_authorization(token: &str, key: &AcmeKey, extra_sha256: bool) -> String {
+    let jwk_thumb = JwkThumb::from(key.jwk());
+    // serializing a struct of strings can't fail.
     let jwk_json = serde_json::to_string(&jwk_thumb).expect("jwk_thumb");
     let digest = base64url(&sha256(jwk_json.as_bytes()));
     let key_auth = format!("{}.{}", token, digest);
@@ -281,15 +314,17 @@ fn key_authorization(token: &str, key: &AcmeKey, extra_sha256: bool) -> String {
 fn wait_for_auth_status<P: Persist>(
     inner: &Arc<AccountInner<P>>,
     auth_url: &str,
-    delay_millis: u64,
+    poll: &PollPolicy,
 ) -> Result<ApiAuth> {
+    let mut poller = poll.start("authorization");
     let auth = loop {
         let res = inner.transport.call(auth_url, &ApiEmptyString)?;
+        let retry_after = retry_after(&res);
         let auth: ApiAuth = read_json(res)?;
         if !auth.is_status_pending() {
             break auth;
         }
-        thread::sleep(Duration::from_millis(delay_millis));
+        poller.wait(retry_after)?;
     };
     Ok(auth)
 }
@@ -310,11 +345,11 @@ mod test {
         assert!(authz.len() == 1);
         let auth = &authz[0];
         {
-            let http = auth.http_challenge();
+            let http = auth.http_challenge()?;
             assert!(http.need_validate());
         }
         {
-            let dns = auth.dns_challenge();
+            let dns = auth.dns_challenge()?;
             assert!(dns.need_validate());
         }
         Ok(())
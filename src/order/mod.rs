@@ -17,20 +17,31 @@
 //! [`Challenge`]: struct.Challenge.html
 //! [`CsrOrder`]: struct.CsrOrder.html
 //! [`CertOrder`]: struct.CertOrder.html
-use openssl::pkey::{self, PKey};
-use std::{sync::Arc, thread, time::Duration};
+use std::{
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 
 use crate::{
-    acc::AccountInner,
-    api::{ApiAuth, ApiEmptyString, ApiFinalize, ApiOrder},
-    cert::{create_csr, Certificate},
+    acc::{history_key, history_versions, AccountInner},
+    api::{ApiAuth, ApiChallenge, ApiEmptyObject, ApiEmptyString, ApiFinalize, ApiOrder},
+    cert::{CertKey, Certificate, PrivateKey, VerifyOptions},
+    crypto::create_external_csr,
+    deploy::{DeployHooks, DeployReport},
+    http::HttpResponse,
     persist::{Persist, PersistKey, PersistKind},
-    util::{base64url, read_json},
-    Result,
+    poll::PollPolicy,
+    req::retry_after,
+    signer::CsrSigner,
+    util::{base64url, parallel_map, pem_encode, read_json},
+    Error, Result,
 };
 
 mod auth;
 
+pub(crate) use self::auth::auth_result;
+#[cfg(feature = "async")]
+pub(crate) use self::auth::{key_authorization, Dns, Http, TlsAlpn};
 pub use self::auth::{Auth, Challenge};
 
 /// The order wrapped with an outer façade.
@@ -51,32 +62,36 @@ impl<P: Persist> Order<P> {
 }
 
 /// Helper to refresh an order status (POST-as-GET).
+///
+/// Also returns the `Retry-After` from the response, if any.
 pub(crate) fn refresh_order<P: Persist>(
     inner: &Arc<AccountInner<P>>,
     url: String,
     want_status: &'static str,
-) -> Result<Order<P>> {
+) -> Result<(Order<P>, Option<Duration>)> {
     let res = inner.transport.call(&url, &ApiEmptyString)?;
+    let retry_after = retry_after(&res);
 
     // our test rig requires the order to be in `want_status`.
     // api_order_of is different for test compilation
     let api_order = api_order_of(res, want_status)?;
 
-    Ok(Order {
+    let order = Order {
         inner: inner.clone(),
         api_order,
         url,
-    })
+    };
+    Ok((order, retry_after))
 }
 
 #[cfg(not(test))]
-fn api_order_of(res: ureq::Response, _want_status: &str) -> Result<ApiOrder> {
+pub(crate) fn api_order_of(res: HttpResponse, _want_status: &str) -> Result<ApiOrder> {
     read_json(res)
 }
 
 #[cfg(test)]
 // our test rig requires the order to be in `want_status`
-fn api_order_of(res: ureq::Response, want_status: &str) -> Result<ApiOrder> {
+pub(crate) fn api_order_of(res: HttpResponse, want_status: &str) -> Result<ApiOrder> {
     let s = res.into_string()?;
     #[allow(clippy::trivial_regex)]
     let re = regex::Regex::new("<STATUS>").unwrap();
@@ -142,7 +157,7 @@ impl<P: Persist> NewOrder<P> {
     ///
     /// The specification calls this a "POST-as-GET" against the order URL.
     pub fn refresh(&mut self) -> Result<()> {
-        let order = refresh_order(&self.order.inner, self.order.url.clone(), "ready")?;
+        let (order, _) = refresh_order(&self.order.inner, self.order.url.clone(), "ready")?;
         self.order = order;
         Ok(())
     }
@@ -153,18 +168,136 @@ impl<P: Persist> NewOrder<P> {
     ///
     /// If the order includes new domain names that have not been authorized before, this
     /// list might contain a mix of already valid and not yet valid auths.
+    ///
+    /// The authorizations are fetched in parallel.
     pub fn authorizations(&self) -> Result<Vec<Auth<P>>> {
+        let auth_urls = match &self.order.api_order.authorizations {
+            Some(a) => &a[..],
+            None => &[],
+        };
+        let transport = &self.order.inner.transport;
+        let api_auths = parallel_map(auth_urls, |auth_url| {
+            let res = transport.call(auth_url, &ApiEmptyString)?;
+            read_json::<ApiAuth>(res)
+        });
         let mut result = vec![];
-        if let Some(authorizations) = &self.order.api_order.authorizations {
-            for auth_url in authorizations {
-                let res = self.order.inner.transport.call(auth_url, &ApiEmptyString)?;
-                let api_auth: ApiAuth = read_json(res)?;
-                result.push(Auth::new(&self.order.inner, api_auth, auth_url));
-            }
+        for (auth_url, api_auth) in auth_urls.iter().zip(api_auths) {
+            result.push(Auth::new(&self.order.inner, api_auth?, auth_url));
         }
         Ok(result)
     }
 
+    /// Validate all authorizations of the order in one go.
+    ///
+    /// The authorizations are fetched in parallel and `challenge` is called for each
+    /// one that [needs a challenge]. The closure is expected to place the proof
+    /// (web server file, DNS record etc) and return the challenge to validate.
+    ///
+    /// Once all proofs are in place, every challenge is triggered and the authorizations
+    /// are polled together in one loop, sharing the backoff of the [`PollPolicy`].
+    /// If the policy gives up, the still pending domains get a timeout error.
+    ///
+    /// The result holds one outcome per domain. A failing domain does not stop the
+    /// validation of the others. Call [`refresh`] afterwards to update the order state.
+    ///
+    /// ```no_run
+    /// use acme_lib::{order::NewOrder, persist::Persist, Error};
+    ///
+    /// fn authorize_all<P: Persist>(ord: &NewOrder<P>) -> Result<(), Error> {
+    ///   let outcomes = ord.validate_authorizations(|auth| {
+    ///     let challenge = auth.dns_challenge()?;
+    ///     let record = format!("_acme-challenge.{}.", auth.domain_name());
+    ///     // route_53_set_record(&record, "TXT", challenge.dns_proof());
+    ///     Ok(challenge)
+    ///   }, 5000)?;
+    ///   for outcome in outcomes.iter().filter(|o| !o.is_valid()) {
+    ///     println!("{} failed", outcome.domain_name());
+    ///   }
+    ///   Ok(())
+    /// }
+    /// ```
+    ///
+    /// [needs a challenge]: struct.Auth.html#method.need_challenge
+    /// [`PollPolicy`]: ../struct.PollPolicy.html
+    /// [`refresh`]: struct.NewOrder.html#method.refresh
+    pub fn validate_authorizations<A, F>(
+        &self,
+        mut challenge: F,
+        poll: impl Into<PollPolicy>,
+    ) -> Result<Vec<AuthOutcome>>
+    where
+        F: FnMut(&Auth<P>) -> Result<Challenge<P, A>>,
+    {
+        let auths = self.authorizations()?;
+
+        let mut outcomes = Vec::with_capacity(auths.len());
+        let mut to_trigger = vec![];
+        for auth in &auths {
+            let idx = outcomes.len();
+            outcomes.push(AuthOutcome {
+                domain: auth.domain_name().to_string(),
+                result: Ok(()),
+            });
+            if !auth.need_challenge() {
+                continue;
+            }
+            match challenge(auth) {
+                Ok(c) => {
+                    to_trigger.push((idx, c.api_challenge().url.clone(), c.auth_url().to_string()))
+                }
+                Err(e) => outcomes[idx].result = Err(e),
+            }
+        }
+
+        // tell the ACME API to start checking all proofs
+        let transport = &self.order.inner.transport;
+        let triggered = parallel_map(&to_trigger, |(_, url, _)| {
+            let res = transport.call(url, &ApiEmptyObject)?;
+            read_json::<ApiChallenge>(res).map(|_| ())
+        });
+        let mut pending = vec![];
+        for ((idx, _, auth_url), r) in to_trigger.into_iter().zip(triggered) {
+            match r {
+                Ok(()) => pending.push((idx, auth_url)),
+                Err(e) => outcomes[idx].result = Err(e),
+            }
+        }
+
+        // poll all pending authorizations with a shared backoff
+        let mut poller = poll.into().start("authorizations");
+        let mut retry = None;
+        while !pending.is_empty() {
+            if let Err(e) = poller.wait(retry) {
+                for (idx, _) in pending {
+                    outcomes[idx].result = Err(Error::Timeout(e.to_string()));
+                }
+                break;
+            }
+
+            let polled = parallel_map(&pending, |(_, auth_url)| {
+                let res = transport.call(auth_url, &ApiEmptyString)?;
+                let retry = retry_after(&res);
+                read_json::<ApiAuth>(res).map(|auth| (auth, retry))
+            });
+            retry = None;
+            let mut still_pending = vec![];
+            for ((idx, auth_url), r) in pending.into_iter().zip(polled) {
+                match r {
+                    Ok((auth, r)) if auth.is_status_pending() => {
+                        // the longest Retry-After of the round wins
+                        retry = retry.max(r);
+                        still_pending.push((idx, auth_url));
+                    }
+                    Ok((auth, _)) => outcomes[idx].result = auth_result(&auth),
+                    Err(e) => outcomes[idx].result = Err(e),
+                }
+            }
+            pending = still_pending;
+        }
+
+        Ok(outcomes)
+    }
+
     /// Access the underlying JSON object for debugging.
     pub fn api_order(&self) -> &ApiOrder {
         &self.order.api_order
@@ -177,7 +310,8 @@ impl<P: Persist> NewOrder<P> {
 ///
 /// To finalize, the user supplies a private key (from which a public key is derived). This
 /// library provides [functions to create private keys], but the user can opt for creating them
-/// in some other way.
+/// in some other way. A key held externally, i.e. in an HSM, signs the CSR through a
+/// [`CsrSigner`] with [`finalize_signer`].
 ///
 /// This library makes no attempt at validating which key algorithms are used. Unsupported
 /// algorithms will show as an error when finalizing the order. It is up to the ACME API
@@ -191,6 +325,8 @@ impl<P: Persist> NewOrder<P> {
 /// [CSR]: https://en.wikipedia.org/wiki/Certificate_signing_request
 /// [functions to create key pairs]: ../index.html#functions
 /// [supports]: https://letsencrypt.org/docs/integration-guide/#supported-key-algorithms
+/// [`CsrSigner`]: ../signer/trait.CsrSigner.html
+/// [`finalize_signer`]: struct.CsrOrder.html#method.finalize_signer
 pub struct CsrOrder<P: Persist> {
     pub(crate) order: Order<P>,
 }
@@ -199,41 +335,64 @@ impl<P: Persist> CsrOrder<P> {
     /// Finalize the order by providing a private key as PEM.
     ///
     /// Once the CSR has been submitted, the order goes into a `processing` status,
-    /// where we must poll until the status changes. The `delay_millis` is the
-    /// amount of time to wait between each poll attempt.
+    /// where we must poll until the status changes. The [`PollPolicy`] decides
+    /// how long to wait between each poll attempt and when to give up. A plain
+    /// `u64` is the initial wait in milliseconds.
     ///
     /// This is a convenience wrapper that in turn calls the lower level [`finalize_pkey`].
     ///
+    /// [`PollPolicy`]: ../struct.PollPolicy.html
     /// [`finalize_pkey`]: struct.CsrOrder.html#method.finalize_pkey
-    pub fn finalize(self, private_key_pem: &str, delay_millis: u64) -> Result<CertOrder<P>> {
-        let pkey_pri = PKey::private_key_from_pem(private_key_pem.as_bytes())
-            .map_err(|e| format!("Error reading private key PEM: {}", e))?;
-        self.finalize_pkey(pkey_pri, delay_millis)
+    pub fn finalize(
+        self,
+        private_key_pem: &str,
+        poll: impl Into<PollPolicy>,
+    ) -> Result<CertOrder<P>> {
+        let pkey_pri = PrivateKey::from_pem(private_key_pem.as_bytes())?;
+        self.finalize_pkey(pkey_pri, poll)
     }
 
-    /// Lower level finalize call that works directly with the openssl crate structures.
+    /// Lower level finalize call that takes the private key directly, i.e. one made by
+    /// [`create_p384_key`] or (with the `openssl` feature) an openssl `PKey<Private>`.
     ///
     /// Creates the CSR for the domains in the order and submit it to the ACME API.
     ///
     /// Once the CSR has been submitted, the order goes into a `processing` status,
-    /// where we must poll until the status changes. The `delay_millis` is the
-    /// amount of time to wait between each poll attempt.
+    /// where we must poll until the status changes according to the [`PollPolicy`].
+    ///
+    /// [`create_p384_key`]: ../fn.create_p384_key.html
+    /// [`PollPolicy`]: ../struct.PollPolicy.html
     pub fn finalize_pkey(
         self,
-        private_key: PKey<pkey::Private>,
-        delay_millis: u64,
+        private_key: impl Into<PrivateKey>,
+        poll: impl Into<PollPolicy>,
     ) -> Result<CertOrder<P>> {
-        //
-        // the domains that we have authorized
-        let domains = self.order.api_order.domains();
+        let private_key = private_key.into();
+        let csr = private_key.0.create_csr(&self.order.api_order.domains())?;
+        self.finalize_csr(&csr, CertKey::Private(private_key), poll.into())
+    }
 
-        // csr from private key and authorized domains.
-        let csr = create_csr(&private_key, &domains)?;
+    /// Finalize call where the CSR is signed by a private key held externally, i.e.
+    /// in an HSM or a KMS.
+    ///
+    /// The private key is never seen by this library. The certificate saved by
+    /// [`download_and_save_cert`] has no private key, only the public key is saved
+    /// alongside it in the persistence.
+    ///
+    /// [`download_and_save_cert`]: struct.CertOrder.html#method.download_and_save_cert
+    pub fn finalize_signer(
+        self,
+        signer: &dyn CsrSigner,
+        poll: impl Into<PollPolicy>,
+    ) -> Result<CertOrder<P>> {
+        let public_key_der = signer.public_key_der()?;
+        let domains = self.order.api_order.domains();
+        let csr = create_external_csr(signer, &public_key_der, &domains)?;
+        self.finalize_csr(&csr, CertKey::External(public_key_der), poll.into())
+    }
 
-        // this is not the same as PEM.
-        let csr_der = csr.to_der().expect("to_der()");
-        let csr_enc = base64url(&csr_der);
-        let finalize = ApiFinalize { csr: csr_enc };
+    fn finalize_csr(self, csr_der: &[u8], key: CertKey, poll: PollPolicy) -> Result<CertOrder<P>> {
+        let finalize = finalize_body(csr_der);
 
         let inner = self.order.inner;
         let order_url = self.order.url;
@@ -246,13 +405,10 @@ impl<P: Persist> CsrOrder<P> {
         // wait for the status to not be processing.
         // valid -> cert is issued
         // invalid -> the whole thing is off
-        let order = wait_for_order_status(&inner, &order_url, delay_millis)?;
-
-        if !order.api_order.is_status_valid() {
-            return Err(format!("Order is in status: {:?}", order.api_order.status).into());
-        }
+        let order = wait_for_order_status(&inner, &order_url, &poll)?;
+        check_order_valid(&order.api_order)?;
 
-        Ok(CertOrder { private_key, order })
+        Ok(CertOrder { key, order })
     }
 
     /// Access the underlying JSON object for debugging.
@@ -261,23 +417,73 @@ impl<P: Persist> CsrOrder<P> {
     }
 }
 
+/// The body of the finalize call with the DER encoded CSR.
+pub(crate) fn finalize_body(csr_der: &[u8]) -> ApiFinalize {
+    ApiFinalize {
+        csr: base64url(csr_der),
+    }
+}
+
+/// Check that a finalized order is valid, i.e. the certificate is issued.
+pub(crate) fn check_order_valid(api_order: &ApiOrder) -> Result<()> {
+    if !api_order.is_status_valid() {
+        if let Some(problem) = &api_order.error {
+            return Err(Error::ApiProblem(problem.clone()));
+        }
+        return Err(format!("Order is in status: {:?}", api_order.status).into());
+    }
+    Ok(())
+}
+
+/// The outcome of validating one domain in [`validate_authorizations`].
+///
+/// [`validate_authorizations`]: struct.NewOrder.html#method.validate_authorizations
+#[derive(Debug)]
+pub struct AuthOutcome {
+    pub(crate) domain: String,
+    pub(crate) result: Result<()>,
+}
+
+impl AuthOutcome {
+    /// Domain name of the authorization.
+    pub fn domain_name(&self) -> &str {
+        &self.domain
+    }
+
+    /// Whether the domain is now authorized.
+    pub fn is_valid(&self) -> bool {
+        self.result.is_ok()
+    }
+
+    /// The result of the validation for this domain.
+    pub fn result(&self) -> &Result<()> {
+        &self.result
+    }
+
+    /// Turn the outcome into the result of the validation.
+    pub fn into_result(self) -> Result<()> {
+        self.result
+    }
+}
+
 fn wait_for_order_status<P: Persist>(
     inner: &Arc<AccountInner<P>>,
     url: &str,
-    delay_millis: u64,
+    poll: &PollPolicy,
 ) -> Result<Order<P>> {
+    let mut poller = poll.start("order status");
     loop {
-        let order = refresh_order(inner, url.to_string(), "valid")?;
+        let (order, retry_after) = refresh_order(inner, url.to_string(), "valid")?;
         if !order.api_order.is_status_processing() {
             return Ok(order);
         }
-        thread::sleep(Duration::from_millis(delay_millis));
+        poller.wait(retry_after)?;
     }
 }
 
 /// Order for an issued certificate that is ready to download.
 pub struct CertOrder<P: Persist> {
-    private_key: PKey<pkey::Private>,
+    key: CertKey,
     order: Order<P>,
 }
 
@@ -289,28 +495,46 @@ impl<P: Persist> CertOrder<P> {
     ///
     /// [`Account::certificate`]: ../struct.Account.html#method.certificate
     pub fn download_and_save_cert(self) -> Result<Certificate> {
-        //
-        let primary_name = self.order.api_order.domains()[0].to_string();
-        let url = self.order.api_order.certificate.expect("certificate url");
-        let inner = self.order.inner;
-        let realm = &inner.realm[..];
+        self.download_and_save().map(|(_, cert, _)| cert)
+    }
 
-        let res = inner.transport.call(&url, &ApiEmptyString)?;
+    /// Download and save the issued certificate like [`download_and_save_cert`], then
+    /// run the hooks if the certificate differs from the one it replaces.
+    ///
+    /// Failing hooks don't fail the call, the certificate is saved by then. The failures
+    /// are in the returned report.
+    ///
+    /// [`download_and_save_cert`]: struct.CertOrder.html#method.download_and_save_cert
+    pub fn download_and_deploy_cert(self, hooks: &DeployHooks) -> Result<DeployReport> {
+        let (primary_name, cert, changed) = self.download_and_save()?;
+        Ok(hooks.deploy_if_changed(&primary_name, cert, changed))
+    }
 
-        // save key and cert into persistence
-        let persist = &inner.persist;
-        let pk_key = PersistKey::new(realm, PersistKind::PrivateKey, &primary_name);
-        let pkey_pem_bytes = self.private_key.private_key_to_pem_pkcs8().expect("to_pem");
-        let pkey_pem = String::from_utf8_lossy(&pkey_pem_bytes);
-        debug!("Save private key: {}", pk_key);
-        persist.put(&pk_key, &pkey_pem_bytes)?;
+    /// Returns the primary name, the certificate and whether it changed.
+    fn download_and_save(self) -> Result<(String, Certificate, bool)> {
+        let primary_name = self
+            .order
+            .api_order
+            .domains()
+            .first()
+            .ok_or("Order has no identifiers")?
+            .to_string();
+        let url = certificate_url(&self.order.api_order)?;
+        let inner = self.order.inner;
 
+        let res = inner.transport.call(&url, &ApiEmptyString)?;
         let cert = res.into_string()?;
-        let pk_crt = PersistKey::new(realm, PersistKind::Certificate, &primary_name);
-        debug!("Save certificate: {}", pk_crt);
-        persist.put(&pk_crt, cert.as_bytes())?;
-
-        Ok(Certificate::new(pkey_pem.to_string(), cert))
+        verify_issued(&self.key, &cert, &self.order.api_order.domains())?;
+
+        let (cert, changed) = save_certificate(
+            &inner.persist,
+            &inner.realm,
+            &primary_name,
+            &self.key,
+            cert,
+            inner.history,
+        )?;
+        Ok((primary_name, cert, changed))
     }
 
     /// Access the underlying JSON object for debugging.
@@ -319,6 +543,94 @@ impl<P: Persist> CertOrder<P> {
     }
 }
 
+/// The url to download the certificate of a valid order.
+pub(crate) fn certificate_url(api_order: &ApiOrder) -> Result<String> {
+    api_order
+        .certificate
+        .clone()
+        .ok_or_else(|| "Order is valid but has no certificate url".into())
+}
+
+/// Check a downloaded certificate against the key and names of the order, so
+/// nothing is saved for a broken certificate.
+pub(crate) fn verify_issued(key: &CertKey, cert: &str, domains: &[&str]) -> Result<()> {
+    let public_key_der = match key {
+        CertKey::Private(private_key) => private_key.0.public_key_der()?,
+        CertKey::External(public_key_der) => public_key_der.clone(),
+    };
+    let options = VerifyOptions::new().names(domains);
+    Certificate::new(None, cert.to_string()).verify_key(Some(&public_key_der), &options)
+}
+
+/// Save a downloaded certificate and its private key into the persistence. Also tells
+/// whether the certificate differs from the one it replaces.
+pub(crate) fn save_certificate<P: Persist>(
+    persist: &P,
+    realm: &str,
+    primary_name: &str,
+    key: &CertKey,
+    cert: String,
+    history: usize,
+) -> Result<(Certificate, bool)> {
+    // an external key is persisted as its public key, under a kind of its own
+    let (kind, stale_kind, saved_pem, pkey_pem) = match key {
+        CertKey::Private(private_key) => {
+            let pem = private_key.to_pem()?;
+            let kind = PersistKind::PrivateKey;
+            (kind, PersistKind::PublicKey, pem.clone(), Some(pem))
+        }
+        CertKey::External(public_key_der) => {
+            let pem = pem_encode("PUBLIC KEY", public_key_der);
+            (PersistKind::PublicKey, PersistKind::PrivateKey, pem, None)
+        }
+    };
+    let pk_key = PersistKey::new(realm, kind, primary_name);
+    debug!("Save key: {}", pk_key);
+
+    let pk_crt = PersistKey::new(realm, PersistKind::Certificate, primary_name);
+    debug!("Save certificate: {}", pk_crt);
+
+    // the key and certificate must match, replace both or neither
+    let _lock = persist.lock(&pk_crt)?;
+    let changed = persist.get(&pk_crt)?.as_deref() != Some(cert.as_bytes());
+    if history == 0 {
+        persist.put_all(&[(pk_key, saved_pem.as_bytes()), (pk_crt, cert.as_bytes())])?;
+    } else {
+        let versions = history_versions(persist, realm, primary_name)?;
+        let version = versions.last().map(|(v, _)| v + 1).unwrap_or(1);
+        let saved = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        let hist = history_key(primary_name, version, saved);
+        debug!("Save certificate version {}: {}", version, pk_crt);
+        persist.put_all(&[
+            (pk_key, saved_pem.as_bytes()),
+            (pk_crt, cert.as_bytes()),
+            (PersistKey::new(realm, kind, &hist), saved_pem.as_bytes()),
+            (
+                PersistKey::new(realm, PersistKind::Certificate, &hist),
+                cert.as_bytes(),
+            ),
+        ])?;
+
+        // the new version is one of those kept
+        for (old, saved) in versions.into_iter().rev().skip(history - 1) {
+            let hist = history_key(primary_name, old, saved);
+            debug!("Remove certificate version {}: {}", old, pk_crt);
+            persist.delete(&PersistKey::new(realm, PersistKind::Certificate, &hist))?;
+            persist.delete(&PersistKey::new(realm, PersistKind::PrivateKey, &hist))?;
+            persist.delete(&PersistKey::new(realm, PersistKind::PublicKey, &hist))?;
+        }
+    }
+
+    // The key of the other kind belongs to the replaced certificate. Reading tells the
+    // kinds apart if this isn't reached.
+    persist.delete(&PersistKey::new(realm, stale_kind, primary_name))?;
+
+    Ok((Certificate::new(pkey_pem, cert), changed))
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -336,6 +648,50 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn test_download_without_identifiers() -> Result<()> {
+        let server = crate::test::with_directory_server();
+        let url = DirectoryUrl::Other(&server.dir_url);
+        let dir = Directory::from_url(MemoryPersist::new(), url)?;
+        let acc = dir.account("foo@bar.com")?;
+        let mut order = acc.new_order("acmetest.example.com", &[])?.order;
+        order.api_order.identifiers.clear();
+        let key = CertKey::Private(crate::test::example_key());
+        let ord = CertOrder { key, order };
+        assert!(ord.download_and_save_cert().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_validate_authorizations() -> Result<()> {
+        let server = crate::test::with_directory_server();
+        let url = DirectoryUrl::Other(&server.dir_url);
+        let persist = MemoryPersist::new();
+        let dir = Directory::from_url(persist, url)?;
+        let acc = dir.account("foo@bar.com")?;
+        let ord = acc.new_order("acmetest.example.com", &[])?;
+        let outcomes = ord.validate_authorizations(|auth| auth.http_challenge(), 1)?;
+        assert_eq!(outcomes.len(), 1);
+        assert_eq!(outcomes[0].domain_name(), "acmetest.algesten.se");
+        assert!(outcomes[0].is_valid());
+        Ok(())
+    }
+
+    #[test]
+    fn test_validate_authorizations_failing_domain() -> Result<()> {
+        let server = crate::test::with_directory_server();
+        let url = DirectoryUrl::Other(&server.dir_url);
+        let persist = MemoryPersist::new();
+        let dir = Directory::from_url(persist, url)?;
+        let acc = dir.account("foo@bar.com")?;
+        let ord = acc.new_order("acmetest.example.com", &[])?;
+        let outcomes =
+            ord.validate_authorizations::<auth::Http, _>(|_| Err("no web server".into()), 1)?;
+        assert_eq!(outcomes.len(), 1);
+        assert!(!outcomes[0].is_valid());
+        Ok(())
+    }
+
     #[test]
     fn test_finalize() -> Result<()> {
         let server = crate::test::with_directory_server();
@@ -346,7 +702,7 @@ mod test {
         let ord = acc.new_order("acmetest.example.com", &[])?;
         // shortcut auth
         let ord = CsrOrder { order: ord.order };
-        let pkey = cert::create_p256_key();
+        let pkey = cert::create_p256_key()?;
         let _ord = ord.finalize_pkey(pkey, 1)?;
         Ok(())
     }
@@ -362,19 +718,106 @@ mod test {
 
         // shortcut auth
         let ord = CsrOrder { order: ord.order };
-        let pkey = cert::create_p256_key();
-        let ord = ord.finalize_pkey(pkey, 1)?;
+        let ord = ord.finalize_pkey(crate::test::example_key(), 1)?;
 
         let cert = ord.download_and_save_cert()?;
-        assert_eq!("CERT HERE", cert.certificate());
-        assert!(!cert.private_key().is_empty());
+        assert_eq!(crate::test::EXAMPLE_CHAIN, cert.certificate());
+        assert!(cert.private_key().is_some());
 
         // check that the keys have been persisted
         let cert2 = acc.certificate("acmetest.example.com")?.unwrap();
         assert_eq!(cert.private_key(), cert2.private_key());
         assert_eq!(cert.certificate(), cert2.certificate());
-        assert_eq!(cert.valid_days_left(), 89);
+        assert_eq!(cert.valid_days_left()?, 89);
 
         Ok(())
     }
+
+    #[test]
+    fn test_finalize_signer() -> Result<()> {
+        use crate::crypto::KeyPair;
+        use crate::signer::{CsrSigner, SignatureAlgorithm};
+
+        struct External(KeyPair);
+        impl CsrSigner for External {
+            fn public_key_der(&self) -> Result<Vec<u8>> {
+                self.0.public_key_der()
+            }
+            fn algorithm(&self) -> SignatureAlgorithm {
+                SignatureAlgorithm::EcdsaSha256
+            }
+            fn sign(&self, data: &[u8]) -> Result<Vec<u8>> {
+                Ok(self.0.sign_csr(data)?.1)
+            }
+        }
+
+        let server = crate::test::with_directory_server();
+        let url = DirectoryUrl::Other(&server.dir_url);
+        let persist = MemoryPersist::new();
+        let dir = Directory::from_url(persist.clone(), url)?;
+        let acc = dir.account("foo@bar.com")?;
+
+        // a software key from an earlier issuance must not be paired with the new cert
+        let pk = PersistKey::new(
+            "foo@bar.com",
+            PersistKind::PrivateKey,
+            "acmetest.example.com",
+        );
+        persist.put(&pk, create_p256_key()?.to_pem()?.as_bytes())?;
+
+        let ord = acc.new_order("acmetest.example.com", &[])?;
+        let ord = CsrOrder { order: ord.order };
+        let key = KeyPair::from_pem(crate::test::EXAMPLE_KEY.as_bytes())?;
+        let ord = ord.finalize_signer(&External(key), 1)?;
+
+        let cert = ord.download_and_save_cert()?;
+        assert!(cert.is_key_external());
+
+        assert!(persist.get(&pk)?.is_none());
+        let pk_pub = PersistKey::new(
+            "foo@bar.com",
+            PersistKind::PublicKey,
+            "acmetest.example.com",
+        );
+        let saved = String::from_utf8(persist.get(&pk_pub)?.unwrap()).unwrap();
+        assert!(saved.starts_with("-----BEGIN PUBLIC KEY-----"));
+
+        let cert2 = acc.certificate("acmetest.example.com")?.unwrap();
+        assert!(cert2.is_key_external());
+        assert_eq!(cert.certificate(), cert2.certificate());
+
+        // a software key replaces the public key
+        let ord = acc.new_order("acmetest.example.com", &[])?;
+        let ord = CsrOrder { order: ord.order };
+        let ord = ord.finalize_pkey(crate::test::example_key(), 1)?;
+        assert!(!ord.download_and_save_cert()?.is_key_external());
+        assert!(persist.get(&pk_pub)?.is_none());
+
+        // the public key of another key, left by an interrupted save, is ignored
+        let other = pem_encode("PUBLIC KEY", &create_p256_key()?.0.public_key_der()?);
+        persist.put(&pk_pub, other.as_bytes())?;
+        let cert3 = acc.certificate("acmetest.example.com")?.unwrap();
+        assert_eq!(cert3.private_key(), Some(crate::test::EXAMPLE_KEY));
+        Ok(())
+    }
+
+    #[test]
+    fn test_download_mismatched_key() -> Result<()> {
+        let server = crate::test::with_directory_server();
+        let url = DirectoryUrl::Other(&server.dir_url);
+        let persist = MemoryPersist::new();
+        let dir = Directory::from_url(persist.clone(), url)?;
+        let acc = dir.account("foo@bar.com")?;
+        let ord = acc.new_order("acmetest.example.com", &[])?;
+        let ord = CsrOrder { order: ord.order };
+        let ord = ord.finalize_pkey(create_p256_key()?, 1)?;
+
+        let res = ord.download_and_save_cert();
+        assert!(matches!(res, Err(Error::Verification(_))));
+        assert!(acc.certificate("acmetest.example.com")?.is_none());
+        assert!(persist
+            .list("foo@bar.com", PersistKind::PrivateKey)?
+            .is_empty());
+        Ok(())
+    }
 }
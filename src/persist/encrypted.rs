@@ -0,0 +1,395 @@
+//! Encryption of private keys at rest (requires the `encryption` feature).
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN};
+use std::{fmt, sync::Arc};
+
+use super::{Persist, PersistKey, PersistKind, PersistLock};
+use crate::{crypto::random_bytes, Error, Result};
+
+/// Start of every encrypted value, followed by the key id, nonce and ciphertext.
+const MAGIC: &[u8] = b"ACMEENC1";
+
+/// Key for the [`EncryptedPersist`], identified by an id that is saved with every
+/// value it encrypts.
+///
+/// [`EncryptedPersist`]: struct.EncryptedPersist.html
+pub struct EncryptionKey {
+    id: String,
+    key: LessSafeKey,
+}
+
+impl EncryptionKey {
+    /// A key from 32 random bytes, i.e. from a secrets manager. The `id` is at most
+    /// 255 bytes and tells the keys apart when rotating.
+    pub fn from_bytes(id: &str, key: &[u8; 32]) -> Result<EncryptionKey> {
+        if id.is_empty() || id.len() > 255 {
+            return Err("Encryption key id must be 1 to 255 bytes".into());
+        }
+        let key = UnboundKey::new(&AES_256_GCM, key)
+            .map_err(|_| Error::Crypto("Invalid encryption key".into()))?;
+        Ok(EncryptionKey {
+            id: id.to_string(),
+            key: LessSafeKey::new(key),
+        })
+    }
+
+    /// A key derived from a passphrase with Argon2id (version 0x13, 19 MiB of memory, 2
+    /// passes, 1 lane). The salt is not secret, but must be the same every time and at
+    /// least 8 bytes. Deriving the key takes a moment on purpose.
+    pub fn from_passphrase(id: &str, passphrase: &str, salt: &[u8]) -> Result<EncryptionKey> {
+        EncryptionKey::from_bytes(id, &derive_key(passphrase, salt)?)
+    }
+
+    /// The id of the key.
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+}
+
+/// The Argon2 parameters are spelled out, as changing them loses the keys derived before.
+fn derive_key(passphrase: &str, salt: &[u8]) -> Result<[u8; 32]> {
+    let config = argon2::Config {
+        ad: &[],
+        hash_length: 32,
+        lanes: 1,
+        mem_cost: 19456,
+        secret: &[],
+        time_cost: 2,
+        variant: argon2::Variant::Argon2id,
+        version: argon2::Version::Version13,
+    };
+    let hash = argon2::hash_raw(passphrase.as_bytes(), salt, &config)
+        .map_err(|e| Error::Crypto(format!("Failed to derive key: {}", e)))?;
+    let mut key = [0_u8; 32];
+    key.copy_from_slice(&hash);
+    Ok(key)
+}
+
+impl fmt::Debug for EncryptionKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "EncryptionKey({})", self.id)
+    }
+}
+
+/// Persistence that encrypts private keys before handing them to another persistence.
+///
+/// Values of kind [`AccountPrivateKey`] and [`PrivateKey`] are encrypted with AES-256-GCM.
+/// Certificates, public keys and OCSP responses are public and stored as is. Each value
+/// is bound to its key in the persistence, so a modified value, or a value moved to
+/// another key, fails to read.
+///
+/// To rotate the key, make the new key current, add the old one with [`previous_key`] and
+/// call [`rotate`] for each realm.
+///
+/// ```no_run
+/// use acme_lib::persist::{EncryptedPersist, EncryptionKey, FilePersist};
+/// # fn main() -> acme_lib::Result<()> {
+/// let key = EncryptionKey::from_passphrase("2024", "correct horse", b"acme-lib.example.com")?;
+/// let persist = EncryptedPersist::new(FilePersist::new("/mnt/shared"), key);
+/// # Ok(()) }
+/// ```
+///
+/// [`AccountPrivateKey`]: enum.PersistKind.html#variant.AccountPrivateKey
+/// [`PrivateKey`]: enum.PersistKind.html#variant.PrivateKey
+/// [`previous_key`]: struct.EncryptedPersist.html#method.previous_key
+/// [`rotate`]: struct.EncryptedPersist.html#method.rotate
+#[derive(Clone, Debug)]
+pub struct EncryptedPersist<P: Persist> {
+    inner: P,
+    current: Arc<EncryptionKey>,
+    previous: Vec<Arc<EncryptionKey>>,
+    allow_plaintext: bool,
+}
+
+impl<P: Persist> EncryptedPersist<P> {
+    /// Wrap a persistence, encrypting with the key.
+    pub fn new(inner: P, key: EncryptionKey) -> Self {
+        EncryptedPersist {
+            inner,
+            current: Arc::new(key),
+            previous: vec![],
+            allow_plaintext: false,
+        }
+    }
+
+    /// An earlier key that values might still be encrypted with.
+    pub fn previous_key(mut self, key: EncryptionKey) -> Self {
+        self.previous.push(Arc::new(key));
+        self
+    }
+
+    /// Read private keys that are not encrypted, i.e. saved before the persistence was
+    /// wrapped. They are encrypted by [`rotate`]. Defaults to `false`, since it allows
+    /// replacing an encrypted key with a plaintext one.
+    ///
+    /// [`rotate`]: struct.EncryptedPersist.html#method.rotate
+    pub fn allow_plaintext(mut self, allow: bool) -> Self {
+        self.allow_plaintext = allow;
+        self
+    }
+
+    /// The wrapped persistence.
+    pub fn inner(&self) -> &P {
+        &self.inner
+    }
+
+    /// Encrypt all private keys of the realm that are not encrypted with the current
+    /// key. Returns the number of re-encrypted values. Requires the wrapped persistence
+    /// to implement `list`.
+    pub fn rotate(&self, realm: &str) -> Result<usize> {
+        let mut count = 0;
+        for kind in [PersistKind::AccountPrivateKey, PersistKind::PrivateKey] {
+            for name in self.inner.list(realm, kind)? {
+                let key = PersistKey::new(realm, kind, &name);
+                let _lock = self.inner.lock(&key)?;
+                let value = match self.inner.get(&key)? {
+                    Some(v) => v,
+                    None => continue,
+                };
+                if key_id(&value) == Some(self.current.id.as_bytes()) {
+                    continue;
+                }
+                let plain = self.decrypt(&key, &value)?;
+                self.inner.put(&key, &self.encrypt(&key, &plain)?)?;
+                count += 1;
+            }
+        }
+        Ok(count)
+    }
+
+    fn encrypt(&self, key: &PersistKey, value: &[u8]) -> Result<Vec<u8>> {
+        let enc = &self.current;
+        let mut nonce = [0_u8; NONCE_LEN];
+        random_bytes(&mut nonce)?;
+
+        let mut in_out = value.to_vec();
+        enc.key
+            .seal_in_place_append_tag(
+                Nonce::assume_unique_for_key(nonce),
+                Aad::from(aad(&enc.id, key)),
+                &mut in_out,
+            )
+            .map_err(|_| Error::Crypto("Failed to encrypt".into()))?;
+
+        let mut out = MAGIC.to_vec();
+        out.push(enc.id.len() as u8);
+        out.extend(enc.id.as_bytes());
+        out.extend(nonce);
+        out.extend(in_out);
+        Ok(out)
+    }
+
+    fn decrypt(&self, key: &PersistKey, value: &[u8]) -> Result<Vec<u8>> {
+        let id = match key_id(value) {
+            Some(id) => id,
+            None if self.allow_plaintext => return Ok(value.to_vec()),
+            None => return Err(Error::Crypto(format!("Value is not encrypted: {}", key))),
+        };
+        let enc = std::iter::once(&self.current)
+            .chain(&self.previous)
+            .find(|k| k.id.as_bytes() == id)
+            .ok_or_else(|| {
+                Error::Crypto(format!(
+                    "Unknown encryption key id: {}",
+                    String::from_utf8_lossy(id)
+                ))
+            })?;
+
+        let rest = &value[MAGIC.len() + 1 + id.len()..];
+        if rest.len() < NONCE_LEN {
+            return Err(Error::Crypto(format!(
+                "Encrypted value is truncated: {}",
+                key
+            )));
+        }
+        let (nonce, ciphertext) = rest.split_at(NONCE_LEN);
+        let nonce = Nonce::try_assume_unique_for_key(nonce)
+            .map_err(|_| Error::Crypto("Invalid nonce".into()))?;
+        let mut in_out = ciphertext.to_vec();
+        let plain = enc
+            .key
+            .open_in_place(nonce, Aad::from(aad(&enc.id, key)), &mut in_out)
+            .map_err(|_| {
+                Error::Crypto(format!(
+                    "Encrypted value is modified or the key is wrong: {}",
+                    key
+                ))
+            })?;
+        Ok(plain.to_vec())
+    }
+}
+
+fn is_encrypted(kind: PersistKind) -> bool {
+    kind.is_private_key()
+}
+
+/// The key id of an encrypted value, `None` if the value isn't encrypted.
+fn key_id(value: &[u8]) -> Option<&[u8]> {
+    let rest = value.strip_prefix(MAGIC)?;
+    let (len, rest) = rest.split_first()?;
+    rest.get(..*len as usize)
+}
+
+/// Binds the ciphertext to the key id and where it is stored.
+fn aad(key_id: &str, key: &PersistKey) -> Vec<u8> {
+    let kind = match key.kind {
+        PersistKind::AccountPrivateKey => 0_u8,
+        PersistKind::PrivateKey => 1,
+        PersistKind::Certificate => 2,
+        PersistKind::OcspResponse => 3,
+        PersistKind::Revocation => 4,
+        PersistKind::PublicKey => 5,
+    };
+    let mut aad = MAGIC.to_vec();
+    aad.push(key_id.len() as u8);
+    aad.extend(key_id.as_bytes());
+    aad.extend(key.realm.to_be_bytes());
+    aad.push(kind);
+    aad.extend(key.key.as_bytes());
+    aad
+}
+
+impl<P: Persist> Persist for EncryptedPersist<P> {
+    fn put(&self, key: &PersistKey, value: &[u8]) -> Result<()> {
+        if is_encrypted(key.kind) {
+            self.inner.put(key, &self.encrypt(key, value)?)
+        } else {
+            self.inner.put(key, value)
+        }
+    }
+
+    fn get(&self, key: &PersistKey) -> Result<Option<Vec<u8>>> {
+        match self.inner.get(key)? {
+            Some(v) if is_encrypted(key.kind) => Ok(Some(self.decrypt(key, &v)?)),
+            v => Ok(v),
+        }
+    }
+
+    fn put_all(&self, values: &[(PersistKey, &[u8])]) -> Result<()> {
+        let mut encrypted = vec![];
+        for (key, value) in values {
+            encrypted.push(if is_encrypted(key.kind) {
+                self.encrypt(key, value)?
+            } else {
+                value.to_vec()
+            });
+        }
+        let values: Vec<(PersistKey, &[u8])> = values
+            .iter()
+            .zip(&encrypted)
+            .map(|((key, _), value)| (*key, &value[..]))
+            .collect();
+        self.inner.put_all(&values)
+    }
+
+    fn migrate_realm(&self, realm: &str) -> Result<usize> {
+        self.inner.migrate_realm(realm)
+    }
+
+    fn delete(&self, key: &PersistKey) -> Result<()> {
+        self.inner.delete(key)
+    }
+
+    fn list(&self, realm: &str, kind: PersistKind) -> Result<Vec<String>> {
+        self.inner.list(realm, kind)
+    }
+
+    fn lock(&self, key: &PersistKey) -> Result<PersistLock> {
+        self.inner.lock(key)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::persist::MemoryPersist;
+
+    fn key(id: &str, b: u8) -> EncryptionKey {
+        EncryptionKey::from_bytes(id, &[b; 32]).unwrap()
+    }
+
+    #[test]
+    fn test_encrypt_private_keys() -> Result<()> {
+        let inner = MemoryPersist::new();
+        let persist = EncryptedPersist::new(inner.clone(), key("k1", 1));
+        let pk = PersistKey::new("foo@bar.com", PersistKind::PrivateKey, "example.com");
+        let crt = PersistKey::new("foo@bar.com", PersistKind::Certificate, "example.com");
+        persist.put_all(&[(pk, b"SECRET KEY"), (crt, b"CERT")])?;
+
+        assert_eq!(persist.get(&pk)?, Some(b"SECRET KEY".to_vec()));
+        let raw = inner.get(&pk)?.unwrap();
+        assert!(raw.starts_with(MAGIC));
+        assert!(!raw.windows(6).any(|w| w == b"SECRET"));
+        assert_eq!(inner.get(&crt)?, Some(b"CERT".to_vec()));
+
+        // tampering
+        let mut modified = raw.clone();
+        *modified.last_mut().unwrap() ^= 1;
+        inner.put(&pk, &modified)?;
+        assert!(persist.get(&pk).is_err());
+
+        // moved to another key
+        let other = PersistKey::new("foo@bar.com", PersistKind::PrivateKey, "other.com");
+        inner.put(&other, &raw)?;
+        assert!(persist.get(&other).is_err());
+
+        // replaced by plaintext
+        inner.put(&pk, b"PLAIN")?;
+        assert!(persist.get(&pk).is_err());
+        let lenient = persist.clone().allow_plaintext(true);
+        assert_eq!(lenient.get(&pk)?, Some(b"PLAIN".to_vec()));
+        Ok(())
+    }
+
+    #[test]
+    fn test_rotate() -> Result<()> {
+        let inner = MemoryPersist::new();
+        let old = EncryptedPersist::new(inner.clone(), key("old", 1));
+        let pk = PersistKey::new(
+            "foo@bar.com",
+            PersistKind::AccountPrivateKey,
+            "acme_account",
+        );
+        old.put(&pk, b"ACCOUNT")?;
+
+        let new = EncryptedPersist::new(inner.clone(), key("new", 2));
+        assert!(new.get(&pk).is_err());
+
+        let new = new.previous_key(key("old", 1));
+        assert_eq!(new.get(&pk)?, Some(b"ACCOUNT".to_vec()));
+        assert_eq!(new.rotate("foo@bar.com")?, 1);
+        assert_eq!(new.rotate("foo@bar.com")?, 0);
+
+        let new = EncryptedPersist::new(inner, key("new", 2));
+        assert_eq!(new.get(&pk)?, Some(b"ACCOUNT".to_vec()));
+        Ok(())
+    }
+
+    #[test]
+    fn test_passphrase() -> Result<()> {
+        let inner = MemoryPersist::new();
+        let salt = b"acme-lib test salt";
+        let k1 = EncryptionKey::from_passphrase("p", "secret", salt)?;
+        let k2 = EncryptionKey::from_passphrase("p", "secret", salt)?;
+        let pk = PersistKey::new("foo@bar.com", PersistKind::PrivateKey, "example.com");
+        EncryptedPersist::new(inner.clone(), k1).put(&pk, b"KEY")?;
+        assert_eq!(
+            EncryptedPersist::new(inner.clone(), k2).get(&pk)?,
+            Some(b"KEY".to_vec())
+        );
+        let wrong = EncryptionKey::from_passphrase("p", "wrong", salt)?;
+        assert!(EncryptedPersist::new(inner, wrong).get(&pk).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_derive_key() -> Result<()> {
+        // Argon2id, v0x13, m=19456, t=2, p=1 as computed by the Python cryptography package
+        let expected = [
+            0x1d, 0x58, 0x82, 0x04, 0x0c, 0x4a, 0xc1, 0xdf, 0x9f, 0xaa, 0x82, 0xfd, 0xe8, 0x5a,
+            0x1d, 0x4d, 0x6b, 0x4b, 0xf7, 0xcd, 0xd7, 0xde, 0xb7, 0xb8, 0x75, 0x0a, 0x34, 0x0c,
+            0x4a, 0xd8, 0x82, 0xf8,
+        ];
+        assert_eq!(derive_key("secret", b"acme-lib test salt")?, expected);
+        Ok(())
+    }
+}
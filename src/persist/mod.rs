@@ -0,0 +1,888 @@
+//! Pluggable persistence.
+//!
+//! The persistence is a simple key-value store. The intention is to make it simple to implement
+//! other persistence mechanisms than the provided ones, such as against a databases.
+
+#[cfg(unix)]
+use std::os::unix::fs::OpenOptionsExt;
+use std::{
+    collections::HashMap,
+    convert::TryInto,
+    fs,
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use crate::{crypto::sha256, Result};
+
+#[cfg(feature = "encryption")]
+mod encrypted;
+
+#[cfg(feature = "encryption")]
+pub use self::encrypted::{EncryptedPersist, EncryptionKey};
+
+#[cfg(feature = "sqlite")]
+mod sqlite;
+
+#[cfg(feature = "sqlite")]
+pub use self::sqlite::{SqlitePersist, SqliteTimestamps};
+
+/// Kinds of [persistence keys](struct.PersistKey.html).
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum PersistKind {
+    /// Persisted account private key.
+    AccountPrivateKey,
+    /// Persisted private key.
+    PrivateKey,
+    /// Persisted certificate.
+    Certificate,
+    /// Persisted OCSP response of a certificate, for stapling.
+    OcspResponse,
+    /// Persisted revocation of a certificate, keyed by its SHA-256 fingerprint.
+    Revocation,
+    /// Persisted public key of a certificate whose private key is held externally, in
+    /// place of a `PrivateKey`.
+    PublicKey,
+}
+
+impl PersistKind {
+    fn name(self) -> &'static str {
+        match self {
+            PersistKind::Certificate => "crt",
+            PersistKind::PrivateKey => "key",
+            PersistKind::AccountPrivateKey => "key",
+            PersistKind::OcspResponse => "ocsp",
+            PersistKind::Revocation => "rev",
+            PersistKind::PublicKey => "pub",
+        }
+    }
+
+    /// Whether values of the kind are private keys.
+    pub(crate) fn is_private_key(self) -> bool {
+        matches!(
+            self,
+            PersistKind::AccountPrivateKey | PersistKind::PrivateKey
+        )
+    }
+}
+
+/// Key for a value in the persistence.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub struct PersistKey<'a> {
+    pub realm: u64,
+    pub kind: PersistKind,
+    pub key: &'a str,
+}
+
+impl<'a> PersistKey<'a> {
+    /// Create a new key under a "realm", kind and key. The realm is an opaque hash
+    /// of the given realm string, see [`realm_hash`].
+    ///
+    /// The realm is in normally defined as the account contact email, however it depends
+    /// on how the `Account` object is accessed, see [`account_with_realm`].
+    ///
+    /// [`realm_hash`]: fn.realm_hash.html
+    /// [`account_with_realm`]: ../struct.Directory.html#method.account_with_realm
+    pub fn new(realm: &str, kind: PersistKind, key: &'a str) -> Self {
+        let realm = realm_hash(realm);
+        PersistKey { realm, kind, key }
+    }
+
+    /// Create the key the way acme-lib 0.8.2 and earlier did, with the realm hashed
+    /// by [`legacy_realm_hash`]. Use this to move values of a custom [`Persist`] to the
+    /// keys made by [`new`].
+    ///
+    /// [`legacy_realm_hash`]: fn.legacy_realm_hash.html
+    /// [`Persist`]: trait.Persist.html
+    /// [`new`]: struct.PersistKey.html#method.new
+    pub fn legacy(realm: &str, kind: PersistKind, key: &'a str) -> Self {
+        let realm = legacy_realm_hash(realm);
+        PersistKey { realm, kind, key }
+    }
+
+    pub(crate) fn path_in(&self, dir: &Path) -> PathBuf {
+        let mut path = dir.join(self.to_string());
+        path.set_extension(self.kind.name());
+        path
+    }
+}
+
+impl<'a> std::fmt::Display for PersistKey<'a> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{}_{}_{}",
+            self.realm,
+            self.kind.name(),
+            encode_key(self.key)
+        )
+    }
+}
+
+/// The key of the account private key, which the `FilePersist` can't tell from the
+/// certificate private keys by the file name alone.
+pub(crate) const ACCOUNT_KEY: &str = "acme_account";
+
+fn encode_key(key: &str) -> String {
+    key.replace('.', "_").replace('*', "STAR")
+}
+
+/// Reverse of `encode_key` for DNS names. Other keys might not come back the same.
+fn decode_key(encoded: &str) -> String {
+    encoded.replace("STAR", "*").replace('_', ".")
+}
+
+/// The realm hash of a [`PersistKey`]: the first 8 bytes of the SHA-256 of the realm,
+/// as a big endian integer.
+///
+/// This is stable and will not change between releases of acme-lib or Rust.
+///
+/// [`PersistKey`]: struct.PersistKey.html
+pub fn realm_hash(realm: &str) -> u64 {
+    let digest = sha256(realm.as_bytes());
+    u64::from_be_bytes(digest[..8].try_into().unwrap())
+}
+
+/// The realm hash of acme-lib 0.8.2 and earlier.
+///
+/// That used std's `DefaultHasher`, which std may change between Rust releases. This is a
+/// copy of what it was (SipHash-1-3 with zero keys over the realm bytes and a `0xff`), so
+/// values persisted with the old scheme can be found regardless of the Rust version.
+pub fn legacy_realm_hash(realm: &str) -> u64 {
+    let mut data = realm.as_bytes().to_vec();
+    data.push(0xff);
+    siphash13(&data)
+}
+
+fn siphash13(data: &[u8]) -> u64 {
+    let mut v = [
+        0x736f_6d65_7073_6575_u64,
+        0x646f_7261_6e64_6f6d,
+        0x6c79_6765_6e65_7261,
+        0x7465_6462_7974_6573,
+    ];
+    fn round(v: &mut [u64; 4]) {
+        v[0] = v[0].wrapping_add(v[1]);
+        v[1] = v[1].rotate_left(13) ^ v[0];
+        v[0] = v[0].rotate_left(32);
+        v[2] = v[2].wrapping_add(v[3]);
+        v[3] = v[3].rotate_left(16) ^ v[2];
+        v[0] = v[0].wrapping_add(v[3]);
+        v[3] = v[3].rotate_left(21) ^ v[0];
+        v[2] = v[2].wrapping_add(v[1]);
+        v[1] = v[1].rotate_left(17) ^ v[2];
+        v[2] = v[2].rotate_left(32);
+    }
+    fn compress(m: u64, v: &mut [u64; 4]) {
+        v[3] ^= m;
+        round(v);
+        v[0] ^= m;
+    }
+
+    let chunks = data.chunks_exact(8);
+    let rest = chunks.remainder();
+    for chunk in chunks {
+        compress(u64::from_le_bytes(chunk.try_into().unwrap()), &mut v);
+    }
+    let mut last = (data.len() as u64) << 56;
+    for (i, b) in rest.iter().enumerate() {
+        last |= (*b as u64) << (8 * i);
+    }
+    compress(last, &mut v);
+
+    v[2] ^= 0xff;
+    for _ in 0..3 {
+        round(&mut v);
+    }
+    v[0] ^ v[1] ^ v[2] ^ v[3]
+}
+
+/// A lock taken with [`Persist::lock`], released when dropped.
+///
+/// [`Persist::lock`]: trait.Persist.html#method.lock
+#[must_use = "the lock is released when dropped"]
+pub struct PersistLock {
+    _guard: Option<Box<dyn Send>>,
+}
+
+impl PersistLock {
+    /// A lock that is held until the guard is dropped.
+    pub fn new<G: Send + 'static>(guard: G) -> Self {
+        PersistLock {
+            _guard: Some(Box::new(guard)),
+        }
+    }
+
+    /// For a persistence without locking.
+    pub fn none() -> Self {
+        PersistLock { _guard: None }
+    }
+}
+
+impl std::fmt::Debug for PersistLock {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "PersistLock")
+    }
+}
+
+/// Trait for a persistence implementation.
+///
+/// Implementation must be clonable and thread safe (Send). This can easily be done by
+/// wrapping the implemetation an `Arc<Mutex<P>>`.
+pub trait Persist: Clone + Send {
+    /// Store the given bytes under the given key.
+    fn put(&self, key: &PersistKey, value: &[u8]) -> Result<()>;
+    /// Read the bytes stored under the given key.
+    ///
+    /// `None` if the value doesn't exist.
+    fn get(&self, key: &PersistKey) -> Result<Option<Vec<u8>>>;
+
+    /// Store several values such that either all or none of them are replaced, i.e. a
+    /// private key and the certificate for it.
+    ///
+    /// The default calls `put` for each value, which is not atomic.
+    fn put_all(&self, values: &[(PersistKey, &[u8])]) -> Result<()> {
+        for (key, value) in values {
+            self.put(key, value)?;
+        }
+        Ok(())
+    }
+
+    /// Move the values of the realm saved by acme-lib 0.8.2 and earlier to the keys
+    /// made by [`PersistKey::new`]. Returns the number of moved values.
+    ///
+    /// This is called when an account is accessed. The default does nothing. Values
+    /// already under the new keys are never overwritten.
+    ///
+    /// [`PersistKey::new`]: struct.PersistKey.html#method.new
+    fn migrate_realm(&self, _realm: &str) -> Result<usize> {
+        Ok(0)
+    }
+
+    /// Remove the value stored under the given key. Removing a value that doesn't
+    /// exist is not an error.
+    ///
+    /// The default fails, since a persistence with only `put` can't remove values.
+    fn delete(&self, key: &PersistKey) -> Result<()> {
+        Err(format!("Persistence doesn't support delete: {}", key).into())
+    }
+
+    /// The keys of all values of a kind in the realm, i.e. the primary names of the
+    /// certificates. The realm is the string given to [`PersistKey::new`], not the hash.
+    ///
+    /// The default fails, since a persistence with only `get` can't enumerate values.
+    ///
+    /// [`PersistKey::new`]: struct.PersistKey.html#method.new
+    fn list(&self, realm: &str, kind: PersistKind) -> Result<Vec<String>> {
+        let _ = (realm, kind);
+        Err("Persistence doesn't support list".into())
+    }
+
+    /// Take an exclusive lock for the key, waiting until it's available. This should
+    /// exclude other processes using the same persistence.
+    ///
+    /// The library locks the account private key of the realm while looking up or
+    /// creating the account, and the certificate while saving it with its private key.
+    ///
+    /// The default doesn't lock anything.
+    fn lock(&self, key: &PersistKey) -> Result<PersistLock> {
+        let _ = key;
+        Ok(PersistLock::none())
+    }
+}
+
+/// Memory implementation for dev/testing.
+///
+/// The entries in memory are never saved to disk and are gone when the process dies.
+///
+/// Since the API is [rate limited] it's not a good idea to use this in production code.
+///
+/// [rate limited]: ../index.html#rate-limits
+#[derive(Clone, Default)]
+pub struct MemoryPersist {
+    inner: Arc<Mutex<HashMap<MemoryKey, Vec<u8>>>>,
+}
+
+/// The realm hash, kind and key.
+type MemoryKey = (u64, PersistKind, String);
+
+impl MemoryPersist {
+    /// Create a memory persistence for testing.
+    pub fn new() -> Self {
+        MemoryPersist {
+            ..Default::default()
+        }
+    }
+}
+
+fn memory_key(key: &PersistKey) -> MemoryKey {
+    (key.realm, key.kind, key.key.to_string())
+}
+
+impl Persist for MemoryPersist {
+    fn put(&self, key: &PersistKey, value: &[u8]) -> Result<()> {
+        let mut lock = self.inner.lock().unwrap();
+        lock.insert(memory_key(key), value.to_owned());
+        Ok(())
+    }
+
+    fn get(&self, key: &PersistKey) -> Result<Option<Vec<u8>>> {
+        let lock = self.inner.lock().unwrap();
+        Ok(lock.get(&memory_key(key)).cloned())
+    }
+
+    fn put_all(&self, values: &[(PersistKey, &[u8])]) -> Result<()> {
+        let mut lock = self.inner.lock().unwrap();
+        for (key, value) in values {
+            lock.insert(memory_key(key), value.to_vec());
+        }
+        Ok(())
+    }
+
+    fn delete(&self, key: &PersistKey) -> Result<()> {
+        let mut lock = self.inner.lock().unwrap();
+        lock.remove(&memory_key(key));
+        Ok(())
+    }
+
+    fn list(&self, realm: &str, kind: PersistKind) -> Result<Vec<String>> {
+        let realm = realm_hash(realm);
+        let lock = self.inner.lock().unwrap();
+        let mut keys: Vec<String> = lock
+            .keys()
+            .filter(|(r, k, _)| *r == realm && *k == kind)
+            .map(|(_, _, key)| key.clone())
+            .collect();
+        keys.sort();
+        Ok(keys)
+    }
+}
+
+/// Simple file persistence.
+///
+/// Each key is saved under a unique filename, `<realm hash>_<kind>_<key>.<ext>`. Files saved
+/// by acme-lib 0.8.2 and earlier are renamed to the current realm hash when the account
+/// is accessed, see [`migrate_realm`].
+///
+/// [`migrate_realm`]: trait.Persist.html#method.migrate_realm
+///
+/// Values are written to a temporary file that is synced to disk and then renamed over
+/// the old file, so a crash never leaves a truncated key or certificate. [`put_all`]
+/// records the renames in a journal first, and a `FilePersist` that finds a journal
+/// left by a crash completes the renames before reading anything. A journal only gets
+/// its name once it's completely written, and the recovery waits for all `put_all` in
+/// progress in the directory, so it never completes a transaction still being written.
+///
+/// [`put_all`]: trait.Persist.html#method.put_all
+///
+/// Locks are advisory locks on hidden `.lock` files in the directory, which excludes
+/// other processes with a `FilePersist` in the same directory.
+#[derive(Clone)]
+pub struct FilePersist {
+    dir: PathBuf,
+    recovered: Arc<AtomicBool>,
+}
+
+/// Makes temporary file names unique within the process.
+static TEMP_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+const JOURNAL_EXT: &str = "journal";
+/// A journal being written.
+const JOURNAL_TEMP_EXT: &str = "journal-tmp";
+/// Shared by `put_all` while it writes and commits a journal, exclusive for the recovery.
+const JOURNAL_LOCK: &str = ".journal.lock";
+
+impl FilePersist {
+    /// Create a file persistence in the directory pointed out by the `dir` given.
+    ///
+    /// The directory must be writable.
+    pub fn new<P: AsRef<Path>>(dir: P) -> Self {
+        FilePersist {
+            dir: dir.as_ref().to_path_buf(),
+            recovered: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// A hidden file name in the directory that no key maps to.
+    fn temp_path(&self, name: &str, ext: &str) -> PathBuf {
+        let n = TEMP_COUNTER.fetch_add(1, Ordering::SeqCst);
+        let name = format!(".{}.{}-{}.{}", name, std::process::id(), n, ext);
+        self.dir.join(name)
+    }
+
+    /// Write the value to a new temporary file and sync it to disk.
+    fn write_temp(&self, key: &PersistKey, value: &[u8]) -> Result<PathBuf> {
+        let path = self.temp_path(&key.to_string(), "tmp");
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        {
+            if key.kind.is_private_key() {
+                options.mode(0o600);
+            }
+        }
+        let res = options.open(&path).and_then(|mut file| {
+            file.write_all(value)?;
+            file.sync_all()
+        });
+        if let Err(e) = res {
+            let _ = fs::remove_file(&path);
+            return Err(e.into());
+        }
+        Ok(path)
+    }
+
+    /// Sync the directory so renames survive a crash.
+    fn sync_dir(&self) -> Result<()> {
+        #[cfg(unix)]
+        fs::File::open(&self.dir)?.sync_all()?;
+        Ok(())
+    }
+
+    /// Lock out the recovery, or with `exclusive` all `put_all`, in the directory.
+    fn lock_journals(&self, exclusive: bool) -> Result<fs::File> {
+        // never removed, since that would race with another process locking it
+        let file = fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(self.dir.join(JOURNAL_LOCK))?;
+        if exclusive {
+            file.lock()?;
+        } else {
+            file.lock_shared()?;
+        }
+        Ok(file)
+    }
+
+    /// Write all values to temporary files and the journal of renames. Returns the journal.
+    fn prepare(&self, values: &[(PersistKey, &[u8])]) -> Result<PathBuf> {
+        let mut renames = vec![];
+        let mut journal = String::new();
+        for (key, value) in values {
+            let temp = match self.write_temp(key, value) {
+                Ok(temp) => temp,
+                Err(e) => {
+                    for (temp, _) in renames {
+                        let _ = fs::remove_file(temp);
+                    }
+                    return Err(e);
+                }
+            };
+            let target = key.path_in(&self.dir);
+            journal.push_str(&format!("{}\t{}\n", file_name(&temp), file_name(&target)));
+            renames.push((temp, target));
+        }
+        // only a complete journal may be found by the recovery
+        let temp = self.temp_path("put_all", JOURNAL_TEMP_EXT);
+        let path = temp.with_extension(JOURNAL_EXT);
+        let res = fs::File::create(&temp).and_then(|mut file| {
+            file.write_all(journal.as_bytes())?;
+            file.sync_all()?;
+            fs::rename(&temp, &path)
+        });
+        if let Err(e) = res {
+            let _ = fs::remove_file(&temp);
+            for (temp, _) in renames {
+                let _ = fs::remove_file(temp);
+            }
+            return Err(e.into());
+        }
+        self.sync_dir()?;
+        Ok(path)
+    }
+
+    /// Do the renames of a journal and remove it.
+    fn commit(&self, journal: &Path) -> Result<()> {
+        let content = fs::read_to_string(journal)?;
+        for line in content.lines() {
+            let (temp, target) = match line.split_once('\t') {
+                Some(x) => x,
+                None => continue,
+            };
+            match fs::rename(self.dir.join(temp), self.dir.join(target)) {
+                // already renamed before a crash
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                res => res?,
+            }
+        }
+        self.sync_dir()?;
+        match fs::remove_file(journal) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Complete any transactions left by a crash. Done once per `FilePersist`.
+    fn recover(&self) -> Result<()> {
+        if self.recovered.load(Ordering::SeqCst) {
+            return Ok(());
+        }
+        if !self.dir.exists() {
+            return Ok(());
+        }
+        // any journal found while no put_all is in progress was left by a crash
+        let _lock = self.lock_journals(true)?;
+        for entry in fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            match path.extension().and_then(|e| e.to_str()) {
+                Some(JOURNAL_EXT) => {
+                    info!("Complete interrupted write: {:?}", path);
+                    self.commit(&path)?;
+                }
+                Some(JOURNAL_TEMP_EXT) => {
+                    debug!("Remove incomplete journal: {:?}", path);
+                    fs::remove_file(&path)?;
+                }
+                _ => {}
+            }
+        }
+        self.recovered.store(true, Ordering::SeqCst);
+        Ok(())
+    }
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default()
+}
+
+impl Persist for FilePersist {
+    fn put(&self, key: &PersistKey, value: &[u8]) -> Result<()> {
+        self.recover()?;
+        let temp = self.write_temp(key, value)?;
+        if let Err(e) = fs::rename(&temp, key.path_in(&self.dir)) {
+            let _ = fs::remove_file(&temp);
+            return Err(e.into());
+        }
+        self.sync_dir()
+    }
+
+    fn put_all(&self, values: &[(PersistKey, &[u8])]) -> Result<()> {
+        self.recover()?;
+        let _lock = self.lock_journals(false)?;
+        let journal = self.prepare(values)?;
+        self.commit(&journal)
+    }
+
+    fn get(&self, key: &PersistKey) -> Result<Option<Vec<u8>>> {
+        self.recover()?;
+        let path = key.path_in(&self.dir);
+        let ret = if let Ok(mut file) = fs::File::open(path) {
+            let mut v = vec![];
+            file.read_to_end(&mut v)?;
+            Some(v)
+        } else {
+            None
+        };
+        Ok(ret)
+    }
+
+    fn delete(&self, key: &PersistKey) -> Result<()> {
+        self.recover()?;
+        match fs::remove_file(key.path_in(&self.dir)) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(e.into()),
+            Ok(()) => self.sync_dir(),
+        }
+    }
+
+    /// Keys are decoded from the file names, which works for DNS names but not
+    /// for keys with `_` or `STAR` in them.
+    fn list(&self, realm: &str, kind: PersistKind) -> Result<Vec<String>> {
+        self.recover()?;
+        let prefix = format!("{}_{}_", realm_hash(realm), kind.name());
+        let suffix = format!(".{}", kind.name());
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => return Err(e.into()),
+        };
+        let mut keys = vec![];
+        for entry in entries {
+            let name = entry?.file_name();
+            let encoded = match name
+                .to_str()
+                .and_then(|n| n.strip_prefix(&prefix))
+                .and_then(|n| n.strip_suffix(&suffix))
+            {
+                Some(encoded) => encoded,
+                None => continue,
+            };
+            // account and certificate private keys share the file name scheme
+            let is_account = encoded == ACCOUNT_KEY;
+            match kind {
+                PersistKind::AccountPrivateKey if !is_account => continue,
+                PersistKind::PrivateKey if is_account => continue,
+                _ => {}
+            }
+            keys.push(if is_account {
+                ACCOUNT_KEY.to_string()
+            } else {
+                decode_key(encoded)
+            });
+        }
+        keys.sort();
+        Ok(keys)
+    }
+
+    fn lock(&self, key: &PersistKey) -> Result<PersistLock> {
+        // never removed, since that would race with another process locking it
+        let path = self.dir.join(format!(".{}.lock", key));
+        let file = fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        debug!("Wait for lock: {}", key);
+        file.lock()?;
+        Ok(PersistLock::new(file))
+    }
+
+    fn migrate_realm(&self, realm: &str) -> Result<usize> {
+        let legacy = format!("{}_", legacy_realm_hash(realm));
+        let current = format!("{}_", realm_hash(realm));
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            // nothing to migrate in a directory that doesn't exist yet
+            Err(_) => return Ok(0),
+        };
+        let mut count = 0;
+        for entry in entries {
+            let entry = entry?;
+            let name = entry.file_name();
+            let rest = match name.to_str().and_then(|n| n.strip_prefix(&legacy)) {
+                Some(rest) => rest,
+                None => continue,
+            };
+            let target = self.dir.join(format!("{}{}", current, rest));
+            if target.exists() {
+                debug!("Not migrating {:?}, {:?} exists", name, target);
+                continue;
+            }
+            debug!("Migrate {:?} to {:?}", name, target);
+            fs::rename(entry.path(), target)?;
+            count += 1;
+        }
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn temp_dir(name: &str) -> Result<PathBuf> {
+        let dir = std::env::temp_dir().join(format!("acme-lib-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&dir)?;
+        Ok(dir)
+    }
+
+    #[test]
+    fn test_realm_hash() {
+        // values of std's DefaultHasher as used by acme-lib 0.8.2
+        assert_eq!(legacy_realm_hash(""), 3476900567878811119);
+        assert_eq!(legacy_realm_hash("foo@bar.com"), 8372203050500229748);
+        assert_eq!(legacy_realm_hash("12345678"), 4669193037608055501);
+        assert_eq!(
+            legacy_realm_hash("a longer realm string with more than sixteen bytes"),
+            1464840655693099886
+        );
+
+        // sha256("foo@bar.com") is 0c7e6a405862e402eb76...
+        assert_eq!(realm_hash("foo@bar.com"), 0x0c7e_6a40_5862_e402);
+    }
+
+    #[test]
+    fn test_migrate_file_persist() -> Result<()> {
+        let dir = temp_dir("migrate")?;
+        let persist = FilePersist::new(&dir);
+
+        let old = PersistKey::legacy("foo@bar.com", PersistKind::Certificate, "example.com");
+        fs::write(old.path_in(&dir), b"CERT")?;
+        let other = PersistKey::legacy("other@bar.com", PersistKind::Certificate, "example.com");
+        fs::write(other.path_in(&dir), b"OTHER")?;
+
+        assert_eq!(persist.migrate_realm("foo@bar.com")?, 1);
+        assert_eq!(persist.migrate_realm("foo@bar.com")?, 0);
+
+        let new = PersistKey::new("foo@bar.com", PersistKind::Certificate, "example.com");
+        assert_eq!(persist.get(&new)?, Some(b"CERT".to_vec()));
+        assert_eq!(persist.get(&old)?, None);
+        assert_eq!(persist.get(&other)?, Some(b"OTHER".to_vec()));
+
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_recover_waits_for_put_all() -> Result<()> {
+        let dir = temp_dir("recover-wait")?;
+        let persist = FilePersist::new(&dir);
+        let crt = PersistKey::new("foo@bar.com", PersistKind::Certificate, "example.com");
+        persist.put(&crt, b"CERT1")?;
+
+        // a put_all in progress in another process
+        let lock = persist.lock_journals(false)?;
+        let journal = persist.prepare(&[(crt, b"CERT2")])?;
+
+        let dir2 = dir.clone();
+        let reader = std::thread::spawn(move || {
+            let crt = PersistKey::new("foo@bar.com", PersistKind::Certificate, "example.com");
+            FilePersist::new(&dir2).get(&crt)
+        });
+        std::thread::sleep(std::time::Duration::from_millis(100));
+        assert!(journal.exists());
+        persist.commit(&journal)?;
+        drop(lock);
+        assert_eq!(reader.join().unwrap()?, Some(b"CERT2".to_vec()));
+
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_file_put_all_and_recover() -> Result<()> {
+        let dir = temp_dir("put-all")?;
+        let persist = FilePersist::new(&dir);
+        let key = PersistKey::new("foo@bar.com", PersistKind::PrivateKey, "example.com");
+        let crt = PersistKey::new("foo@bar.com", PersistKind::Certificate, "example.com");
+        persist.put_all(&[(key, b"KEY1"), (crt, b"CERT1")])?;
+        assert_eq!(persist.get(&key)?, Some(b"KEY1".to_vec()));
+        assert_eq!(persist.get(&crt)?, Some(b"CERT1".to_vec()));
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(key.path_in(&dir))?.permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        // crash after the journal is written, before any rename
+        let journal = persist.prepare(&[(key, b"KEY2"), (crt, b"CERT2")])?;
+        assert!(journal.exists());
+        assert_eq!(persist.get(&crt)?, Some(b"CERT1".to_vec()));
+
+        // the next process completes the transaction
+        let persist = FilePersist::new(&dir);
+        assert_eq!(persist.get(&key)?, Some(b"KEY2".to_vec()));
+        assert_eq!(persist.get(&crt)?, Some(b"CERT2".to_vec()));
+        assert!(!journal.exists());
+
+        // only the two values are left, besides the lock file
+        let files: Vec<_> = fs::read_dir(&dir)?
+            .map(|e| e.map(|e| e.file_name()))
+            .collect::<std::result::Result<_, _>>()?;
+        assert_eq!(files.len(), 3, "{:?}", files);
+        assert!(files.iter().any(|f| f == JOURNAL_LOCK));
+
+        // an incomplete journal is removed, not committed
+        fs::write(dir.join(".half.tmp"), b"HALF")?;
+        let half = dir.join(format!(".put_all.1-1.{}", JOURNAL_TEMP_EXT));
+        fs::write(
+            &half,
+            format!(".half.tmp\t{}", file_name(&crt.path_in(&dir))),
+        )?;
+        let persist = FilePersist::new(&dir);
+        assert_eq!(persist.get(&crt)?, Some(b"CERT2".to_vec()));
+        assert!(!half.exists());
+
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_file_lock() -> Result<()> {
+        use std::{sync::mpsc, thread, time::Duration};
+
+        let dir = temp_dir("lock")?;
+        let persist = FilePersist::new(&dir);
+        let key = PersistKey::new("foo@bar.com", PersistKind::Certificate, "example.com");
+        let lock = persist.lock(&key)?;
+
+        // another FilePersist has its own open file, just like another process
+        let other = FilePersist::new(&dir);
+        let (tx, rx) = mpsc::channel();
+        let handle = thread::spawn(move || -> Result<()> {
+            let key = PersistKey::new("foo@bar.com", PersistKind::Certificate, "example.com");
+            let _lock = other.lock(&key)?;
+            tx.send(()).unwrap();
+            Ok(())
+        });
+
+        // other keys are not locked
+        let _lock2 = persist.lock(&PersistKey::new(
+            "foo@bar.com",
+            PersistKind::Certificate,
+            "x",
+        ))?;
+
+        assert!(rx.recv_timeout(Duration::from_millis(200)).is_err());
+        drop(lock);
+        assert!(rx.recv_timeout(Duration::from_secs(5)).is_ok());
+        handle.join().unwrap()?;
+
+        // the lock files are hidden from list
+        assert!(persist
+            .list("foo@bar.com", PersistKind::Certificate)?
+            .is_empty());
+
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    fn check_delete_and_list<P: Persist>(persist: P) -> Result<()> {
+        let acc = PersistKey::new("foo@bar.com", PersistKind::AccountPrivateKey, ACCOUNT_KEY);
+        persist.put(&acc, b"ACCOUNT")?;
+        for name in ["example.com", "*.example.com"] {
+            let crt = PersistKey::new("foo@bar.com", PersistKind::Certificate, name);
+            persist.put(&crt, b"CERT")?;
+            let key = PersistKey::new("foo@bar.com", PersistKind::PrivateKey, name);
+            persist.put(&key, b"KEY")?;
+        }
+        let other = PersistKey::new("other@bar.com", PersistKind::Certificate, "other.com");
+        persist.put(&other, b"CERT")?;
+
+        let list = |kind| persist.list("foo@bar.com", kind);
+        assert_eq!(
+            list(PersistKind::Certificate)?,
+            ["*.example.com", "example.com"]
+        );
+        assert_eq!(
+            list(PersistKind::PrivateKey)?,
+            ["*.example.com", "example.com"]
+        );
+        assert_eq!(list(PersistKind::AccountPrivateKey)?, [ACCOUNT_KEY]);
+
+        let crt = PersistKey::new("foo@bar.com", PersistKind::Certificate, "example.com");
+        persist.delete(&crt)?;
+        persist.delete(&crt)?;
+        assert_eq!(persist.get(&crt)?, None);
+        assert_eq!(list(PersistKind::Certificate)?, ["*.example.com"]);
+        assert!(persist.list("nobody", PersistKind::Certificate)?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_memory_delete_and_list() -> Result<()> {
+        check_delete_and_list(MemoryPersist::new())
+    }
+
+    #[test]
+    fn test_file_delete_and_list() -> Result<()> {
+        let dir = temp_dir("list")?;
+        let res = check_delete_and_list(FilePersist::new(&dir));
+        fs::remove_dir_all(&dir)?;
+        res
+    }
+    #[test]
+    #[cfg(feature = "sqlite")]
+    fn test_sqlite_delete_and_list() -> Result<()> {
+        check_delete_and_list(SqlitePersist::open_in_memory()?)
+    }
+}
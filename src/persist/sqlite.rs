@@ -0,0 +1,286 @@
+//! Persistence in a SQLite database (requires the `sqlite` feature).
+use rusqlite::{params, Connection, OptionalExtension};
+use std::{
+    convert::TryFrom,
+    path::Path,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use super::{realm_hash, Persist, PersistKey, PersistKind};
+use crate::{crypto::certificate_not_after, Result};
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS acme_persist (
+    realm INTEGER NOT NULL,
+    kind TEXT NOT NULL,
+    key TEXT NOT NULL,
+    value BLOB NOT NULL,
+    not_after INTEGER,
+    created INTEGER NOT NULL,
+    updated INTEGER NOT NULL,
+    PRIMARY KEY (realm, kind, key)
+);
+CREATE INDEX IF NOT EXISTS acme_persist_not_after
+    ON acme_persist (realm, not_after) WHERE not_after IS NOT NULL;
+";
+
+/// Persistence in a single table of a SQLite database.
+///
+/// Values are stored in the table `acme_persist` by realm hash, kind and key, with
+/// the time they were created and last updated. For certificates the end of the
+/// validity is stored too, which makes [`expiring_before`] a simple query.
+///
+/// Writes of several values, i.e. a certificate and its private key, happen in one
+/// transaction. The database can be shared between processes, but `lock` doesn't
+/// exclude other processes from creating the same account concurrently.
+///
+/// [`expiring_before`]: struct.SqlitePersist.html#method.expiring_before
+#[derive(Clone, Debug)]
+pub struct SqlitePersist {
+    conn: Arc<Mutex<Connection>>,
+}
+
+/// When a value in a [`SqlitePersist`] was created and last updated.
+///
+/// [`SqlitePersist`]: struct.SqlitePersist.html
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SqliteTimestamps {
+    pub created: SystemTime,
+    pub updated: SystemTime,
+}
+
+impl SqlitePersist {
+    /// Open or create the database file and the table.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<SqlitePersist> {
+        SqlitePersist::with_connection(Connection::open(path)?)
+    }
+
+    /// A database that only lives in memory, mostly for tests.
+    pub fn open_in_memory() -> Result<SqlitePersist> {
+        SqlitePersist::with_connection(Connection::open_in_memory()?)
+    }
+
+    /// Use an existing connection, creating the table unless it exists.
+    pub fn with_connection(conn: Connection) -> Result<SqlitePersist> {
+        conn.busy_timeout(Duration::from_secs(10))?;
+        conn.execute_batch(SCHEMA)?;
+        Ok(SqlitePersist {
+            conn: Arc::new(Mutex::new(conn)),
+        })
+    }
+
+    /// When the value was created and last updated. `None` if it doesn't exist.
+    pub fn timestamps(&self, key: &PersistKey) -> Result<Option<SqliteTimestamps>> {
+        let conn = self.conn.lock().unwrap();
+        let row = conn
+            .query_row(
+                "SELECT created, updated FROM acme_persist
+                 WHERE realm = ?1 AND kind = ?2 AND key = ?3",
+                params![realm_id(key.realm), kind_id(key.kind), key.key],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()?;
+        Ok(row.map(|(created, updated)| SqliteTimestamps {
+            created: from_unix(created),
+            updated: from_unix(updated),
+        }))
+    }
+
+    /// The primary names of the certificates in the realm that expire before the given
+    /// time, the soonest first. Versions in the certificate history are not included.
+    pub fn expiring_before(&self, realm: &str, time: SystemTime) -> Result<Vec<String>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT key FROM acme_persist
+             WHERE realm = ?1 AND kind = ?2 AND not_after < ?3 AND instr(key, '#') = 0
+             ORDER BY not_after, key",
+        )?;
+        let rows = stmt.query_map(
+            params![
+                realm_id(realm_hash(realm)),
+                kind_id(PersistKind::Certificate),
+                to_unix(time)
+            ],
+            |row| row.get(0),
+        )?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+}
+
+/// SQLite integers are signed, the realm hash keeps its bits.
+fn realm_id(realm: u64) -> i64 {
+    realm as i64
+}
+
+fn kind_id(kind: PersistKind) -> &'static str {
+    match kind {
+        PersistKind::AccountPrivateKey => "account_key",
+        PersistKind::PrivateKey => "key",
+        PersistKind::Certificate => "crt",
+        PersistKind::OcspResponse => "ocsp",
+        PersistKind::Revocation => "rev",
+        PersistKind::PublicKey => "pub",
+    }
+}
+
+fn to_unix(time: SystemTime) -> i64 {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(d) => i64::try_from(d.as_secs()).unwrap_or(i64::MAX),
+        Err(e) => -i64::try_from(e.duration().as_secs()).unwrap_or(i64::MAX),
+    }
+}
+
+fn from_unix(secs: i64) -> SystemTime {
+    if secs >= 0 {
+        UNIX_EPOCH + Duration::from_secs(secs as u64)
+    } else {
+        UNIX_EPOCH - Duration::from_secs(secs.unsigned_abs())
+    }
+}
+
+fn put_value(conn: &Connection, key: &PersistKey, value: &[u8], now: i64) -> Result<()> {
+    let not_after = if key.kind == PersistKind::Certificate {
+        match certificate_not_after(value) {
+            Ok(t) => Some(t.unix_timestamp()),
+            Err(e) => {
+                debug!("No expiry for certificate {}: {}", key, e);
+                None
+            }
+        }
+    } else {
+        None
+    };
+    conn.execute(
+        "INSERT INTO acme_persist (realm, kind, key, value, not_after, created, updated)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?6)
+         ON CONFLICT (realm, kind, key) DO UPDATE SET
+             value = excluded.value,
+             not_after = excluded.not_after,
+             updated = excluded.updated",
+        params![
+            realm_id(key.realm),
+            kind_id(key.kind),
+            key.key,
+            value,
+            not_after,
+            now
+        ],
+    )?;
+    Ok(())
+}
+
+impl Persist for SqlitePersist {
+    fn put(&self, key: &PersistKey, value: &[u8]) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        put_value(&conn, key, value, to_unix(SystemTime::now()))
+    }
+
+    fn get(&self, key: &PersistKey) -> Result<Option<Vec<u8>>> {
+        let conn = self.conn.lock().unwrap();
+        let value = conn
+            .query_row(
+                "SELECT value FROM acme_persist
+                 WHERE realm = ?1 AND kind = ?2 AND key = ?3",
+                params![realm_id(key.realm), kind_id(key.kind), key.key],
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(value)
+    }
+
+    fn put_all(&self, values: &[(PersistKey, &[u8])]) -> Result<()> {
+        let mut conn = self.conn.lock().unwrap();
+        let now = to_unix(SystemTime::now());
+        let tx = conn.transaction()?;
+        for (key, value) in values {
+            put_value(&tx, key, value, now)?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn delete(&self, key: &PersistKey) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "DELETE FROM acme_persist WHERE realm = ?1 AND kind = ?2 AND key = ?3",
+            params![realm_id(key.realm), kind_id(key.kind), key.key],
+        )?;
+        Ok(())
+    }
+
+    fn list(&self, realm: &str, kind: PersistKind) -> Result<Vec<String>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn
+            .prepare("SELECT key FROM acme_persist WHERE realm = ?1 AND kind = ?2 ORDER BY key")?;
+        let rows = stmt.query_map(params![realm_id(realm_hash(realm)), kind_id(kind)], |row| {
+            row.get(0)
+        })?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_timestamps_and_transactions() -> Result<()> {
+        let persist = SqlitePersist::open_in_memory()?;
+        let pk = PersistKey::new("foo@bar.com", PersistKind::PrivateKey, "example.com");
+        assert_eq!(persist.timestamps(&pk)?, None);
+        persist.put(&pk, b"KEY")?;
+        let first = persist.timestamps(&pk)?.unwrap();
+        assert_eq!(first.created, first.updated);
+        persist.put(&pk, b"KEY2")?;
+        assert_eq!(persist.timestamps(&pk)?.unwrap().created, first.created);
+
+        // a failing write in put_all rolls back the earlier ones
+        persist.conn.lock().unwrap().execute_batch(
+            "CREATE TRIGGER no_crt BEFORE INSERT ON acme_persist WHEN NEW.kind = 'crt'
+             BEGIN SELECT RAISE(ABORT, 'no certificates'); END;",
+        )?;
+        let crt = PersistKey::new("foo@bar.com", PersistKind::Certificate, "example.com");
+        assert!(persist.put_all(&[(pk, b"KEY3"), (crt, b"CERT")]).is_err());
+        assert_eq!(persist.get(&pk)?, Some(b"KEY2".to_vec()));
+        Ok(())
+    }
+
+    #[test]
+    fn test_expiring_before() -> Result<()> {
+        let persist = SqlitePersist::open_in_memory()?;
+        let crt = PersistKey::new("foo@bar.com", PersistKind::Certificate, "example.com");
+        persist.put(&crt, CERT_2049.as_bytes())?;
+        // not a certificate, never expires
+        let bad = PersistKey::new("foo@bar.com", PersistKind::Certificate, "bad.com");
+        persist.put(&bad, b"CERT")?;
+
+        // 2049-01-01T00:00:00Z
+        let not_after = UNIX_EPOCH + Duration::from_secs(2_493_072_000);
+        assert!(persist
+            .expiring_before("foo@bar.com", not_after)?
+            .is_empty());
+        let after = not_after + Duration::from_secs(1);
+        assert_eq!(
+            persist.expiring_before("foo@bar.com", after)?,
+            ["example.com"]
+        );
+        assert!(persist.expiring_before("other@bar.com", after)?.is_empty());
+        Ok(())
+    }
+
+    /// Self-signed, valid until 2049-01-01.
+    const CERT_2049: &str = "\
+-----BEGIN CERTIFICATE-----
+MIIBgTCCASegAwIBAgIUEWxK4kcCI8+7VsXO+BKYdFGqiTowCgYIKoZIzj0EAwIw
+FjEUMBIGA1UEAwwLZXhhbXBsZS5jb20wHhcNMjQwMTAxMDAwMDAwWhcNNDkwMTAx
+MDAwMDAwWjAWMRQwEgYDVQQDDAtleGFtcGxlLmNvbTBZMBMGByqGSM49AgEGCCqG
+SM49AwEHA0IABEYtPDOsIBjSCmvt2z4jsnR2djo9YSQg3pJtk7W9foREtspvhMeO
+iIy7nDUGtIxN6JwrDJbqloitX9a7Ns18bSWjUzBRMB0GA1UdDgQWBBSjVxKxGfHl
+WWxc/u4jk7F0Xk+7qDAfBgNVHSMEGDAWgBSjVxKxGfHlWWxc/u4jk7F0Xk+7qDAP
+BgNVHRMBAf8EBTADAQH/MAoGCCqGSM49BAMCA0gAMEUCIFwGu65cewXT1mV1uBnV
+4ypfyQRQWFvp/2x8G+ogHRc2AiEAwNhfNzC+xVDFmb9HCtIoxweyK9RwoQvLPq9+
+sWA4pn8=
+-----END CERTIFICATE-----
+";
+}
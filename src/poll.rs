@@ -0,0 +1,313 @@
+//
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{crypto, Error, Result};
+
+const DEFAULT_INITIAL_DELAY: Duration = Duration::from_secs(5);
+const DEFAULT_BACKOFF: f64 = 2.0;
+const DEFAULT_MAX_DELAY: Duration = Duration::from_secs(60);
+const DEFAULT_JITTER: f64 = 0.1;
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+/// Policy for polling the ACME API while waiting for a status change.
+///
+/// The wait between polls starts at the `initial_delay` and is multiplied by the `backoff`
+/// for every attempt, up to `max_delay`. Each wait is randomly adjusted by `jitter` (a
+/// fraction of the delay) to avoid many clients polling in lockstep.
+///
+/// When the ACME API provider answers with a `Retry-After` header, that takes precedence
+/// over the computed delay, but is also bounded by `max_delay`.
+///
+/// Polling gives up with [`Error::Timeout`] when either `max_attempts` or the overall
+/// `timeout` is reached.
+///
+/// A `u64` converts to a policy using that many milliseconds as initial delay
+/// and the defaults for everything else.
+///
+/// ```
+/// use acme_lib::PollPolicy;
+/// use std::time::Duration;
+///
+/// let policy = PollPolicy::new(Duration::from_secs(2))
+///     .backoff(1.5)
+///     .max_delay(Duration::from_secs(30))
+///     .max_attempts(20)
+///     .timeout(Duration::from_secs(300));
+/// ```
+///
+/// [`Error::Timeout`]: enum.Error.html#variant.Timeout
+#[derive(Debug, Clone)]
+pub struct PollPolicy {
+    initial_delay: Duration,
+    backoff: f64,
+    max_delay: Duration,
+    jitter: f64,
+    max_attempts: Option<u32>,
+    timeout: Option<Duration>,
+}
+
+impl PollPolicy {
+    /// Create a policy with the given initial delay and defaults for the rest.
+    ///
+    /// The defaults are a backoff of `2.0`, max delay of 60 seconds, jitter of `0.1`,
+    /// no limit on the number of attempts and a timeout of 10 minutes.
+    pub fn new(initial_delay: Duration) -> Self {
+        PollPolicy {
+            initial_delay,
+            ..Default::default()
+        }
+    }
+
+    /// Factor to multiply the delay with for every attempt. `1.0` means a fixed delay.
+    pub fn backoff(mut self, backoff: f64) -> Self {
+        self.backoff = backoff.max(1.0);
+        self
+    }
+
+    /// Upper bound of the delay between polls, computed or from a `Retry-After`.
+    pub fn max_delay(mut self, max_delay: Duration) -> Self {
+        self.max_delay = max_delay;
+        self
+    }
+
+    /// Random adjustment of each delay as a fraction between `0.0` and `1.0`. With `0.1`
+    /// a delay of 10 seconds becomes something between 9 and 11 seconds.
+    pub fn jitter(mut self, jitter: f64) -> Self {
+        self.jitter = if jitter.is_finite() {
+            jitter.clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+        self
+    }
+
+    /// Max number of polls before giving up.
+    pub fn max_attempts(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = Some(max_attempts);
+        self
+    }
+
+    /// Overall deadline for the polling, counted from the first poll.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Never give up polling.
+    pub fn no_limits(mut self) -> Self {
+        self.max_attempts = None;
+        self.timeout = None;
+        self
+    }
+
+    pub(crate) fn start(&self, what: &'static str) -> Poller {
+        Poller {
+            policy: self.clone(),
+            what,
+            started: Instant::now(),
+            attempts: 0,
+        }
+    }
+
+    /// The jittered delay after the given number of failures (at least 1), for retrying
+    /// something other than a poll.
+    pub(crate) fn retry_delay(&self, failures: u32) -> Duration {
+        jittered(self.delay_for(failures.saturating_sub(1)), self.jitter)
+    }
+
+    /// The delay before the given attempt (0-based) without jitter.
+    fn delay_for(&self, attempt: u32) -> Duration {
+        let factor = self.backoff.powi(attempt.min(i32::MAX as u32) as i32);
+        let delay = self.initial_delay.as_secs_f64() * factor;
+        let max = self.delay_bound().as_secs_f64();
+        Duration::from_secs_f64(delay.min(max))
+    }
+
+    /// The longest delay between polls, never shorter than the initial one.
+    fn delay_bound(&self) -> Duration {
+        self.max_delay.max(self.initial_delay)
+    }
+}
+
+impl Default for PollPolicy {
+    fn default() -> Self {
+        PollPolicy {
+            initial_delay: DEFAULT_INITIAL_DELAY,
+            backoff: DEFAULT_BACKOFF,
+            max_delay: DEFAULT_MAX_DELAY,
+            jitter: DEFAULT_JITTER,
+            max_attempts: None,
+            timeout: Some(DEFAULT_TIMEOUT),
+        }
+    }
+}
+
+impl From<u64> for PollPolicy {
+    fn from(delay_millis: u64) -> Self {
+        PollPolicy::new(Duration::from_millis(delay_millis))
+    }
+}
+
+impl From<Duration> for PollPolicy {
+    fn from(initial_delay: Duration) -> Self {
+        PollPolicy::new(initial_delay)
+    }
+}
+
+/// State of an ongoing polling.
+pub(crate) struct Poller {
+    policy: PollPolicy,
+    what: &'static str,
+    started: Instant,
+    attempts: u32,
+}
+
+impl Poller {
+    /// The delay to use before the next poll, or a timeout error if
+    /// the policy says we should give up.
+    pub fn next_delay(&mut self, retry_after: Option<Duration>) -> Result<Duration> {
+        self.attempts += 1;
+
+        if let Some(max) = self.policy.max_attempts {
+            if self.attempts >= max {
+                return Err(self.timeout_error());
+            }
+        }
+
+        let delay = match retry_after {
+            // the server's value can be anything
+            Some(d) => d.min(self.policy.delay_bound()),
+            None => jittered(self.policy.delay_for(self.attempts - 1), self.policy.jitter),
+        };
+
+        if let Some(timeout) = self.policy.timeout {
+            let at = self.started.elapsed().checked_add(delay);
+            if at.is_none_or(|at| at > timeout) {
+                return Err(self.timeout_error());
+            }
+        }
+
+        Ok(delay)
+    }
+
+    /// Sleep until it's time for the next poll.
+    pub fn wait(&mut self, retry_after: Option<Duration>) -> Result<()> {
+        let delay = self.next_delay(retry_after)?;
+        trace!("Poll {} again in {:?}", self.what, delay);
+        thread::sleep(delay);
+        Ok(())
+    }
+
+    /// Sleep without blocking the thread until it's time for the next poll.
+    #[cfg(feature = "async")]
+    pub async fn wait_async(&mut self, retry_after: Option<Duration>) -> Result<()> {
+        let delay = self.next_delay(retry_after)?;
+        trace!("Poll {} again in {:?}", self.what, delay);
+        tokio::time::sleep(delay).await;
+        Ok(())
+    }
+
+    fn timeout_error(&self) -> Error {
+        Error::Timeout(format!(
+            "Gave up polling {} after {} attempts ({:?})",
+            self.what,
+            self.attempts,
+            self.started.elapsed()
+        ))
+    }
+}
+
+fn jittered(delay: Duration, jitter: f64) -> Duration {
+    if jitter == 0.0 {
+        return delay;
+    }
+    match random_fraction() {
+        Some(r) => delay.mul_f64(1.0 + jitter * (r * 2.0 - 1.0)),
+        None => delay,
+    }
+}
+
+/// Random number in [0, 1], `None` if there is no randomness to be had.
+pub(crate) fn random_fraction() -> Option<f64> {
+    let mut buf = [0; 4];
+    crypto::random_bytes(&mut buf).ok()?;
+    Some(u32::from_be_bytes(buf) as f64 / u32::MAX as f64)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_backoff_delays() {
+        let policy = PollPolicy::new(Duration::from_secs(1))
+            .backoff(2.0)
+            .max_delay(Duration::from_secs(5))
+            .jitter(0.0);
+        let mut poller = policy.start("test");
+        let delays: Vec<_> = (0..5)
+            .map(|_| poller.next_delay(None).unwrap().as_secs())
+            .collect();
+        assert_eq!(delays, vec![1, 2, 4, 5, 5]);
+    }
+
+    #[test]
+    fn test_retry_after_takes_precedence() {
+        let policy = PollPolicy::new(Duration::from_secs(1)).jitter(0.0);
+        let mut poller = policy.start("test");
+        let retry_after = Some(Duration::from_secs(3));
+        assert_eq!(poller.next_delay(retry_after).unwrap().as_secs(), 3);
+    }
+
+    #[test]
+    fn test_huge_retry_after() {
+        let huge = Some(Duration::from_secs(u64::MAX));
+        let policy = PollPolicy::new(Duration::from_secs(1)).max_delay(Duration::from_secs(30));
+        let mut poller = policy.start("test");
+        assert_eq!(poller.next_delay(huge).unwrap(), Duration::from_secs(30));
+        let mut poller = policy.clone().no_limits().start("test");
+        assert_eq!(poller.next_delay(huge).unwrap(), Duration::from_secs(30));
+        let mut poller = policy.timeout(Duration::from_secs(10)).start("test");
+        assert!(matches!(poller.next_delay(huge), Err(Error::Timeout(_))));
+    }
+
+    #[test]
+    fn test_jitter_within_bounds() {
+        let policy = PollPolicy::new(Duration::from_secs(10))
+            .backoff(1.0)
+            .jitter(0.5);
+        let mut poller = policy.start("test");
+        for _ in 0..20 {
+            let d = poller.next_delay(None).unwrap();
+            assert!(d >= Duration::from_secs(5) && d <= Duration::from_secs(15));
+        }
+    }
+
+    #[test]
+    fn test_jitter_not_a_number() {
+        for jitter in [f64::NAN, f64::INFINITY] {
+            let policy = PollPolicy::new(Duration::from_secs(1)).jitter(jitter);
+            let mut poller = policy.start("test");
+            assert_eq!(poller.next_delay(None).unwrap(), Duration::from_secs(1));
+        }
+    }
+
+    #[test]
+    fn test_max_attempts() {
+        let policy = PollPolicy::new(Duration::from_millis(1)).max_attempts(3);
+        let mut poller = policy.start("test");
+        assert!(poller.next_delay(None).is_ok());
+        assert!(poller.next_delay(None).is_ok());
+        assert!(matches!(poller.next_delay(None), Err(Error::Timeout(_))));
+    }
+
+    #[test]
+    fn test_deadline() {
+        let policy = PollPolicy::new(Duration::from_secs(2)).timeout(Duration::from_secs(1));
+        let mut poller = policy.start("test");
+        assert!(matches!(poller.next_delay(None), Err(Error::Timeout(_))));
+    }
+}
@@ -0,0 +1,918 @@
+//! Renewal of a set of certificates, see [`RenewalManager`].
+//!
+//! [`RenewalManager`]: struct.RenewalManager.html
+use std::{
+    fmt,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+
+use crate::{
+    api::ApiRenewalInfo,
+    create_p256_key, create_p384_key, create_rsa_key,
+    deploy::{DeployHooks, DeployReport},
+    http::HttpResponse,
+    order::{AuthOutcome, NewOrder},
+    persist::Persist,
+    poll::random_fraction,
+    req::retry_after,
+    util::{base64url, read_json},
+    Account, Certificate, Error, PollPolicy, PrivateKey, Result,
+};
+
+const DEFAULT_RENEW_BEFORE: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+const DEFAULT_JITTER: Duration = Duration::from_secs(24 * 60 * 60);
+const DEFAULT_RETRY_DELAY: Duration = Duration::from_secs(60 * 60);
+const DEFAULT_MAX_RETRY_DELAY: Duration = Duration::from_secs(24 * 60 * 60);
+/// How long to keep renewal information when the CA doesn't say.
+const DEFAULT_RENEWAL_INFO_TTL: Duration = Duration::from_secs(6 * 60 * 60);
+/// The longest the CA can have renewal information kept.
+const MAX_RENEWAL_INFO_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// The renewal window the CA suggests for a certificate, obtained from
+/// [`Account::renewal_info`].
+///
+/// This is ACME Renewal Information (ARI). The CA moves the window earlier when it needs
+/// certificates replaced ahead of time, i.e. before a mass revocation.
+///
+/// [`Account::renewal_info`]: ../struct.Account.html#method.renewal_info
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RenewalInfo {
+    start: OffsetDateTime,
+    end: OffsetDateTime,
+    explanation_url: Option<String>,
+    retry_after: Option<Duration>,
+}
+
+impl RenewalInfo {
+    /// Start of the suggested window for renewing.
+    pub fn start(&self) -> OffsetDateTime {
+        self.start
+    }
+
+    /// End of the suggested window for renewing.
+    pub fn end(&self) -> OffsetDateTime {
+        self.end
+    }
+
+    /// A page explaining why the window is what it is, if the CA gave one.
+    pub fn explanation_url(&self) -> Option<&str> {
+        self.explanation_url.as_deref()
+    }
+
+    /// How long until the information should be fetched again, if the CA said so.
+    pub fn retry_after(&self) -> Option<Duration> {
+        self.retry_after
+    }
+
+    /// How long to keep the information, as the CA says but at most a day.
+    fn ttl(&self) -> Duration {
+        self.retry_after
+            .unwrap_or(DEFAULT_RENEWAL_INFO_TTL)
+            .min(MAX_RENEWAL_INFO_TTL)
+    }
+
+    /// A random time in the window, as the CAs want to spread the renewals out.
+    fn pick_time(&self) -> SystemTime {
+        let r = random_fraction().unwrap_or(0.0);
+        let window = (self.end - self.start).max(time::Duration::ZERO);
+        (self.start + window * r).into()
+    }
+}
+
+/// The URL of the renewal information of a certificate, under the `renewalInfo` URL of
+/// the directory.
+pub(crate) fn renewal_info_url(base: &str, cert: &Certificate) -> Result<String> {
+    Ok(format!(
+        "{}/{}",
+        base.trim_end_matches('/'),
+        ari_cert_id(cert)?
+    ))
+}
+
+/// The ARI identifier of a certificate: the authority key identifier and the serial
+/// number (the DER integer bytes), both base64url, joined by a dot.
+fn ari_cert_id(cert: &Certificate) -> Result<String> {
+    let info = cert.info()?;
+    let aki = info
+        .authority_key_id()
+        .ok_or("Certificate has no authority key identifier")?;
+
+    // the serial is hex without leading zeros
+    let hex = info.serial_number();
+    let hex = if hex.len() % 2 == 1 {
+        format!("0{}", hex)
+    } else {
+        hex.to_string()
+    };
+    let mut serial = hex
+        .as_bytes()
+        .chunks(2)
+        .map(|c| u8::from_str_radix(std::str::from_utf8(c).unwrap_or("xx"), 16))
+        .collect::<std::result::Result<Vec<u8>, _>>()
+        .map_err(|e| format!("Bad serial number {}: {}", hex, e))?;
+    // a positive DER integer starts with a zero byte if the high bit is set
+    if serial.first().is_some_and(|b| b & 0x80 != 0) {
+        serial.insert(0, 0);
+    }
+
+    Ok(format!("{}.{}", base64url(aki), base64url(&serial)))
+}
+
+/// Read the renewal information from the response of the CA.
+pub(crate) fn renewal_info_of(res: HttpResponse) -> Result<RenewalInfo> {
+    let retry_after = retry_after(&res);
+    let api: ApiRenewalInfo = read_json(res)?;
+    let parse = |s: &str| {
+        OffsetDateTime::parse(s, &Rfc3339)
+            .map_err(|e| Error::Other(format!("Bad time in renewal info {}: {}", s, e)))
+    };
+    Ok(RenewalInfo {
+        start: parse(&api.suggestedWindow.start)?,
+        end: parse(&api.suggestedWindow.end)?,
+        explanation_url: api.explanationURL,
+        retry_after,
+    })
+}
+
+/// The type of challenge a [`ChallengeSolver`] solves.
+///
+/// [`ChallengeSolver`]: trait.ChallengeSolver.html
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChallengeType {
+    /// `http-01`, a file served by a web server on port 80.
+    Http,
+    /// `dns-01`, a TXT record.
+    Dns,
+    /// `tls-alpn-01`, a certificate served for the `acme-tls/1` protocol on port 443.
+    TlsAlpn,
+}
+
+/// The proof a [`ChallengeSolver`] places for a domain.
+///
+/// [`ChallengeSolver`]: trait.ChallengeSolver.html
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChallengeProof {
+    /// Serve `proof` at `http://<domain>/.well-known/acme-challenge/<token>`.
+    Http {
+        domain: String,
+        token: String,
+        proof: String,
+    },
+    /// Set `proof` as TXT record of `_acme-challenge.<domain>`.
+    Dns { domain: String, proof: String },
+    /// Serve a certificate for `domain` with the `proof` in the `acmeIdentifier` extension.
+    TlsAlpn { domain: String, proof: [u8; 32] },
+}
+
+impl ChallengeProof {
+    /// The domain being validated.
+    pub fn domain(&self) -> &str {
+        match self {
+            ChallengeProof::Http { domain, .. }
+            | ChallengeProof::Dns { domain, .. }
+            | ChallengeProof::TlsAlpn { domain, .. } => domain,
+        }
+    }
+}
+
+/// Places the proofs of the challenges when a [`RenewalManager`] renews a certificate.
+///
+/// ```
+/// use acme_lib::renew::{ChallengeProof, ChallengeSolver, ChallengeType};
+/// use std::{fs, path::PathBuf};
+///
+/// /// Writes the proofs to the webroot of a web server.
+/// struct Webroot(PathBuf);
+///
+/// impl ChallengeSolver for Webroot {
+///     fn challenge_type(&self) -> ChallengeType {
+///         ChallengeType::Http
+///     }
+///
+///     fn present(&self, proof: &ChallengeProof) -> acme_lib::Result<()> {
+///         if let ChallengeProof::Http { token, proof, .. } = proof {
+///             let dir = self.0.join(".well-known/acme-challenge");
+///             fs::create_dir_all(&dir)?;
+///             fs::write(dir.join(token), proof)?;
+///         }
+///         Ok(())
+///     }
+///
+///     fn cleanup(&self, proof: &ChallengeProof) -> acme_lib::Result<()> {
+///         if let ChallengeProof::Http { token, .. } = proof {
+///             fs::remove_file(self.0.join(".well-known/acme-challenge").join(token))?;
+///         }
+///         Ok(())
+///     }
+/// }
+/// ```
+///
+/// [`RenewalManager`]: struct.RenewalManager.html
+pub trait ChallengeSolver: Send + Sync {
+    /// The type of challenge to solve. The proofs given are of this type.
+    fn challenge_type(&self) -> ChallengeType;
+
+    /// Place the proof, i.e. write the file the web server serves or set the DNS record.
+    /// The validation starts when this returns, so wait for DNS changes to propagate.
+    fn present(&self, proof: &ChallengeProof) -> Result<()>;
+
+    /// Remove a proof once the validation is over, whether it succeeded or not.
+    ///
+    /// The default does nothing.
+    fn cleanup(&self, proof: &ChallengeProof) -> Result<()> {
+        let _ = proof;
+        Ok(())
+    }
+}
+
+/// The type of private key for a new certificate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyType {
+    /// ECDSA P-256.
+    P256,
+    /// ECDSA P-384.
+    P384,
+    /// RSA with the given number of bits.
+    Rsa(u32),
+}
+
+impl KeyType {
+    fn create(self) -> Result<PrivateKey> {
+        match self {
+            KeyType::P256 => create_p256_key(),
+            KeyType::P384 => create_p384_key(),
+            KeyType::Rsa(bits) => create_rsa_key(bits),
+        }
+    }
+}
+
+/// A certificate for a [`RenewalManager`] to keep fresh.
+///
+/// The primary name is also the name of the certificate in the persistence.
+///
+/// [`RenewalManager`]: struct.RenewalManager.html
+#[derive(Clone)]
+pub struct CertSpec {
+    primary_name: String,
+    alt_names: Vec<String>,
+    key_type: KeyType,
+    reuse_key: bool,
+    solver: Arc<dyn ChallengeSolver>,
+}
+
+impl CertSpec {
+    /// A certificate for the primary name with a P-256 key, validated by the solver.
+    pub fn new(primary_name: &str, solver: Arc<dyn ChallengeSolver>) -> Self {
+        CertSpec {
+            primary_name: primary_name.to_string(),
+            alt_names: vec![],
+            key_type: KeyType::P256,
+            reuse_key: false,
+            solver,
+        }
+    }
+
+    /// The other names of the certificate.
+    pub fn alt_names<S: AsRef<str>>(mut self, names: &[S]) -> Self {
+        self.alt_names = names.iter().map(|n| n.as_ref().to_string()).collect();
+        self
+    }
+
+    /// The type of key for new certificates.
+    pub fn key_type(mut self, key_type: KeyType) -> Self {
+        self.key_type = key_type;
+        self
+    }
+
+    /// Renew with the private key of the current certificate instead of a new key, i.e.
+    /// when the key is pinned in TLSA records. A new key is still made when there is no
+    /// current certificate or its key is held externally.
+    pub fn reuse_key(mut self, reuse_key: bool) -> Self {
+        self.reuse_key = reuse_key;
+        self
+    }
+
+    /// The primary name of the certificate.
+    pub fn primary_name(&self) -> &str {
+        &self.primary_name
+    }
+}
+
+impl fmt::Debug for CertSpec {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("CertSpec")
+            .field("primary_name", &self.primary_name)
+            .field("alt_names", &self.alt_names)
+            .field("key_type", &self.key_type)
+            .field("reuse_key", &self.reuse_key)
+            .finish()
+    }
+}
+
+/// What a [`RenewalManager::check`] did about a certificate.
+///
+/// [`RenewalManager::check`]: struct.RenewalManager.html#method.check
+#[derive(Debug)]
+pub enum RenewalStatus {
+    /// The certificate is not due, it will be renewed from the given time.
+    Fresh { renew_at: SystemTime },
+    /// The certificate was renewed and saved in the persistence. The report has the
+    /// failures of the deploy hooks, which are not retried.
+    Renewed(DeployReport),
+    /// The renewal failed. It is retried after a backoff.
+    Failed(Error),
+    /// An earlier renewal failed and it is not yet time to retry.
+    BackingOff { retry_at: SystemTime },
+}
+
+/// The [`RenewalStatus`] of one certificate.
+///
+/// [`RenewalStatus`]: enum.RenewalStatus.html
+#[derive(Debug)]
+pub struct RenewalOutcome {
+    primary_name: String,
+    status: RenewalStatus,
+}
+
+impl RenewalOutcome {
+    /// The primary name of the certificate.
+    pub fn primary_name(&self) -> &str {
+        &self.primary_name
+    }
+
+    /// What was done about the certificate.
+    pub fn status(&self) -> &RenewalStatus {
+        &self.status
+    }
+
+    /// Whether the certificate was due and the renewal failed.
+    pub fn is_failed(&self) -> bool {
+        matches!(self.status, RenewalStatus::Failed(_))
+    }
+
+    /// Turn the outcome into the status.
+    pub fn into_status(self) -> RenewalStatus {
+        self.status
+    }
+}
+
+/// What a manager remembers about a certificate between checks.
+#[derive(Default)]
+struct RenewalState {
+    /// The fingerprint of the certificate the times were chosen for.
+    fingerprint: Option<[u8; 32]>,
+    /// When to renew going by the expiry.
+    expiry_at: Option<SystemTime>,
+    /// The suggested window of the renewal information and the time picked in it.
+    window: Option<(OffsetDateTime, OffsetDateTime, SystemTime)>,
+    /// When to ask for the renewal information again.
+    recheck_at: Option<SystemTime>,
+    failures: u32,
+    retry_at: Option<SystemTime>,
+}
+
+/// Whether a certificate is due for renewal.
+enum Due {
+    /// Not before the time.
+    No(SystemTime),
+    /// Yes, replacing the current certificate, if there is one.
+    Yes(Option<Certificate>),
+}
+
+type RenewedCallback = Box<dyn FnMut(&str, &Certificate) + Send>;
+type FailedCallback = Box<dyn FnMut(&str, &Error) + Send>;
+
+/// Keeps a set of certificates of an account fresh.
+///
+/// Every [`check`] goes through the certificates and renews those that are missing,
+/// [revoked] or due. A certificate is due a random time of up to `jitter` before it is
+/// within `renew_before` of expiring, so renewals of many certificates spread out. When
+/// the CA provides [renewal information], a random time in its suggested window is
+/// used instead.
+///
+/// A failed renewal is retried after the delays of the retry [`PollPolicy`], which
+/// grow with every failure of that certificate. The renewals are blocking and done one
+/// after the other.
+///
+/// Renewed certificates are deployed with the [`DeployHooks`] of the manager, unless the
+/// CA issued the same certificate again.
+///
+/// ```no_run
+/// use acme_lib::persist::FilePersist;
+/// use acme_lib::renew::{CertSpec, ChallengeSolver, RenewalManager};
+/// use acme_lib::{Directory, DirectoryUrl};
+/// use std::{sync::Arc, thread, time::Duration};
+///
+/// fn keep_fresh(solver: Arc<dyn ChallengeSolver>) -> acme_lib::Result<()> {
+///     let url = DirectoryUrl::LetsEncrypt;
+///     let dir = Directory::from_url(FilePersist::new("."), url)?;
+///     let acc = dir.account("foo@bar.com")?;
+///
+///     let www = CertSpec::new("example.com", solver.clone()).alt_names(&["www.example.com"]);
+///     let mut manager = RenewalManager::new(acc)
+///         .certificate(www)
+///         .certificate(CertSpec::new("example.org", solver))
+///         .on_renewed(|name, _cert| println!("Renewed {}", name))
+///         .on_failed(|name, e| eprintln!("Renewing {} failed: {}", name, e));
+///     loop {
+///         manager.check();
+///         thread::sleep(Duration::from_secs(60 * 60));
+///     }
+/// }
+/// ```
+///
+/// [`check`]: struct.RenewalManager.html#method.check
+/// [revoked]: ../struct.Certificate.html#method.is_revoked
+/// [renewal information]: ../struct.Account.html#method.renewal_info
+/// [`PollPolicy`]: ../struct.PollPolicy.html
+/// [`DeployHooks`]: ../deploy/struct.DeployHooks.html
+pub struct RenewalManager<P: Persist> {
+    account: Account<P>,
+    certs: Vec<(CertSpec, RenewalState)>,
+    renew_before: Duration,
+    jitter: Duration,
+    use_renewal_info: bool,
+    poll: PollPolicy,
+    retry: PollPolicy,
+    hooks: DeployHooks,
+    on_renewed: Option<RenewedCallback>,
+    on_failed: Option<FailedCallback>,
+}
+
+impl<P: Persist> RenewalManager<P> {
+    /// A manager for certificates of the account.
+    ///
+    /// The defaults are to renew 30 days before expiry with a jitter of one day, to use
+    /// renewal information when the CA has it and to retry failures after an hour,
+    /// doubling up to a day.
+    pub fn new(account: Account<P>) -> Self {
+        RenewalManager {
+            account,
+            certs: vec![],
+            renew_before: DEFAULT_RENEW_BEFORE,
+            jitter: DEFAULT_JITTER,
+            use_renewal_info: true,
+            poll: PollPolicy::default(),
+            retry: PollPolicy::new(DEFAULT_RETRY_DELAY).max_delay(DEFAULT_MAX_RETRY_DELAY),
+            hooks: DeployHooks::new(),
+            on_renewed: None,
+            on_failed: None,
+        }
+    }
+
+    /// Add a certificate to keep fresh.
+    pub fn certificate(mut self, spec: CertSpec) -> Self {
+        self.certs.push((spec, RenewalState::default()));
+        self
+    }
+
+    /// Renew certificates this long before they expire.
+    pub fn renew_before(mut self, renew_before: Duration) -> Self {
+        self.renew_before = renew_before;
+        self
+    }
+
+    /// Renew up to this much earlier, at random.
+    pub fn jitter(mut self, jitter: Duration) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    /// Whether to use the renewal information of the CA, if it has any. It's fetched
+    /// again after the `Retry-After` of the CA, 6 hours if it gives none, but at least
+    /// once a day.
+    pub fn renewal_info(mut self, use_renewal_info: bool) -> Self {
+        self.use_renewal_info = use_renewal_info;
+        self
+    }
+
+    /// How to poll the validations and the finalizing of the orders.
+    pub fn poll_policy(mut self, poll: impl Into<PollPolicy>) -> Self {
+        self.poll = poll.into();
+        self
+    }
+
+    /// The delays before retrying a failed renewal. Only the delays are used, the
+    /// manager never gives up on a certificate.
+    pub fn retry_policy(mut self, retry: impl Into<PollPolicy>) -> Self {
+        self.retry = retry.into();
+        self
+    }
+
+    /// The hooks to deploy renewed certificates with.
+    pub fn deploy_hooks(mut self, hooks: DeployHooks) -> Self {
+        self.hooks = hooks;
+        self
+    }
+
+    /// Called with the primary name and the certificate after every renewal.
+    pub fn on_renewed<F>(mut self, f: F) -> Self
+    where
+        F: FnMut(&str, &Certificate) + Send + 'static,
+    {
+        self.on_renewed = Some(Box::new(f));
+        self
+    }
+
+    /// Called with the primary name and the error after every failed renewal.
+    pub fn on_failed<F>(mut self, f: F) -> Self
+    where
+        F: FnMut(&str, &Error) + Send + 'static,
+    {
+        self.on_failed = Some(Box::new(f));
+        self
+    }
+
+    /// Check all certificates and renew those that are due, in the order they were
+    /// added. Returns one outcome per certificate.
+    pub fn check(&mut self) -> Vec<RenewalOutcome> {
+        let mut certs = std::mem::take(&mut self.certs);
+        let outcomes = certs
+            .iter_mut()
+            .map(|(spec, state)| RenewalOutcome {
+                primary_name: spec.primary_name.clone(),
+                status: self.check_one(spec, state),
+            })
+            .collect();
+        self.certs = certs;
+        outcomes
+    }
+
+    fn check_one(&mut self, spec: &CertSpec, state: &mut RenewalState) -> RenewalStatus {
+        let now = SystemTime::now();
+        if let Some(retry_at) = state.retry_at.filter(|t| now < *t) {
+            return RenewalStatus::BackingOff { retry_at };
+        }
+
+        let result = match self.due(spec, state, now) {
+            Ok(Due::No(renew_at)) => return RenewalStatus::Fresh { renew_at },
+            Ok(Due::Yes(current)) => self.renew(spec, current.as_ref()),
+            Err(e) => Err(e),
+        };
+
+        let name = &spec.primary_name;
+        match result {
+            Ok(report) => {
+                info!("Renewed certificate: {}", name);
+                *state = RenewalState::default();
+                if let Some(f) = &mut self.on_renewed {
+                    f(name, report.certificate());
+                }
+                RenewalStatus::Renewed(report)
+            }
+            Err(e) => {
+                state.failures += 1;
+                let delay = self.retry.retry_delay(state.failures);
+                info!("Renewing {} failed, retry in {:?}: {}", name, delay, e);
+                state.retry_at = Some(now + delay);
+                if let Some(f) = &mut self.on_failed {
+                    f(name, &e);
+                }
+                RenewalStatus::Failed(e)
+            }
+        }
+    }
+
+    fn due(&self, spec: &CertSpec, state: &mut RenewalState, now: SystemTime) -> Result<Due> {
+        let Some(cert) = self.account.certificate(&spec.primary_name)? else {
+            debug!("No certificate: {}", spec.primary_name);
+            return Ok(Due::Yes(None));
+        };
+        if cert.is_revoked() {
+            debug!("Certificate is revoked: {}", spec.primary_name);
+            return Ok(Due::Yes(Some(cert)));
+        }
+
+        let renew_at = self.renew_at(spec, &cert, state, now)?;
+        if now < renew_at {
+            Ok(Due::No(renew_at))
+        } else {
+            Ok(Due::Yes(Some(cert)))
+        }
+    }
+
+    /// When to renew the certificate: the time picked in the window of the renewal
+    /// information, or else ahead of the expiry. The times are picked once per
+    /// certificate and window.
+    fn renew_at(
+        &self,
+        spec: &CertSpec,
+        cert: &Certificate,
+        state: &mut RenewalState,
+        now: SystemTime,
+    ) -> Result<SystemTime> {
+        let info = cert.info()?;
+        let fingerprint = info.fingerprint_sha256();
+        if state.fingerprint != Some(fingerprint) {
+            let not_after: SystemTime = info.not_after().into();
+            let jitter = self.jitter.mul_f64(random_fraction().unwrap_or(0.0));
+            let early = self.renew_before + jitter;
+            state.fingerprint = Some(fingerprint);
+            state.expiry_at = Some(not_after.checked_sub(early).unwrap_or(UNIX_EPOCH));
+            state.window = None;
+            state.recheck_at = None;
+        }
+
+        let stale = state.recheck_at.is_none_or(|t| now >= t);
+        if self.use_renewal_info && stale {
+            let ttl = match self.account.renewal_info(cert) {
+                Ok(Some(i)) => {
+                    let unchanged =
+                        matches!(state.window, Some((s, e, _)) if (s, e) == (i.start, i.end));
+                    if !unchanged {
+                        state.window = Some((i.start, i.end, i.pick_time()));
+                    }
+                    i.ttl()
+                }
+                // the CA has no renewal information
+                Ok(None) => DEFAULT_RENEWAL_INFO_TTL,
+                Err(e) => {
+                    info!("No renewal info for {}: {}", spec.primary_name, e);
+                    DEFAULT_RENEWAL_INFO_TTL
+                }
+            };
+            // fetched again next time if it doesn't fit
+            state.recheck_at = now.checked_add(ttl);
+        }
+
+        let renew_at = match state.window {
+            Some((_, _, picked)) => picked,
+            None => state.expiry_at.unwrap_or(now),
+        };
+        debug!("Renew {} at {:?}", spec.primary_name, renew_at);
+        Ok(renew_at)
+    }
+
+    /// Order, validate, finalize, save and deploy a new certificate.
+    fn renew(&self, spec: &CertSpec, current: Option<&Certificate>) -> Result<DeployReport> {
+        let alt_names: Vec<&str> = spec.alt_names.iter().map(|n| &n[..]).collect();
+        let mut ord = self.account.new_order(&spec.primary_name, &alt_names)?;
+
+        if !ord.is_validated() {
+            for outcome in validate(&ord, &*spec.solver, &self.poll)? {
+                let domain = outcome.domain_name().to_string();
+                outcome.into_result().map_err(|e| {
+                    info!("Validation of {} failed: {}", domain, e);
+                    e
+                })?;
+            }
+            ord.refresh()?;
+        }
+        let ord = ord
+            .confirm_validations()
+            .ok_or_else(|| format!("Order is not ready: {}", spec.primary_name))?;
+
+        let key = match current.and_then(|c| c.private_key()) {
+            Some(pem) if spec.reuse_key => PrivateKey::from_pem(pem.as_bytes())?,
+            _ => spec.key_type.create()?,
+        };
+        ord.finalize_pkey(key, self.poll.clone())?
+            .download_and_deploy_cert(&self.hooks)
+    }
+}
+
+/// Validate the authorizations of the order with the solver, cleaning up the proofs
+/// afterwards.
+fn validate<P: Persist>(
+    ord: &NewOrder<P>,
+    solver: &dyn ChallengeSolver,
+    poll: &PollPolicy,
+) -> Result<Vec<AuthOutcome>> {
+    let mut placed = vec![];
+    let mut present = |proof: ChallengeProof| {
+        solver.present(&proof)?;
+        placed.push(proof);
+        Ok::<_, Error>(())
+    };
+
+    let outcomes = match solver.challenge_type() {
+        ChallengeType::Http => ord.validate_authorizations(
+            |auth| {
+                let c = auth.http_challenge()?;
+                present(ChallengeProof::Http {
+                    domain: auth.domain_name().to_string(),
+                    token: c.http_token().to_string(),
+                    proof: c.http_proof(),
+                })?;
+                Ok(c)
+            },
+            poll.clone(),
+        ),
+        ChallengeType::Dns => ord.validate_authorizations(
+            |auth| {
+                let c = auth.dns_challenge()?;
+                present(ChallengeProof::Dns {
+                    domain: auth.domain_name().to_string(),
+                    proof: c.dns_proof(),
+                })?;
+                Ok(c)
+            },
+            poll.clone(),
+        ),
+        ChallengeType::TlsAlpn => ord.validate_authorizations(
+            |auth| {
+                let c = auth.tls_alpn_challenge()?;
+                present(ChallengeProof::TlsAlpn {
+                    domain: auth.domain_name().to_string(),
+                    proof: c.tls_alpn_proof(),
+                })?;
+                Ok(c)
+            },
+            poll.clone(),
+        ),
+    };
+
+    for proof in &placed {
+        if let Err(e) = solver.cleanup(proof) {
+            info!("Cleaning up challenge of {} failed: {}", proof.domain(), e);
+        }
+    }
+    outcomes
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::persist::*;
+    use crate::test::*;
+    use crate::{Directory, DirectoryUrl};
+    use std::sync::Mutex;
+    use time::macros::datetime;
+
+    /// Keeps the proofs presented and cleaned up.
+    #[derive(Default)]
+    struct Recorder {
+        presented: Mutex<Vec<ChallengeProof>>,
+        cleaned: Mutex<Vec<ChallengeProof>>,
+    }
+
+    impl ChallengeSolver for Recorder {
+        fn challenge_type(&self) -> ChallengeType {
+            ChallengeType::Http
+        }
+
+        fn present(&self, proof: &ChallengeProof) -> Result<()> {
+            self.presented.lock().unwrap().push(proof.clone());
+            Ok(())
+        }
+
+        fn cleanup(&self, proof: &ChallengeProof) -> Result<()> {
+            self.cleaned.lock().unwrap().push(proof.clone());
+            Ok(())
+        }
+    }
+
+    const NAME: &str = "acmetest.example.com";
+
+    fn save_example(persist: &MemoryPersist) -> Result<()> {
+        let pk_crt = PersistKey::new("foo@bar.com", PersistKind::Certificate, NAME);
+        let pk_key = PersistKey::new("foo@bar.com", PersistKind::PrivateKey, NAME);
+        persist.put(&pk_crt, EXAMPLE_CHAIN.as_bytes())?;
+        persist.put(&pk_key, EXAMPLE_KEY.as_bytes())
+    }
+
+    #[test]
+    fn test_ari_cert_id() -> Result<()> {
+        let cert = Certificate::new(None, EXAMPLE_CHAIN.into());
+        assert_eq!(ari_cert_id(&cert)?, EXAMPLE_ARI_ID);
+        assert_eq!(
+            renewal_info_url("https://ca/ari/", &cert)?,
+            format!("https://ca/ari/{}", EXAMPLE_ARI_ID)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_renewal_info() -> Result<()> {
+        let server = with_directory_server();
+        let url = DirectoryUrl::Other(&server.dir_url);
+        let dir = Directory::from_url(MemoryPersist::new(), url)?;
+        let acc = dir.account("foo@bar.com")?;
+
+        let cert = Certificate::new(None, EXAMPLE_CHAIN.into());
+        let info = acc.renewal_info(&cert)?.unwrap();
+        assert_eq!(info.start(), datetime!(2025-01-01 00:00:00 UTC));
+        assert_eq!(info.end(), datetime!(2025-01-02 00:00:00 UTC));
+        assert_eq!(info.explanation_url(), Some("https://testdir.org/ari"));
+        assert_eq!(info.retry_after(), Some(Duration::from_secs(21600)));
+        let picked = info.pick_time();
+        assert!(picked >= info.start() && picked <= info.end());
+
+        assert_eq!(info.ttl(), Duration::from_secs(21600));
+        let huge = RenewalInfo {
+            retry_after: Some(Duration::from_secs(u64::MAX)),
+            ..info.clone()
+        };
+        assert_eq!(huge.ttl(), MAX_RENEWAL_INFO_TTL);
+        let unsaid = RenewalInfo {
+            retry_after: None,
+            ..info
+        };
+        assert_eq!(unsaid.ttl(), DEFAULT_RENEWAL_INFO_TTL);
+        Ok(())
+    }
+
+    #[test]
+    fn test_renewal_manager() -> Result<()> {
+        let server = with_directory_server();
+        let url = DirectoryUrl::Other(&server.dir_url);
+        let persist = MemoryPersist::new();
+        let dir = Directory::from_url(persist.clone(), url)?;
+        let acc = dir.account("foo@bar.com")?;
+        save_example(&persist)?;
+
+        let solver = Arc::new(Recorder::default());
+        let renewed = Arc::new(Mutex::new(vec![]));
+        let renewed2 = renewed.clone();
+        let spec = CertSpec::new(NAME, solver.clone()).reuse_key(true);
+        let deployed = Arc::new(Mutex::new(0));
+        let deployed2 = deployed.clone();
+        let hooks = DeployHooks::new().callback("count", move |_| {
+            *deployed2.lock().unwrap() += 1;
+            Ok(())
+        });
+
+        // fresh going by the expiry
+        let mut manager = RenewalManager::new(acc.clone())
+            .certificate(spec.clone())
+            .renewal_info(false)
+            .poll_policy(1);
+        let outcomes = manager.check();
+        assert_eq!(outcomes.len(), 1);
+        assert_eq!(outcomes[0].primary_name(), NAME);
+        let RenewalStatus::Fresh { renew_at } = *outcomes[0].status() else {
+            panic!("not fresh: {:?}", outcomes[0]);
+        };
+        let not_after: SystemTime = datetime!(2048-03-01 12:00:00 UTC).into();
+        assert!(renew_at <= not_after - DEFAULT_RENEW_BEFORE);
+        assert!(renew_at >= not_after - DEFAULT_RENEW_BEFORE - DEFAULT_JITTER);
+        // the jitter is picked once
+        let RenewalStatus::Fresh { renew_at: again } = *manager.check()[0].status() else {
+            panic!("not fresh");
+        };
+        assert_eq!(again, renew_at);
+        assert!(solver.presented.lock().unwrap().is_empty());
+
+        // due by the renewal information
+        let mut manager = RenewalManager::new(acc.clone())
+            .certificate(spec)
+            .poll_policy(1)
+            .deploy_hooks(hooks)
+            .on_renewed(move |name, _| renewed2.lock().unwrap().push(name.to_string()));
+        let outcome = manager.check().pop().unwrap();
+        let RenewalStatus::Renewed(report) = outcome.into_status() else {
+            panic!("not renewed");
+        };
+        assert_eq!(report.certificate().private_key(), Some(EXAMPLE_KEY));
+        // the test server issued the saved certificate again
+        assert!(!report.is_changed());
+        assert_eq!(*deployed.lock().unwrap(), 0);
+        assert_eq!(*renewed.lock().unwrap(), [NAME]);
+        let presented = solver.presented.lock().unwrap().clone();
+        assert_eq!(presented.len(), 1);
+        assert_eq!(presented[0].domain(), "acmetest.algesten.se");
+        assert!(matches!(presented[0], ChallengeProof::Http { .. }));
+        assert_eq!(*solver.cleaned.lock().unwrap(), presented);
+        Ok(())
+    }
+
+    #[test]
+    fn test_renewal_failure_backs_off() -> Result<()> {
+        let server = with_directory_server();
+        let url = DirectoryUrl::Other(&server.dir_url);
+        let dir = Directory::from_url(MemoryPersist::new(), url)?;
+        let acc = dir.account("foo@bar.com")?;
+
+        // the test server issues the example certificate, which doesn't match a new key
+        let failed = Arc::new(Mutex::new(vec![]));
+        let failed2 = failed.clone();
+        let mut manager = RenewalManager::new(acc)
+            .certificate(CertSpec::new(NAME, Arc::new(Recorder::default())))
+            .poll_policy(1)
+            .retry_policy(Duration::from_secs(60))
+            .on_failed(move |name, e| {
+                failed2
+                    .lock()
+                    .unwrap()
+                    .push((name.to_string(), e.to_string()))
+            });
+        let outcome = manager.check().pop().unwrap();
+        assert!(outcome.is_failed());
+        assert!(matches!(
+            outcome.status(),
+            RenewalStatus::Failed(Error::Verification(_))
+        ));
+        assert_eq!(failed.lock().unwrap().len(), 1);
+
+        let outcome = manager.check().pop().unwrap();
+        let RenewalStatus::BackingOff { retry_at } = *outcome.status() else {
+            panic!("not backing off: {:?}", outcome);
+        };
+        let now = SystemTime::now();
+        assert!(retry_at > now && retry_at <= now + Duration::from_secs(66));
+        assert_eq!(failed.lock().unwrap().len(), 1);
+        Ok(())
+    }
+}
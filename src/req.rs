@@ -1,57 +1,163 @@
-use crate::api::ApiProblem;
+use std::{convert::TryFrom, time::Duration};
+use time::{macros::format_description, OffsetDateTime, PrimitiveDateTime};
 
-pub(crate) type ApiResult<T> = std::result::Result<T, ApiProblem>;
+use crate::{
+    api::ApiProblem,
+    http::{HttpClient, HttpRequest, HttpResponse, Method},
+    Error, Result,
+};
 
-const TIMEOUT_DURATION: std::time::Duration = std::time::Duration::from_secs(30);
+pub(crate) type ApiResult<T> = std::result::Result<T, ApiProblem>;
 
-pub(crate) fn get(url: &str) -> Result<ureq::Response, Box<ureq::Error>> {
-    let req = ureq::get(url).timeout(TIMEOUT_DURATION);
+pub(crate) fn get(http: &dyn HttpClient, url: &str) -> Result<HttpResponse> {
+    let req = HttpRequest {
+        method: Method::Get,
+        url,
+        content_type: None,
+        body: &[],
+    };
     trace!("{:?}", req);
-    req.call().map_err(Box::new)
+    http.request(&req)
 }
 
-pub(crate) fn head(url: &str) -> Result<ureq::Response, Box<ureq::Error>> {
-    let req = ureq::head(url).timeout(TIMEOUT_DURATION);
+pub(crate) fn head(http: &dyn HttpClient, url: &str) -> Result<HttpResponse> {
+    let req = HttpRequest {
+        method: Method::Head,
+        url,
+        content_type: None,
+        body: &[],
+    };
     trace!("{:?}", req);
-    req.call().map_err(Box::new)
+    http.request(&req)
 }
 
-pub(crate) fn post(url: &str, body: &str) -> Result<ureq::Response, Box<ureq::Error>> {
-    let req = ureq::post(url)
-        .set("content-type", "application/jose+json")
-        .timeout(TIMEOUT_DURATION);
+pub(crate) fn post(http: &dyn HttpClient, url: &str, body: &str) -> Result<HttpResponse> {
+    let req = HttpRequest {
+        method: Method::Post,
+        url,
+        content_type: Some("application/jose+json"),
+        body: body.as_bytes(),
+    };
     trace!("{:?} {}", req, body);
-    req.send_string(body).map_err(Box::new)
+    http.request(&req)
+}
+
+/// POST an OCSP request, which isn't an ACME API call and needs no JWS.
+pub(crate) fn post_ocsp(http: &dyn HttpClient, url: &str, body: &[u8]) -> Result<HttpResponse> {
+    let req = HttpRequest {
+        method: Method::Post,
+        url,
+        content_type: Some("application/ocsp-request"),
+        body,
+    };
+    trace!("{:?}", req);
+    http.request(&req)
+}
+
+/// Turn responses with an error status into an `Error::ApiProblem`.
+pub(crate) fn check_status(res: HttpResponse) -> Result<HttpResponse> {
+    if !res.is_error() {
+        return Ok(res);
+    }
+
+    let status = res.status();
+    let retry_after = retry_after(&res);
+
+    let mut problem = if res.content_type() == "application/problem+json" {
+        // if we were sent a problem+json, deserialize it
+        let body = res.extract_body();
+        serde_json::from_str(&body).unwrap_or_else(|e| ApiProblem {
+            _type: "problemJsonFail".into(),
+            detail: Some(format!(
+                "Failed to deserialize application/problem+json ({}) body: {}",
+                e, body
+            )),
+            ..Default::default()
+        })
+    } else {
+        // some other problem
+        let body = res.extract_body();
+        let detail = format!("{} body: {}", status, body);
+
+        ApiProblem {
+            _type: "httpReqError".into(),
+            detail: Some(detail),
+            ..Default::default()
+        }
+    };
+
+    // the HTTP status takes precedence over the one in the body.
+    problem.status = Some(status);
+    problem.retry_after = retry_after;
+
+    Err(Error::ApiProblem(problem))
 }
 
 pub(crate) trait ExtractHeader {
     fn extract_header(&self, name: &str) -> ApiResult<String>;
 }
 
-impl ExtractHeader for ureq::Response {
+impl ExtractHeader for HttpResponse {
     fn extract_header(&self, name: &str) -> ApiResult<String> {
         self.header(name)
             .map(|v| v.to_string())
             .ok_or_else(|| ApiProblem {
                 _type: format!("Missing header: {}", name),
-                detail: None,
-                subproblems: None,
+                ..Default::default()
             })
     }
 }
 
+/// Read the `Retry-After` header, which is either a number of seconds
+/// or an HTTP date.
+pub(crate) fn retry_after(res: &HttpResponse) -> Option<Duration> {
+    parse_retry_after(res.header("retry-after")?, OffsetDateTime::now_utc())
+}
+
+fn parse_retry_after(value: &str, now: OffsetDateTime) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+    // Sun, 06 Nov 1994 08:49:37 GMT
+    let format = format_description!(
+        "[weekday repr:short], [day] [month repr:short] [year] [hour]:[minute]:[second] GMT"
+    );
+    let at = PrimitiveDateTime::parse(value, &format).ok()?.assume_utc();
+    // a date in the past means retry right away.
+    Some(Duration::try_from(at - now).unwrap_or(Duration::ZERO))
+}
+
 pub(crate) trait ExtractBody {
     fn extract_body(self) -> String;
 }
 
-impl ExtractBody for ureq::Response {
+impl ExtractBody for HttpResponse {
     fn extract_body(self) -> String {
-        use std::io::Read;
+        String::from_utf8_lossy(self.body()).to_string()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use time::macros::datetime;
 
-        let mut res_body = String::new();
-        // letsencrypt sometimes closes the TLS abruptly causing io error
-        // even though we did capture the body.
-        self.into_reader().read_to_string(&mut res_body).ok();
-        res_body
+    #[test]
+    fn test_parse_retry_after() {
+        let now = datetime!(2019-05-03 07:40:15 UTC);
+        assert_eq!(
+            parse_retry_after("120", now),
+            Some(Duration::from_secs(120))
+        );
+        assert_eq!(
+            parse_retry_after("Fri, 03 May 2019 07:41:15 GMT", now),
+            Some(Duration::from_secs(60))
+        );
+        assert_eq!(
+            parse_retry_after("Fri, 03 May 2019 07:30:00 GMT", now),
+            Some(Duration::ZERO)
+        );
+        assert_eq!(parse_retry_after("soon", now), None);
     }
 }
@@ -0,0 +1,160 @@
+//! Signers for the JWS of the ACME account.
+//!
+//! Every request against the ACME API is signed by the account key. By default the
+//! key is generated by the library and kept as a PEM in the [`Persist`]. When the key
+//! must not leave an HSM, a cloud KMS or a PKCS#11 token, implement [`JwsSigner`] and
+//! use [`Directory::account_with_signer`].
+//!
+//! ```no_run
+//! use acme_lib::signer::{Jwk, JwsSigner};
+//! use acme_lib::persist::FilePersist;
+//! use acme_lib::{Directory, DirectoryUrl, Result};
+//!
+//! struct MyKms;
+//!
+//! impl JwsSigner for MyKms {
+//!     fn jwk(&self) -> Result<Jwk> {
+//!         // let (x, y) = kms.public_key_coordinates()?;
+//!         # let (x, y) = ([0; 32], [0; 32]);
+//!         Ok(Jwk::p256(&x, &y))
+//!     }
+//!     fn sign(&self, data: &[u8]) -> Result<Vec<u8>> {
+//!         // kms.sign_es256(data)
+//!         # Ok(vec![])
+//!     }
+//! }
+//!
+//! fn account() -> Result<()> {
+//!     let dir = Directory::from_url(FilePersist::new("."), DirectoryUrl::LetsEncrypt)?;
+//!     let contact = vec!["mailto:foo@bar.com".to_string()];
+//!     let acc = dir.account_with_signer("foo@bar.com", Some(contact), MyKms)?;
+//!     Ok(())
+//! }
+//! ```
+//!
+//! [`Persist`]: ../persist/trait.Persist.html
+//! [`JwsSigner`]: trait.JwsSigner.html
+//! [`Directory::account_with_signer`]: ../struct.Directory.html#method.account_with_signer
+use std::fmt;
+
+use crate::{crypto::EcP256Key, Result};
+
+pub use crate::crypto::csr::SignatureAlgorithm;
+pub use crate::jwt::Jwk;
+
+#[cfg(feature = "pkcs11")]
+mod pkcs11;
+
+#[cfg(feature = "pkcs11")]
+pub use self::pkcs11::Pkcs11Signer;
+
+/// Signer of the JWS sent to the ACME API.
+///
+/// The only supported algorithm is `ES256`, i.e. ECDSA with the P-256 curve and SHA-256.
+pub trait JwsSigner: Send + Sync {
+    /// The public key of the signer. Called once when the account is accessed.
+    fn jwk(&self) -> Result<Jwk>;
+
+    /// Sign the data with SHA-256 and ECDSA. The signature is the 64 bytes `r || s`,
+    /// not DER encoded.
+    fn sign(&self, data: &[u8]) -> Result<Vec<u8>>;
+}
+
+/// Signer of the CSR for a certificate whose private key is held externally.
+///
+/// Used with [`CsrOrder::finalize_signer`]. The library never sees the private key, and
+/// only the public key is saved in the persistence alongside the certificate.
+///
+/// [`CsrOrder::finalize_signer`]: ../order/struct.CsrOrder.html#method.finalize_signer
+pub trait CsrSigner: Send + Sync {
+    /// The DER encoded `SubjectPublicKeyInfo` of the certificate key.
+    fn public_key_der(&self) -> Result<Vec<u8>>;
+
+    /// The algorithm [`sign`] uses.
+    ///
+    /// [`sign`]: trait.CsrSigner.html#tymethod.sign
+    fn algorithm(&self) -> SignatureAlgorithm;
+
+    /// Sign the data, hashing it as part of the signature algorithm. ECDSA signatures
+    /// are DER encoded.
+    fn sign(&self, data: &[u8]) -> Result<Vec<u8>>;
+}
+
+/// Signer with the private key in memory.
+///
+/// This is what the library uses for accounts whose key is kept in the persistence.
+#[derive(Clone)]
+pub struct SoftwareSigner {
+    key: EcP256Key,
+}
+
+impl SoftwareSigner {
+    /// Generate a new P-256 key.
+    pub fn generate() -> Result<SoftwareSigner> {
+        Ok(SoftwareSigner {
+            key: EcP256Key::generate()?,
+        })
+    }
+
+    /// Read a P-256 private key from PEM.
+    pub fn from_pem(pem: &[u8]) -> Result<SoftwareSigner> {
+        Ok(SoftwareSigner {
+            key: EcP256Key::from_pem(pem)?,
+        })
+    }
+
+    /// The private key as SEC1 PEM (`EC PRIVATE KEY`).
+    pub fn to_pem(&self) -> Result<Vec<u8>> {
+        self.key.to_pem()
+    }
+}
+
+impl JwsSigner for SoftwareSigner {
+    fn jwk(&self) -> Result<Jwk> {
+        let (x, y) = self.key.public_xy()?;
+        Ok(Jwk::p256(&x, &y))
+    }
+
+    fn sign(&self, data: &[u8]) -> Result<Vec<u8>> {
+        self.key.sign_es256(data)
+    }
+}
+
+impl fmt::Debug for SoftwareSigner {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "SoftwareSigner")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::persist::*;
+    use crate::{Directory, DirectoryUrl};
+
+    #[test]
+    fn test_software_signer_pem() -> Result<()> {
+        let signer = SoftwareSigner::generate()?;
+        let again = SoftwareSigner::from_pem(&signer.to_pem()?)?;
+        assert_eq!(signer.jwk()?, again.jwk()?);
+        assert_eq!(signer.sign(b"data")?.len(), 64);
+        Ok(())
+    }
+
+    #[test]
+    fn test_account_with_signer() -> Result<()> {
+        let server = crate::test::with_directory_server();
+        let url = DirectoryUrl::Other(&server.dir_url);
+        let persist = MemoryPersist::new();
+        let dir = Directory::from_url(persist.clone(), url)?;
+        let acc = dir.account_with_signer("foo@bar.com", None, SoftwareSigner::generate()?)?;
+        let key = PersistKey::new(
+            "foo@bar.com",
+            PersistKind::AccountPrivateKey,
+            "acme_account",
+        );
+        assert!(persist.get(&key)?.is_none());
+        assert!(acc.acme_private_key_pem().is_err());
+        Ok(())
+    }
+}
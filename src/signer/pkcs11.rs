@@ -0,0 +1,472 @@
+//! PKCS#11 signer (requires the `pkcs11` feature).
+//!
+//! The module is loaded at runtime, so there is no build time dependency on any
+//! particular PKCS#11 implementation. Only the handful of functions needed to find
+//! a P-256 key pair and sign with `CKM_ECDSA` are bound.
+use libloading::Library;
+use once_cell::sync::Lazy;
+use std::{collections::HashMap, ffi::c_void, fmt, os::raw::c_ulong, path::Path, ptr, sync::Mutex};
+
+use super::{Jwk, JwsSigner};
+use crate::{crypto::sha256, Error, Result};
+
+type CkUlong = c_ulong;
+type CkRv = CkUlong;
+
+const CKR_OK: CkRv = 0;
+const CKR_USER_ALREADY_LOGGED_IN: CkRv = 0x100;
+const CKR_CRYPTOKI_ALREADY_INITIALIZED: CkRv = 0x191;
+
+const CKF_OS_LOCKING_OK: CkUlong = 0x2;
+const CKF_SERIAL_SESSION: CkUlong = 0x4;
+const CKU_USER: CkUlong = 1;
+
+const CKA_CLASS: CkUlong = 0x0;
+const CKA_LABEL: CkUlong = 0x3;
+const CKA_EC_POINT: CkUlong = 0x181;
+const CKO_PUBLIC_KEY: CkUlong = 2;
+const CKO_PRIVATE_KEY: CkUlong = 3;
+const CKM_ECDSA: CkUlong = 0x1041;
+
+/// The number of signers using each module this library initialized, by the address of
+/// its function list. The module is finalized when the last of them is dropped, so one
+/// signer doesn't tear it down under the others.
+static INITIALIZED: Lazy<Mutex<HashMap<usize, usize>>> = Lazy::new(Default::default);
+
+#[repr(C)]
+struct CkAttribute {
+    kind: CkUlong,
+    value: *mut c_void,
+    value_len: CkUlong,
+}
+
+#[repr(C)]
+struct CkMechanism {
+    mechanism: CkUlong,
+    parameter: *mut c_void,
+    parameter_len: CkUlong,
+}
+
+#[repr(C)]
+struct CkInitializeArgs {
+    create_mutex: *mut c_void,
+    destroy_mutex: *mut c_void,
+    lock_mutex: *mut c_void,
+    unlock_mutex: *mut c_void,
+    flags: CkUlong,
+    reserved: *mut c_void,
+}
+
+#[repr(C)]
+struct CkTokenInfo {
+    label: [u8; 32],
+    manufacturer_id: [u8; 32],
+    model: [u8; 16],
+    serial_number: [u8; 16],
+    counters: [CkUlong; 11],
+    hardware_version: [u8; 2],
+    firmware_version: [u8; 2],
+    utc_time: [u8; 16],
+}
+
+type Unused = Option<unsafe extern "C" fn()>;
+
+/// The start of `CK_FUNCTION_LIST` up to `C_Sign`.
+#[repr(C)]
+struct CkFunctionList {
+    version: [u8; 2],
+    initialize: unsafe extern "C" fn(*mut c_void) -> CkRv,
+    finalize: unsafe extern "C" fn(*mut c_void) -> CkRv,
+    _get_info: Unused,
+    _get_function_list: Unused,
+    get_slot_list: unsafe extern "C" fn(u8, *mut CkUlong, *mut CkUlong) -> CkRv,
+    _get_slot_info: Unused,
+    get_token_info: unsafe extern "C" fn(CkUlong, *mut CkTokenInfo) -> CkRv,
+    _get_mechanism_list: Unused,
+    _get_mechanism_info: Unused,
+    _init_token: Unused,
+    _init_pin: Unused,
+    _set_pin: Unused,
+    open_session: unsafe extern "C" fn(CkUlong, CkUlong, *mut c_void, Unused, *mut CkUlong) -> CkRv,
+    close_session: unsafe extern "C" fn(CkUlong) -> CkRv,
+    _close_all_sessions: Unused,
+    _get_session_info: Unused,
+    _get_operation_state: Unused,
+    _set_operation_state: Unused,
+    login: unsafe extern "C" fn(CkUlong, CkUlong, *const u8, CkUlong) -> CkRv,
+    _logout: Unused,
+    _create_object: Unused,
+    _copy_object: Unused,
+    _destroy_object: Unused,
+    _get_object_size: Unused,
+    get_attribute_value: unsafe extern "C" fn(CkUlong, CkUlong, *mut CkAttribute, CkUlong) -> CkRv,
+    _set_attribute_value: Unused,
+    find_objects_init: unsafe extern "C" fn(CkUlong, *mut CkAttribute, CkUlong) -> CkRv,
+    find_objects: unsafe extern "C" fn(CkUlong, *mut CkUlong, CkUlong, *mut CkUlong) -> CkRv,
+    find_objects_final: unsafe extern "C" fn(CkUlong) -> CkRv,
+    _encrypt: [Unused; 4],
+    _decrypt: [Unused; 4],
+    _digest: [Unused; 5],
+    sign_init: unsafe extern "C" fn(CkUlong, *mut CkMechanism, CkUlong) -> CkRv,
+    sign: unsafe extern "C" fn(CkUlong, *const u8, CkUlong, *mut u8, *mut CkUlong) -> CkRv,
+}
+
+fn check(name: &str, rv: CkRv) -> Result<()> {
+    if rv == CKR_OK {
+        Ok(())
+    } else {
+        Err(Error::Crypto(format!(
+            "PKCS#11 {} failed: 0x{:x}",
+            name, rv
+        )))
+    }
+}
+
+/// Signer with the account key on a PKCS#11 token, such as an HSM or SoftHSM.
+///
+/// The token must hold a P-256 key pair where both the private and the public key
+/// object have the same label.
+///
+/// Signers for the same module share its initialization, which is finalized when the
+/// last of them is dropped. A module initialized by other code in the process is never
+/// finalized.
+pub struct Pkcs11Signer {
+    functions: *const CkFunctionList,
+    /// One session, since a PKCS#11 session can only do one operation at a time.
+    session: Mutex<CkUlong>,
+    private_key: CkUlong,
+    jwk: Jwk,
+    /// Whether this signer counts in `INITIALIZED`.
+    counted: bool,
+    // keep last so it is unloaded after the session is closed
+    _library: Library,
+}
+
+// The function list is immutable and the PKCS#11 module is initialized with
+// CKF_OS_LOCKING_OK. All use of the session goes through the mutex.
+unsafe impl Send for Pkcs11Signer {}
+unsafe impl Sync for Pkcs11Signer {}
+
+impl Pkcs11Signer {
+    /// Load the PKCS#11 module, log in to the token with `token_label` and find the
+    /// key pair with `key_label`.
+    pub fn open(
+        module: impl AsRef<Path>,
+        token_label: &str,
+        pin: &str,
+        key_label: &str,
+    ) -> Result<Pkcs11Signer> {
+        // SAFETY: loading a PKCS#11 module runs its initializers, which is what the
+        // caller asks for by naming the module.
+        let library = unsafe { Library::new(module.as_ref()) }
+            .map_err(|e| Error::Crypto(format!("Failed to load PKCS#11 module: {}", e)))?;
+
+        let functions = unsafe {
+            let get_function_list = library
+                .get::<unsafe extern "C" fn(*mut *const CkFunctionList) -> CkRv>(
+                    b"C_GetFunctionList\0",
+                )
+                .map_err(|e| Error::Crypto(format!("Not a PKCS#11 module: {}", e)))?;
+            let mut functions = ptr::null();
+            check("C_GetFunctionList", get_function_list(&mut functions))?;
+            functions
+        };
+
+        let counted = {
+            let mut initialized = INITIALIZED.lock().unwrap();
+            match initialized.get_mut(&(functions as usize)) {
+                Some(count) => {
+                    *count += 1;
+                    true
+                }
+                None => {
+                    let mut args = CkInitializeArgs {
+                        create_mutex: ptr::null_mut(),
+                        destroy_mutex: ptr::null_mut(),
+                        lock_mutex: ptr::null_mut(),
+                        unlock_mutex: ptr::null_mut(),
+                        flags: CKF_OS_LOCKING_OK,
+                        reserved: ptr::null_mut(),
+                    };
+                    let rv =
+                        unsafe { ((*functions).initialize)(&mut args as *mut _ as *mut c_void) };
+                    // Someone else in the process owns the module, leave finalizing to them.
+                    let owned = rv != CKR_CRYPTOKI_ALREADY_INITIALIZED;
+                    if owned {
+                        check("C_Initialize", rv)?;
+                        initialized.insert(functions as usize, 1);
+                    }
+                    owned
+                }
+            }
+        };
+
+        let mut signer = Pkcs11Signer {
+            functions,
+            session: Mutex::new(0),
+            private_key: 0,
+            jwk: Jwk::p256(&[], &[]),
+            counted,
+            _library: library,
+        };
+
+        let slot = signer.find_slot(token_label)?;
+        let session = signer.open_session(slot, pin)?;
+        *signer.session.get_mut().unwrap() = session;
+
+        signer.private_key = signer.find_object(session, CKO_PRIVATE_KEY, key_label)?;
+        let public_key = signer.find_object(session, CKO_PUBLIC_KEY, key_label)?;
+        let point = signer.attribute(session, public_key, CKA_EC_POINT)?;
+        let (x, y) = ec_point_xy(&point)?;
+        signer.jwk = Jwk::p256(x, y);
+
+        Ok(signer)
+    }
+
+    fn f(&self) -> &CkFunctionList {
+        // SAFETY: the pointer is from C_GetFunctionList and lives as long as the library.
+        unsafe { &*self.functions }
+    }
+
+    fn find_slot(&self, token_label: &str) -> Result<CkUlong> {
+        let f = self.f();
+        let mut count = 0;
+        check("C_GetSlotList", unsafe {
+            (f.get_slot_list)(1, ptr::null_mut(), &mut count)
+        })?;
+        let mut slots = vec![0; count as usize];
+        check("C_GetSlotList", unsafe {
+            (f.get_slot_list)(1, slots.as_mut_ptr(), &mut count)
+        })?;
+        slots.truncate(count as usize);
+
+        for slot in slots {
+            let mut info: CkTokenInfo = unsafe { std::mem::zeroed() };
+            check("C_GetTokenInfo", unsafe {
+                (f.get_token_info)(slot, &mut info)
+            })?;
+            // labels are padded with blanks
+            let label = String::from_utf8_lossy(&info.label);
+            if label.trim_end() == token_label {
+                return Ok(slot);
+            }
+        }
+        Err(Error::Crypto(format!(
+            "No PKCS#11 token with label: {}",
+            token_label
+        )))
+    }
+
+    fn open_session(&self, slot: CkUlong, pin: &str) -> Result<CkUlong> {
+        let f = self.f();
+        let mut session = 0;
+        check("C_OpenSession", unsafe {
+            (f.open_session)(
+                slot,
+                CKF_SERIAL_SESSION,
+                ptr::null_mut(),
+                None,
+                &mut session,
+            )
+        })?;
+        let rv = unsafe { (f.login)(session, CKU_USER, pin.as_ptr(), pin.len() as CkUlong) };
+        if rv != CKR_USER_ALREADY_LOGGED_IN {
+            if let Err(e) = check("C_Login", rv) {
+                unsafe { (f.close_session)(session) };
+                return Err(e);
+            }
+        }
+        Ok(session)
+    }
+
+    fn find_object(&self, session: CkUlong, class: CkUlong, label: &str) -> Result<CkUlong> {
+        let f = self.f();
+        let mut class = class;
+        let mut template = [
+            CkAttribute {
+                kind: CKA_CLASS,
+                value: &mut class as *mut _ as *mut c_void,
+                value_len: std::mem::size_of::<CkUlong>() as CkUlong,
+            },
+            CkAttribute {
+                kind: CKA_LABEL,
+                value: label.as_ptr() as *mut c_void,
+                value_len: label.len() as CkUlong,
+            },
+        ];
+        check("C_FindObjectsInit", unsafe {
+            (f.find_objects_init)(session, template.as_mut_ptr(), template.len() as CkUlong)
+        })?;
+        let mut object = 0;
+        let mut count = 0;
+        let rv = unsafe { (f.find_objects)(session, &mut object, 1, &mut count) };
+        unsafe { (f.find_objects_final)(session) };
+        check("C_FindObjects", rv)?;
+        if count == 0 {
+            return Err(Error::Crypto(format!(
+                "No PKCS#11 key with label: {}",
+                label
+            )));
+        }
+        Ok(object)
+    }
+
+    fn attribute(&self, session: CkUlong, object: CkUlong, kind: CkUlong) -> Result<Vec<u8>> {
+        let f = self.f();
+        let mut attr = CkAttribute {
+            kind,
+            value: ptr::null_mut(),
+            value_len: 0,
+        };
+        check("C_GetAttributeValue", unsafe {
+            (f.get_attribute_value)(session, object, &mut attr, 1)
+        })?;
+        let mut value = vec![0_u8; attr.value_len as usize];
+        attr.value = value.as_mut_ptr() as *mut c_void;
+        check("C_GetAttributeValue", unsafe {
+            (f.get_attribute_value)(session, object, &mut attr, 1)
+        })?;
+        value.truncate(attr.value_len as usize);
+        Ok(value)
+    }
+}
+
+impl JwsSigner for Pkcs11Signer {
+    fn jwk(&self) -> Result<Jwk> {
+        Ok(self.jwk.clone())
+    }
+
+    fn sign(&self, data: &[u8]) -> Result<Vec<u8>> {
+        let f = self.f();
+        let session = self.session.lock().unwrap();
+
+        // CKM_ECDSA signs a digest, which lets us hash locally.
+        let digest = sha256(data);
+        let mut mechanism = CkMechanism {
+            mechanism: CKM_ECDSA,
+            parameter: ptr::null_mut(),
+            parameter_len: 0,
+        };
+        check("C_SignInit", unsafe {
+            (f.sign_init)(*session, &mut mechanism, self.private_key)
+        })?;
+        let mut sig = vec![0_u8; 64];
+        let mut sig_len = sig.len() as CkUlong;
+        check("C_Sign", unsafe {
+            (f.sign)(
+                *session,
+                digest.as_ptr(),
+                digest.len() as CkUlong,
+                sig.as_mut_ptr(),
+                &mut sig_len,
+            )
+        })?;
+        if sig_len != 64 {
+            return Err(Error::Crypto(format!(
+                "Unexpected ECDSA signature length: {}",
+                sig_len
+            )));
+        }
+        Ok(sig)
+    }
+}
+
+impl Drop for Pkcs11Signer {
+    fn drop(&mut self) {
+        let session = *self.session.get_mut().unwrap();
+        unsafe {
+            if session != 0 {
+                (self.f().close_session)(session);
+            }
+        }
+        if !self.counted {
+            return;
+        }
+        let mut initialized = INITIALIZED.lock().unwrap();
+        let key = self.functions as usize;
+        if let Some(count) = initialized.get_mut(&key) {
+            *count -= 1;
+            if *count == 0 {
+                initialized.remove(&key);
+                unsafe {
+                    (self.f().finalize)(ptr::null_mut());
+                }
+            }
+        }
+    }
+}
+
+impl fmt::Debug for Pkcs11Signer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Pkcs11Signer")
+            .field("jwk", &self.jwk)
+            .finish()
+    }
+}
+
+/// The affine coordinates from a `CKA_EC_POINT`, which is an uncompressed point
+/// that most modules wrap in a DER OCTET STRING.
+fn ec_point_xy(point: &[u8]) -> Result<(&[u8], &[u8])> {
+    let point = match point {
+        [0x04, 0x41, rest @ ..] if rest.len() == 65 => rest,
+        _ => point,
+    };
+    match point {
+        [0x04, xy @ ..] if xy.len() == 64 => Ok(xy.split_at(32)),
+        _ => Err(Error::Crypto("Not an uncompressed P-256 point".into())),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_ec_point_xy() -> Result<()> {
+        let mut raw = vec![0x04];
+        raw.extend([1; 32]);
+        raw.extend([2; 32]);
+        let mut der = vec![0x04, 0x41];
+        der.extend(&raw);
+        for p in [&raw, &der] {
+            let (x, y) = ec_point_xy(p)?;
+            assert_eq!(x, &[1; 32][..]);
+            assert_eq!(y, &[2; 32][..]);
+        }
+        assert!(ec_point_xy(&raw[..40]).is_err());
+        Ok(())
+    }
+
+    /// Run against SoftHSM with a P-256 key pair, for example:
+    ///
+    /// ```text
+    /// softhsm2-util --init-token --free --label acme --pin 1234 --so-pin 1234
+    /// pkcs11-tool --module /usr/lib/softhsm/libsofthsm2.so --token-label acme \
+    ///     --login --pin 1234 --keypairgen --key-type EC:prime256v1 --label account
+    /// ACME_PKCS11_MODULE=/usr/lib/softhsm/libsofthsm2.so ACME_PKCS11_TOKEN=acme \
+    ///     ACME_PKCS11_PIN=1234 ACME_PKCS11_KEY=account \
+    ///     cargo test --features pkcs11 -- --ignored pkcs11
+    /// ```
+    #[test]
+    #[ignore]
+    fn test_pkcs11_sign() -> Result<()> {
+        let env = |k: &str| std::env::var(k).unwrap_or_else(|_| panic!("{} not set", k));
+        let open = || {
+            Pkcs11Signer::open(
+                env("ACME_PKCS11_MODULE"),
+                &env("ACME_PKCS11_TOKEN"),
+                &env("ACME_PKCS11_PIN"),
+                &env("ACME_PKCS11_KEY"),
+            )
+        };
+        let signer = open()?;
+        assert_eq!(signer.jwk()?.alg(), "ES256");
+        assert_eq!(signer.sign(b"data")?.len(), 64);
+
+        // dropping one signer leaves the module initialized for the other
+        let other = open()?;
+        drop(signer);
+        assert_eq!(other.sign(b"data")?.len(), 64);
+        Ok(())
+    }
+}
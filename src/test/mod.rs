@@ -6,9 +6,54 @@ use hyper::{
     Body, Method, Request, Response, Server,
 };
 use once_cell::sync::Lazy;
-use std::{convert::Infallible, net::TcpListener, sync::Arc};
+use std::{
+    convert::Infallible,
+    net::TcpListener,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
 use tokio::runtime::Runtime;
 
+/// Certificate for example.com (with www, a wildcard and two IPs) followed by its
+/// intermediate, valid until 2048. The test server issues it for every order.
+pub const EXAMPLE_CHAIN: &str = include_str!("example_chain.pem");
+
+/// The P-256 private key of the certificate in `EXAMPLE_CHAIN`.
+pub const EXAMPLE_KEY: &str = include_str!("example_key.pem");
+
+/// The self-signed root that issued the intermediate in `EXAMPLE_CHAIN`.
+pub const EXAMPLE_ROOT: &str = include_str!("example_root.pem");
+
+/// OCSP response for the certificate in `EXAMPLE_CHAIN` signed by the intermediate,
+/// status good until 2048.
+pub const OCSP_GOOD: &[u8] = include_bytes!("ocsp_good.der");
+
+/// Like `OCSP_GOOD`, but revoked at 2025-01-01 for key compromise.
+pub const OCSP_REVOKED: &[u8] = include_bytes!("ocsp_revoked.der");
+
+/// Like `OCSP_GOOD`, but signed by a responder the intermediate delegated to.
+pub const OCSP_DELEGATED: &[u8] = include_bytes!("ocsp_delegated.der");
+
+/// Like `OCSP_GOOD`, but signed by the root, which didn't issue the certificate.
+pub const OCSP_FORGED: &[u8] = include_bytes!("ocsp_forged.der");
+
+/// Like `OCSP_DELEGATED`, but the delegate is a TLS server certificate without the
+/// OCSPSigning extended key usage.
+pub const OCSP_NOT_SIGNER: &[u8] = include_bytes!("ocsp_not_signer.der");
+
+/// Like `OCSP_GOOD`, but about the next serial number of the same issuer.
+pub const OCSP_OTHER_SERIAL: &[u8] = include_bytes!("ocsp_other_serial.der");
+
+/// Like `OCSP_GOOD`, but only valid from 2047-01-01.
+pub const OCSP_FUTURE: &[u8] = include_bytes!("ocsp_future.der");
+
+/// The key to finalize orders with, since the test server always issues `EXAMPLE_CHAIN`.
+pub fn example_key() -> crate::PrivateKey {
+    crate::PrivateKey::from_pem(EXAMPLE_KEY.as_bytes()).unwrap()
+}
+
 static RE_URL: Lazy<regex::Regex> = Lazy::new(|| regex::Regex::new("<URL>").unwrap());
 
 pub struct TestServer {
@@ -23,6 +68,7 @@ fn get_directory(url: &str) -> Response<Body> {
     "newNonce": "<URL>/acme/new-nonce",
     "newOrder": "<URL>/acme/new-order",
     "revokeCert": "<URL>/acme/revoke-cert",
+    "renewalInfo": "<URL>/acme/renewal-info",
     "meta": {
         "caaIdentities": [
         "testdir.org"
@@ -114,13 +160,13 @@ fn post_get_order(url: &str) -> Response<Body> {
     Response::builder().status(200).body(Body::from(b)).unwrap()
 }
 
-fn post_authz(url: &str) -> Response<Body> {
+fn post_authz(url: &str, validated: bool) -> Response<Body> {
     const BODY: &str = r#"{
         "identifier": {
             "type": "dns",
             "value": "acmetest.algesten.se"
         },
-        "status": "pending",
+        "status": "<STATUS>",
         "expires": "2019-01-09T08:26:43Z",
         "challenges": [
         {
@@ -143,8 +189,20 @@ fn post_authz(url: &str) -> Response<Body> {
         }
         ]
     }"#;
+    let status = if validated { "valid" } else { "pending" };
+    let b = RE_URL.replace_all(BODY, url).replace("<STATUS>", status);
+    Response::builder().status(201).body(Body::from(b)).unwrap()
+}
+
+fn post_challenge(url: &str) -> Response<Body> {
+    const BODY: &str = r#"{
+        "type": "http-01",
+        "status": "processing",
+        "url": "<URL>/acme/challenge/YTqpYUthlVfwBncUufE8IRWLMSRqcSs/216789597",
+        "token": "MUi-gqeOJdRkSb_YR2eaMxQBqf6al8dgt_dOttSWb0w"
+    }"#;
     Response::builder()
-        .status(201)
+        .status(200)
         .body(Body::from(RE_URL.replace_all(BODY, url)))
         .unwrap()
 }
@@ -156,23 +214,69 @@ fn post_finalize(_url: &str) -> Response<Body> {
 fn post_certificate(_url: &str) -> Response<Body> {
     Response::builder()
         .status(200)
-        .body("CERT HERE".into())
+        .body(EXAMPLE_CHAIN.into())
         .unwrap()
 }
 
-fn route_request(req: Request<Body>, uri: &str) -> Response<Body> {
+fn post_revoke_cert() -> Response<Body> {
+    Response::builder()
+        .status(200)
+        .header(
+            "Replay-Nonce",
+            "Lb5Qk_zBNQ3y-9x3tAsJSqCNnLmfL6QpG9UAVTHsCjM",
+        )
+        .body(Body::empty())
+        .unwrap()
+}
+
+/// The ARI id of the certificate in `EXAMPLE_CHAIN`.
+pub const EXAMPLE_ARI_ID: &str = "H4019SyVE8cSzUfAp_rKD3zauQA.AMD_7gEjRWeJ";
+
+fn get_renewal_info(path: &str) -> Response<Body> {
+    // the window has passed, so the example certificate is due
+    const BODY: &str = r#"{
+    "suggestedWindow": {
+        "start": "2025-01-01T00:00:00Z",
+        "end": "2025-01-02T00:00:00Z"
+    },
+    "explanationURL": "https://testdir.org/ari"
+    }"#;
+    if path.strip_prefix("/acme/renewal-info/") != Some(EXAMPLE_ARI_ID) {
+        return Response::builder().status(404).body(Body::empty()).unwrap();
+    }
+    Response::builder()
+        .status(200)
+        .header("Retry-After", "21600")
+        .body(Body::from(BODY))
+        .unwrap()
+}
+
+fn route_request(req: Request<Body>, uri: &str, validated: &AtomicBool) -> Response<Body> {
     let method = req.method();
     let path = req.uri().path();
 
+    if method == Method::GET && path.starts_with("/acme/renewal-info/") {
+        return get_renewal_info(path);
+    }
+
+    if method == Method::POST && path.starts_with("/acme/challenge/") {
+        // once a challenge is triggered, the authorization turns valid.
+        validated.store(true, Ordering::SeqCst);
+        return post_challenge(uri);
+    }
+
     match (method, path) {
         (&Method::GET, "/directory") => get_directory(uri),
         (&Method::HEAD, "/acme/new-nonce") => head_new_nonce(),
         (&Method::POST, "/acme/new-acct") => post_new_acct(uri),
         (&Method::POST, "/acme/new-order") => post_new_order(uri),
         (&Method::POST, "/acme/order/YTqpYUthlVfwBncUufE8") => post_get_order(uri),
-        (&Method::POST, "/acme/authz/YTqpYUthlVfwBncUufE8IRWLMSRqcSs") => post_authz(uri),
+        (&Method::POST, "/acme/authz/YTqpYUthlVfwBncUufE8IRWLMSRqcSs") => {
+            post_authz(uri, validated.load(Ordering::SeqCst))
+        }
         (&Method::POST, "/acme/finalize/7738992/18234324") => post_finalize(uri),
         (&Method::POST, "/acme/cert/fae41c070f967713109028") => post_certificate(uri),
+        (&Method::POST, "/acme/revoke-cert") => post_revoke_cert(),
         (_, _) => Response::builder().status(404).body(Body::empty()).unwrap(),
     }
 }
@@ -189,13 +293,18 @@ pub fn with_directory_server() -> TestServer {
     let rt = tokio::runtime::Runtime::new().unwrap();
     rt.spawn(async move {
         println!("entered spawned fn");
+        let validated = Arc::new(AtomicBool::new(false));
         let make_service = make_service_fn(move |_conn| {
             let svc_url = arc_url.clone();
+            let svc_validated = validated.clone();
 
             async move {
                 Ok::<_, Infallible>(service_fn(move |req| {
                     let req_url = svc_url.clone();
-                    async move { Ok::<_, Infallible>(route_request(req, req_url.as_str())) }
+                    let req_validated = svc_validated.clone();
+                    async move {
+                        Ok::<_, Infallible>(route_request(req, req_url.as_str(), &req_validated))
+                    }
                 }))
             }
         });
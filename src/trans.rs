@@ -1,15 +1,16 @@
-use openssl::{ecdsa::EcdsaSig, sha::sha256};
 use serde::Serialize;
 use std::{
     collections::VecDeque,
+    fmt,
     sync::{Arc, Mutex},
 };
 
 use crate::{
     acc::AcmeKey,
-    api::ApiProblem,
+    crypto::KeyPair,
+    http::{HttpClient, HttpResponse},
     jwt::*,
-    req::{head, post, ExtractHeader},
+    req::{check_status, head, post, ExtractHeader},
     util::base64url,
     Error, Result,
 };
@@ -22,15 +23,17 @@ use crate::{
 /// 2. `call_jwk()` against newAccount url
 /// 3. `set_key_id` from the returned `Location` header.
 /// 4. `call()` for all calls after that.
-#[derive(Clone, Debug)]
+#[derive(Clone)]
 pub(crate) struct Transport {
+    http: Arc<dyn HttpClient>,
     acme_key: AcmeKey,
     nonce_pool: Arc<NoncePool>,
 }
 
 impl Transport {
-    pub fn new(nonce_pool: &Arc<NoncePool>, acme_key: AcmeKey) -> Self {
+    pub fn new(http: &Arc<dyn HttpClient>, nonce_pool: &Arc<NoncePool>, acme_key: AcmeKey) -> Self {
         Transport {
+            http: http.clone(),
             acme_key,
             nonce_pool: nonce_pool.clone(),
         }
@@ -47,61 +50,99 @@ impl Transport {
     }
 
     /// Make call using the full jwk. Only for the first newAccount request.
-    pub fn call_jwk<T: Serialize + ?Sized>(&self, url: &str, body: &T) -> Result<ureq::Response> {
+    pub fn call_jwk<T: Serialize + ?Sized>(&self, url: &str, body: &T) -> Result<HttpResponse> {
         self.do_call(url, body, jws_with_jwk)
     }
 
     /// Make call using the key id
-    pub fn call<T: Serialize + ?Sized>(&self, url: &str, body: &T) -> Result<ureq::Response> {
+    pub fn call<T: Serialize + ?Sized>(&self, url: &str, body: &T) -> Result<HttpResponse> {
         self.do_call(url, body, jws_with_kid)
     }
 
+    /// The HTTP client used for the calls.
+    pub fn http(&self) -> &dyn HttpClient {
+        &*self.http
+    }
+
     fn do_call<T: Serialize + ?Sized, F: Fn(&str, String, &AcmeKey, &T) -> Result<String>>(
         &self,
         url: &str,
         body: &T,
         make_body: F,
-    ) -> Result<ureq::Response> {
-        // The ACME API may at any point invalidate all nonces. If we detect such an
-        // error, we loop until the server accepts the nonce.
-        loop {
-            // Either get a new nonce, or reuse one from a previous request.
-            let nonce = self.nonce_pool.get_nonce()?;
-
-            // Sign the body.
-            let body = make_body(url, nonce, &self.acme_key, body)?;
-
-            debug!("Call endpoint {}", url);
-
-            // Post it to the URL
-            let response = post(url, &body);
-
-            // Regardless of the request being a success or not, there might be
-            // a nonce in the response.
-            self.nonce_pool.extract_nonce(&response);
-
-            // Turn errors into ApiProblem.
-            let result = response.map_err(Error::from);
-
-            if let Err(Error::ApiProblem(problem)) = &result {
-                if problem.is_bad_nonce() {
-                    // retry the request with a new nonce.
-                    debug!("Retrying on bad nonce");
-                    continue;
-                }
-
-                // it seems we sometimes make bad JWTs. Why?!
-                if problem.is_jwt_verification_error() {
-                    debug!("Retrying on: {}", problem);
-                    continue;
-                }
-            }
+    ) -> Result<HttpResponse> {
+        call_signed(self.http(), &self.nonce_pool, url, |nonce| {
+            make_body(url, nonce, &self.acme_key, body)
+        })
+    }
+}
+
+/// POST a JWS made by `make_body` from a nonce.
+///
+/// The ACME API may at any point invalidate all nonces. If we detect such an
+/// error, we loop until the server accepts the nonce.
+pub(crate) fn call_signed<F: Fn(String) -> Result<String>>(
+    http: &dyn HttpClient,
+    nonce_pool: &NoncePool,
+    url: &str,
+    make_body: F,
+) -> Result<HttpResponse> {
+    loop {
+        // Either get a new nonce, or reuse one from a previous request.
+        let nonce = match nonce_pool.pop_nonce() {
+            Some(nonce) => nonce,
+            None => nonce_pool.request_nonce(http)?,
+        };
 
-            return result;
+        // Sign the body.
+        let body = make_body(nonce)?;
+
+        debug!("Call endpoint {}", url);
+
+        // Post it to the URL
+        let response = post(http, url, &body)?;
+
+        // Regardless of the request being a success or not, there might be
+        // a nonce in the response.
+        nonce_pool.extract_nonce(&response);
+
+        // Turn errors into ApiProblem.
+        let result = check_status(response);
+
+        if should_retry(&result) {
+            continue;
         }
+
+        return result;
+    }
+}
+
+impl fmt::Debug for Transport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Transport")
+            .field("acme_key", &self.acme_key)
+            .field("nonce_pool", &self.nonce_pool)
+            .finish()
     }
 }
 
+/// Whether a call should be retried with a new nonce.
+pub(crate) fn should_retry(result: &Result<HttpResponse>) -> bool {
+    if let Err(Error::ApiProblem(problem)) = result {
+        if problem.is_bad_nonce() {
+            // retry the request with a new nonce.
+            debug!("Retrying on bad nonce");
+            return true;
+        }
+
+        // it seems we sometimes make bad JWTs. Why?!
+        if problem.is_jwt_verification_error() {
+            debug!("Retrying on: {}", problem);
+            return true;
+        }
+    }
+    false
+}
+
 /// Shared pool of nonces.
 #[derive(Default, Debug)]
 pub(crate) struct NoncePool {
@@ -117,15 +158,12 @@ impl NoncePool {
         }
     }
 
-    fn extract_nonce(&self, res: &std::result::Result<ureq::Response, Box<ureq::Error>>) {
-        let res = match res {
-            Ok(res) => res,
-            Err(err) => match err.as_ref() {
-                ureq::Error::Status(_, res) => res,
-                ureq::Error::Transport(_) => return,
-            },
-        };
+    #[cfg(feature = "async")]
+    pub fn nonce_url(&self) -> &str {
+        &self.nonce_url
+    }
 
+    pub fn extract_nonce(&self, res: &HttpResponse) {
         if let Some(nonce) = res.header("replay-nonce") {
             trace!("Extract nonce");
             let mut pool = self.pool.lock().unwrap();
@@ -136,55 +174,59 @@ impl NoncePool {
         }
     }
 
-    fn get_nonce(&self) -> Result<String> {
-        if let Some(nonce) = self.pool.lock().unwrap().pop_front() {
+    /// Reuse a nonce from a previous request.
+    pub fn pop_nonce(&self) -> Option<String> {
+        let nonce = self.pool.lock().unwrap().pop_front();
+        if nonce.is_some() {
             trace!("Use previous nonce");
-            return Ok(nonce);
         }
+        nonce
+    }
 
+    fn request_nonce(&self, http: &dyn HttpClient) -> Result<String> {
         debug!("Request new nonce");
-        let res = match head(&self.nonce_url) {
-            Ok(res) => res,
-            Err(err) => match *err {
-                ureq::Error::Status(_, res) => res,
-                ureq::Error::Transport(_) => {
-                    return Err(Error::ApiProblem(ApiProblem {
-                        _type: "httpReqError".into(),
-                        detail: Some("Transport error".into()),
-                        subproblems: None,
-                    }))
-                }
-            },
-        };
+        let res = head(http, &self.nonce_url)?;
 
         Ok(res.extract_header("replay-nonce")?)
     }
 }
 
-fn jws_with_kid<T: Serialize + ?Sized>(
+pub(crate) fn jws_with_kid<T: Serialize + ?Sized>(
     url: &str,
     nonce: String,
     key: &AcmeKey,
     payload: &T,
 ) -> Result<String> {
     let protected = JwsProtected::new_kid(key.key_id(), url, nonce);
-    jws_with(protected, key, payload)
+    jws_with(protected, |data| key.sign(data), payload)
 }
 
-fn jws_with_jwk<T: Serialize + ?Sized>(
+pub(crate) fn jws_with_jwk<T: Serialize + ?Sized>(
     url: &str,
     nonce: String,
     key: &AcmeKey,
     payload: &T,
 ) -> Result<String> {
-    let jwk = Jwk::from(key);
+    let jwk = key.jwk().clone();
     let protected = JwsProtected::new_jwk(jwk, url, nonce);
-    jws_with(protected, key, payload)
+    jws_with(protected, |data| key.sign(data), payload)
+}
+
+/// JWS signed by the private key of a certificate, with its full jwk. RFC 8555 lets
+/// the holder of the key revoke the certificate this way, without an account.
+pub(crate) fn jws_with_cert_key<T: Serialize + ?Sized>(
+    url: &str,
+    nonce: String,
+    key: &KeyPair,
+    payload: &T,
+) -> Result<String> {
+    let protected = JwsProtected::new_jwk(key.jwk()?, url, nonce);
+    jws_with(protected, |data| key.sign_jws(data), payload)
 }
 
-fn jws_with<T: Serialize + ?Sized>(
+fn jws_with<T: Serialize + ?Sized, S: FnOnce(&[u8]) -> Result<Vec<u8>>>(
     protected: JwsProtected,
-    key: &AcmeKey,
+    sign: S,
     payload: &T,
 ) -> Result<String> {
     let protected = {
@@ -204,15 +246,8 @@ fn jws_with<T: Serialize + ?Sized>(
     };
 
     let to_sign = format!("{}.{}", protected, payload);
-    let digest = sha256(to_sign.as_bytes());
-    let sig = EcdsaSig::sign(&digest, key.private_key()).expect("EcdsaSig::sign");
-    let r = sig.r().to_vec();
-    let s = sig.s().to_vec();
-
-    let mut v = Vec::with_capacity(r.len() + s.len());
-    v.extend_from_slice(&r);
-    v.extend_from_slice(&s);
-    let signature = base64url(&v);
+    let sig = sign(to_sign.as_bytes())?;
+    let signature = base64url(&sig);
 
     let jws = Jws::new(protected, payload, signature);
 
@@ -1,14 +1,92 @@
-use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use base64::{
+    engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD},
+    Engine,
+};
 use serde::de::DeserializeOwned;
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+    thread,
+};
 
-use crate::{Result, req::ExtractBody};
+use crate::{http::HttpResponse, req::ExtractBody, Result};
+
+/// Max number of threads used for parallel API calls.
+const MAX_PARALLEL: usize = 8;
 
 pub(crate) fn base64url<T: ?Sized + AsRef<[u8]>>(input: &T) -> String {
     URL_SAFE_NO_PAD.encode(input)
 }
 
-pub(crate) fn read_json<T: DeserializeOwned>(res: ureq::Response) -> Result<T> {
+/// PEM with the given label, i.e. `PUBLIC KEY`.
+pub(crate) fn pem_encode(label: &str, der: &[u8]) -> String {
+    let b64 = STANDARD.encode(der);
+    let mut pem = format!("-----BEGIN {}-----\n", label);
+    for line in b64.as_bytes().chunks(64) {
+        pem.push_str(&String::from_utf8_lossy(line));
+        pem.push('\n');
+    }
+    pem.push_str(&format!("-----END {}-----\n", label));
+    pem
+}
+
+/// The PEM blocks with the given label, in order, each ending with a newline.
+pub(crate) fn pem_blocks(pem: &str, label: &str) -> Vec<String> {
+    let begin = format!("-----BEGIN {}-----", label);
+    let end = format!("-----END {}-----", label);
+    let mut blocks = vec![];
+    let mut rest = pem;
+    while let Some(start) = rest.find(&begin) {
+        let Some(len) = rest[start..].find(&end) else {
+            break;
+        };
+        let stop = start + len + end.len();
+        blocks.push(format!("{}\n", &rest[start..stop]));
+        rest = &rest[stop..];
+    }
+    blocks
+}
+
+pub(crate) fn read_json<T: DeserializeOwned>(res: HttpResponse) -> Result<T> {
     let res_body = res.extract_body();
     debug!("{}", res_body);
     Ok(serde_json::from_str(&res_body)?)
 }
+
+/// Apply `f` to every item using a bounded number of threads. The results are
+/// returned in the same order as the items.
+pub(crate) fn parallel_map<T, R, F>(items: &[T], f: F) -> Vec<R>
+where
+    T: Sync,
+    R: Send,
+    F: Fn(&T) -> R + Sync,
+{
+    if items.len() <= 1 {
+        return items.iter().map(f).collect();
+    }
+
+    let next = AtomicUsize::new(0);
+    let results: Mutex<Vec<Option<R>>> = Mutex::new(items.iter().map(|_| None).collect());
+
+    thread::scope(|s| {
+        for _ in 0..MAX_PARALLEL.min(items.len()) {
+            s.spawn(|| loop {
+                let idx = next.fetch_add(1, Ordering::SeqCst);
+                let Some(item) = items.get(idx) else {
+                    break;
+                };
+                let r = f(item);
+                results.lock().unwrap()[idx] = Some(r);
+            });
+        }
+    });
+
+    results
+        .into_inner()
+        .unwrap()
+        .into_iter()
+        .map(|r| r.expect("parallel_map result"))
+        .collect()
+}